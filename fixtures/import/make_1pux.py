@@ -0,0 +1,79 @@
+#!/usr/bin/env python3
+# writes 1password.1pux, a 1PUX export with two vaults. See README
+import json
+import zipfile
+
+items_private = [
+    {
+        "uuid": "a1",
+        "overview": {
+            "title": "Exchange",
+            "url": "https://mail.example.com",
+            "urls": [{"label": "", "url": "https://mail.example.com"}, {"label": "", "url": "https://owa.example.com"}],
+        },
+        "details": {
+            "loginFields": [
+                {"value": "bob", "name": "username", "fieldType": "T", "designation": "username"},
+                {"value": "pw1", "name": "password", "fieldType": "P", "designation": "password"},
+                {"value": "", "name": "remember", "fieldType": "C", "designation": ""},
+                {"value": "EU", "name": "region", "fieldType": "T", "designation": ""},
+            ],
+            "notesPlain": "first note line\nsecond note line",
+            "sections": [
+                {
+                    "title": "Security",
+                    "fields": [
+                        {"title": "one-time password", "id": "TOTP_1", "value": {"totp": "otpauth://totp/Work:bob?secret=JBSWY3DPEHPK3PXP"}},
+                        {"title": "Recovery Code", "id": "r1", "value": {"concealed": "ABC-123"}},
+                        {"title": "", "id": "pin", "value": {"string": "1234"}},
+                        {"title": "support", "id": "e1", "value": {"email": {"email_address": "help@example.com", "provider": ""}}},
+                        {"title": "empty", "id": "e2", "value": {"string": ""}},
+                        {"title": "backup codes", "id": "b1", "value": {"string": "111\n222"}},
+                    ],
+                }
+            ],
+        },
+    },
+    {
+        "uuid": "a2",
+        "overview": {"title": "Forum", "url": "", "urls": []},
+        "details": {"loginFields": [], "password": "pw2", "notesPlain": "", "sections": []},
+    },
+    {
+        "uuid": "a3",
+        "overview": {"title": "Forum", "url": "", "urls": []},
+        "details": {"loginFields": [], "password": "pw3", "notesPlain": "", "sections": []},
+    },
+]
+
+items_shared = [
+    {
+        "uuid": "b1",
+        "overview": {"title": "Wi-Fi", "url": "", "urls": []},
+        "details": {
+            "loginFields": [],
+            "notesPlain": "",
+            "sections": [
+                {"title": "", "fields": [{"title": "network name", "id": "ssid", "value": {"string": "home"}},
+                                         {"title": "port", "id": "port", "value": {"number": 8080}}]}
+            ],
+            "password": "wifi pw",
+        },
+    }
+]
+
+export = {
+    "accounts": [
+        {
+            "attrs": {"name": "Bob"},
+            "vaults": [
+                {"attrs": {"name": "Private"}, "items": items_private},
+                {"attrs": {"name": "Shared/Home"}, "items": items_shared},
+            ],
+        }
+    ]
+}
+
+with zipfile.ZipFile("1password.1pux", "w") as z:
+    z.writestr("export.attributes", json.dumps({"version": 3, "description": "1Password Unencrypted Export"}))
+    z.writestr("export.data", json.dumps(export, indent=2))
@@ -0,0 +1,159 @@
+# Writes the KDBX sample databases the tests in src/kdbx.rs read, independently of the reader:
+#   python3 make_fixtures.py fixtures/kdbx
+# Needs the python cryptography package. The Python packages have no Argon2d, so ARGON2D_HASHER has to name a
+# program that prints the hex Argon2d (v1.3, 32 bytes) hash for the args: key-hex salt-hex mem-kib iterations lanes
+import base64, gzip, hashlib, hmac, os, struct, sys
+from cryptography.hazmat.primitives.ciphers import Cipher, algorithms, modes
+from cryptography.hazmat.primitives import padding
+
+PW = b"secret pw"
+
+def rotl(v, c): return ((v << c) & 0xffffffff) | (v >> (32 - c))
+
+def salsa20_block(key, nonce, counter):
+    c = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]
+    k = struct.unpack("<8I", key); n = struct.unpack("<2I", nonce)
+    s = [c[0], k[0], k[1], k[2], k[3], c[1], n[0], n[1], counter & 0xffffffff, counter >> 32, c[2], k[4], k[5], k[6], k[7], c[3]]
+    x = s[:]
+    def qr(a, b, cc, d):
+        x[b] ^= rotl((x[a] + x[d]) & 0xffffffff, 7)
+        x[cc] ^= rotl((x[b] + x[a]) & 0xffffffff, 9)
+        x[d] ^= rotl((x[cc] + x[b]) & 0xffffffff, 13)
+        x[a] ^= rotl((x[d] + x[cc]) & 0xffffffff, 18)
+    for _ in range(10):
+        qr(0, 4, 8, 12); qr(5, 9, 13, 1); qr(10, 14, 2, 6); qr(15, 3, 7, 11)
+        qr(0, 1, 2, 3); qr(5, 6, 7, 4); qr(10, 11, 8, 9); qr(15, 12, 13, 14)
+    return struct.pack("<16I", *[(x[i] + s[i]) & 0xffffffff for i in range(16)])
+
+class Salsa:
+    def __init__(self, key, nonce):
+        self.key, self.nonce, self.buf, self.ctr = key, nonce, b"", 0
+    def apply(self, data):
+        while len(self.buf) < len(data):
+            self.buf += salsa20_block(self.key, self.nonce, self.ctr); self.ctr += 1
+        ks, self.buf = self.buf[:len(data)], self.buf[len(data):]
+        return bytes(a ^ b for a, b in zip(data, ks))
+
+class Chacha:
+    def __init__(self, key, nonce):
+        self.enc = Cipher(algorithms.ChaCha20(key, b"\0" * 4 + nonce), None).encryptor()
+    def apply(self, data): return self.enc.update(data)
+
+def aes_kdf(composite, seed, rounds):
+    enc = Cipher(algorithms.AES(seed), modes.ECB()).encryptor()
+    k = composite
+    for _ in range(rounds): k = enc.update(k)
+    return hashlib.sha256(k).digest()
+
+def xml(stream, v3_binaries=""):
+    def prot(s): return base64.b64encode(stream.apply(s.encode())).decode()
+    # protected values in document order: e1 password, e1 history password, e2 password, e3 password (recycle bin)
+    p1 = prot("pässwörd&<1>")
+    ph = prot("old-history-pw")
+    p2 = prot("pw2")
+    p3 = prot("deleted-pw")
+    return f"""<?xml version="1.0" encoding="utf-8" standalone="yes"?>
+<KeePassFile><Meta><DatabaseName>db</DatabaseName><RecycleBinUUID>cmVjeWNsZWJpbnV1aWQxMg==</RecycleBinUUID>{v3_binaries}</Meta>
+<Root><Group><UUID>cm9vdHJvb3Ryb290cm9vdA==</UUID><Name>Root</Name>
+<Entry><UUID>AAAAAAAAAAAAAAAAAAAAAQ==</UUID>
+<String><Key>Title</Key><Value>Mail/Work</Value></String>
+<String><Key>UserName</Key><Value>bob &amp; co</Value></String>
+<String><Key>Password</Key><Value Protected="True">{p1}</Value></String>
+<String><Key>URL</Key><Value>https://mail.example.com</Value></String>
+<String><Key>Notes</Key><Value>line one
+line two</Value></String>
+<String><Key>Recovery Code</Key><Value>ABC-123</Value></String>
+<String><Key>Multi</Key><Value>a
+b</Value></String>
+<Binary><Key>note.txt</Key><Value Ref="0"/></Binary>
+<Binary><Key>blob.bin</Key><Value Ref="1"/></Binary>
+<History><Entry><String><Key>Password</Key><Value Protected="True">{ph}</Value></String></Entry></History>
+</Entry>
+<Group><UUID>c3ViZ3JvdXBzdWJncm91cA==</UUID><Name>Social</Name>
+<Entry><String><Key>Title</Key><Value>Mail/Work</Value></String><String><Key>Password</Key><Value Protected="True">{p2}</Value></String></Entry>
+<Entry><String><Key>Title</Key><Value>Mail/Work</Value></String><String><Key>Password</Key><Value>pw3</Value></String></Entry>
+<Entry><String><Key>Title</Key><Value></Value></String><String><Key>Password</Key><Value>x&#252;</Value></String></Entry>
+</Group>
+<Group><UUID>cmVjeWNsZWJpbnV1aWQxMg==</UUID><Name>Recycle Bin</Name>
+<Entry><String><Key>Title</Key><Value>gone</Value></String><String><Key>Password</Key><Value Protected="True">{p3}</Value></String></Entry>
+</Group>
+</Group></Root></KeePassFile>"""
+
+ATT0 = b"attached text\n"
+ATT1 = bytes([0, 255, 1, 254])
+
+def v3(path, rounds=1000):
+    seed, tseed, iv, skey, start = os.urandom(32), os.urandom(32), os.urandom(16), os.urandom(32), os.urandom(32)
+    def f(i, d): return struct.pack("<BH", i, len(d)) + d
+    hdr = struct.pack("<IIHH", 0x9AA2D903, 0xB54BFB67, 1, 3)
+    hdr += f(2, bytes.fromhex("31c1f2e6bf714350be5805216afc5aff")) + f(3, struct.pack("<I", 1))
+    hdr += f(4, seed) + f(5, tseed) + f(6, struct.pack("<Q", rounds)) + f(7, iv) + f(8, skey) + f(9, start)
+    hdr += f(10, struct.pack("<I", 2)) + f(0, b"\r\n\r\n")
+    bins = '<Binaries><Binary ID="0" Compressed="True">' + base64.b64encode(gzip.compress(ATT0)).decode() + \
+        '</Binary><Binary ID="1">' + base64.b64encode(ATT1).decode() + '</Binary></Binaries>'
+    payload = gzip.compress(xml(Salsa(hashlib.sha256(skey).digest(), bytes.fromhex("E830094B97205D2A")), bins).encode())
+    blocks = b""
+    idx = 0
+    for i in range(0, len(payload), 100):
+        d = payload[i:i + 100]
+        blocks += struct.pack("<I", idx) + hashlib.sha256(d).digest() + struct.pack("<I", len(d)) + d; idx += 1
+    blocks += struct.pack("<I", idx) + b"\0" * 32 + struct.pack("<I", 0)
+    key = hashlib.sha256(seed + aes_kdf(hashlib.sha256(hashlib.sha256(PW).digest()).digest(), tseed, rounds)).digest()
+    p = padding.PKCS7(128).padder(); data = p.update(start + blocks) + p.finalize()
+    enc = Cipher(algorithms.AES(key), modes.CBC(iv)).encryptor()
+    open(path, "wb").write(hdr + enc.update(data) + enc.finalize())
+
+def vd(items):
+    out = struct.pack("<H", 0x100)
+    for t, k, v in items:
+        out += struct.pack("<BI", t, len(k)) + k.encode() + struct.pack("<I", len(v)) + v
+    return out + b"\0"
+
+def v4(path, outer, kdf):
+    seed, iv = os.urandom(32), os.urandom(12 if outer == "chacha" else 16)
+    def f(i, d): return struct.pack("<BI", i, len(d)) + d
+    cipher = "d6038a2b8b6f4cb5a524339a31dbb59a" if outer == "chacha" else "31c1f2e6bf714350be5805216afc5aff"
+    composite = hashlib.sha256(hashlib.sha256(PW).digest()).digest()
+    if kdf == "aes":
+        tseed = os.urandom(32)
+        params = vd([(0x42, "$UUID", bytes.fromhex("c9d9f39a628a4460bf740d08c18a4fea")), (0x05, "R", struct.pack("<Q", 500)), (0x42, "S", tseed)])
+        transformed = aes_kdf(composite, tseed, 500)
+    else:
+        salt = os.urandom(32)
+        uuid = "ef636ddf8c29444b91f7a9a403e30a0c" if kdf == "argon2d" else "9e298b1956db4773b23dfc3ec6f0a1e6"
+        params = vd([(0x42, "$UUID", bytes.fromhex(uuid)), (0x42, "S", salt), (0x04, "P", struct.pack("<I", 2)),
+                     (0x05, "M", struct.pack("<Q", 1024 * 1024)), (0x05, "I", struct.pack("<Q", 2)), (0x04, "V", struct.pack("<I", 0x13))])
+        if kdf == "argon2d":
+            import subprocess
+            out = subprocess.check_output([os.environ["ARGON2D_HASHER"], composite.hex(), salt.hex(), "1024", "2", "2"])
+            transformed = bytes.fromhex(out.decode().strip())
+        else:
+            from cryptography.hazmat.primitives.kdf.argon2 import Argon2id
+            transformed = Argon2id(salt=salt, length=32, iterations=2, lanes=2, memory_cost=1024).derive(composite)
+    hdr = struct.pack("<IIHH", 0x9AA2D903, 0xB54BFB67, 1, 4)
+    hdr += f(2, bytes.fromhex(cipher)) + f(3, struct.pack("<I", 1)) + f(4, seed) + f(7, iv) + f(11, params) + f(0, b"\r\n\r\n")
+    skey = os.urandom(64)
+    inner = f(1, struct.pack("<I", 3)) + f(2, skey) + f(3, b"\x01" + ATT0) + f(3, b"\x00" + ATT1) + f(0, b"")
+    h = hashlib.sha512(skey).digest()
+    payload = gzip.compress(inner + xml(Chacha(h[:32], h[32:44])).encode())
+    key = hashlib.sha256(seed + transformed).digest()
+    if outer == "chacha":
+        enc = Cipher(algorithms.ChaCha20(key, b"\0" * 4 + iv), None).encryptor(); ct = enc.update(payload)
+    else:
+        p = padding.PKCS7(128).padder(); d = p.update(payload) + p.finalize()
+        enc = Cipher(algorithms.AES(key), modes.CBC(iv)).encryptor(); ct = enc.update(d) + enc.finalize()
+    hkey = hashlib.sha512(seed + transformed + b"\x01").digest()
+    def bk(i): return hashlib.sha512(struct.pack("<Q", i) + hkey).digest()
+    out = hdr + hashlib.sha256(hdr).digest() + hmac.new(bk(0xFFFFFFFFFFFFFFFF), hdr, "sha256").digest()
+    idx = 0
+    for i in range(0, len(ct), 200):
+        d = ct[i:i + 200]
+        out += hmac.new(bk(idx), struct.pack("<QI", idx, len(d)) + d, "sha256").digest() + struct.pack("<I", len(d)) + d; idx += 1
+    out += hmac.new(bk(idx), struct.pack("<QI", idx, 0), "sha256").digest() + struct.pack("<I", 0)
+    open(path, "wb").write(out)
+
+d = sys.argv[1]
+v3(d + "/v3_aeskdf_salsa20.kdbx")
+v4(d + "/v4_aeskdf_aes.kdbx", "aes", "aes")
+v4(d + "/v4_argon2id_chacha20.kdbx", "chacha", "argon2id")
+v4(d + "/v4_argon2d_aes.kdbx", "aes", "argon2d")
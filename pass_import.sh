@@ -1,21 +1,7 @@
 #!/usr/bin/env bash
-# export passwords to external file
+# import the entries of a password store into sparkpass, below pass_import/
+# decrypting is done by spass itself, see 'import pass' in the README
 
-shopt -s nullglob globstar
 prefix=${PASSWORD_STORE_DIR:-$HOME/.password-store}
 
-echo -n "Password for sparkpass:" 
-read -s password
-
-for file in "$prefix"/**/*.gpg; do                           
-    file="${file/$prefix//}"
-    path="${file%.*}"
-    content="$(pass "${file%.*}")" 
-    
-    echo "$path"
-
-    export SPARKPASS_KEY="$password"
-
-    spass insert "pass_import/$path" \""$content"\"
-    
-done
\ No newline at end of file
+spass import pass "$prefix"
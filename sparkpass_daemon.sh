@@ -24,7 +24,11 @@ do
         fi
 
         if [[ "$line" == 'show' ]]; then
-            cargo run --bin spass -- -i show  $(cargo run --bin spass -- -i ls -t | rofi -dmenu) | xclip -sel clip
+            cargo run --bin spass -- -i show -c $(cargo run --bin spass -- -i ls -t | rofi -dmenu)
+        fi
+
+        if [[ "$line" == 'otp' ]]; then
+            cargo run --bin spass -- -i otp -c $(cargo run --bin spass -- -i ls -t | rofi -dmenu)
         fi
         echo "Received: "$line
     fi
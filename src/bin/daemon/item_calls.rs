@@ -49,6 +49,18 @@ pub fn handle_item_calls(
                         };
                         return Some(result);
                     }
+                    "Otp" => {
+                        let name = route[2..].join("/");
+                        let reply = match coll.handle_otp(name.as_str()) {
+                            Ok(code) => (*msg).method_return().append1(code),
+                            Err(e) => dbus::tree::MethodErr::failed(&e.to_string()).to_message(msg),
+                        };
+                        return Some(MsgHandlerResult {
+                            done: false,
+                            handled: true,
+                            reply: vec![reply],
+                        });
+                    }
                     "Locked" => {
                         return Some(MsgHandlerResult {
                             done: false,
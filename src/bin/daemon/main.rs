@@ -5,10 +5,10 @@ use dbus::{
 
 extern crate rpassword;
 extern crate sparkpass;
-use sparkpass::transform::{EncryptionParams, DEFAULT_IV};
 use sparkpass::util::{flatten_tree, get_tree_from_path, show_entry, TreeNode};
-
-use openssl::sha::sha256;
+use sparkpass::repo;
+use sparkpass::otp;
+use sparkpass::entry::Entry;
 
 mod collection_calls;
 mod item_calls;
@@ -16,7 +16,9 @@ mod service_calls;
 mod session_calls;
 
 pub struct Collection {
+    //the key derived from the master password, not the password itself
     key: Option<Vec<u8>>,
+    header: repo::RepoHeader,
     prefix: Box<std::path::Path>,
 }
 
@@ -143,18 +145,13 @@ impl Collection {
             Some(v) => v.clone(),
         };
 
-        let keyhash = sha256(key.as_slice());
-        let enc_params = EncryptionParams {
-            key: &keyhash,
-            //this iv is only used for encrypting the path. This must unfortunately be deterministic.
-            iv: DEFAULT_IV,
-        };
+        let enc_params = self.header.encryption_params(&key);
 
         let mut full_path = self.prefix.as_ref().to_str().unwrap().to_owned();
         full_path.push('/');
 
         let tree =
-            match get_tree_from_path(std::path::Path::new(full_path.as_str()), true, &enc_params) {
+            match get_tree_from_path(std::path::Path::new(full_path.as_str()), None, &enc_params) {
                 Ok(t) => t,
                 Err(e) => {
                     return Err(MethodErr::failed(&format!(
@@ -191,12 +188,7 @@ impl Collection {
             Some(v) => v.clone(),
         };
 
-        let keyhash = sha256(key.as_slice());
-        let enc_params = EncryptionParams {
-            key: &keyhash,
-            //this iv is only used for encrypting the path. This must unfortunately be deterministic.
-            iv: DEFAULT_IV,
-        };
+        let enc_params = self.header.encryption_params(&key);
 
         let content = show_entry(
             self.prefix.as_ref(),
@@ -205,9 +197,20 @@ impl Collection {
         );
         match content {
             Ok(c) => Ok(c.to_owned()),
-            Err(e) => {
-                panic!(e.as_str().to_owned());
-            }
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    //only totp, hotp codes would need the counter in the entry to be written back
+    fn handle_otp(&self, name: &str) -> Result<String, Box<std::error::Error>> {
+        let content = self.handle_show(name)?;
+        let params = match otp::find_params(&Entry::parse(content.as_str())) {
+            Ok(p) => p,
+            Err(e) => return Err(Box::new(e)),
+        };
+        match otp::totp_at(&params, otp::now()) {
+            Ok(code) => Ok(code),
+            Err(e) => Err(Box::new(e)),
         }
     }
 }
@@ -224,9 +227,16 @@ fn run_default_coll() -> Result<(), dbus::Error> {
     let repo = std::path::Path::new(home.as_str()).join(".sparkpass/".to_owned());
     let repo = repo.as_path();
 
+    //deriving the key is expensive on purpose, so only do it once
+    let (header, key) = match repo::unlock(repo, pass.as_bytes()) {
+        Ok(hk) => hk,
+        Err(e) => panic!("Couldn't open the repo: {}", e),
+    };
+
     let handler = Handler {
         default_coll: Collection {
-            key: Some(pass.as_bytes().to_vec()),
+            key: Some(key),
+            header,
             prefix: Box::from(repo),
         },
     };
@@ -7,19 +7,26 @@ use argparse::{ArgumentParser, Store, StoreTrue, StoreFalse, Collect};
 extern crate rpassword;
 
 use std::path;
-use openssl::sha::sha256;
 
 // internal imports
 extern crate sparkpass;
 use sparkpass::transform;
 use sparkpass::util;
+use sparkpass::repo;
+use sparkpass::git;
+use sparkpass::clipboard;
+use sparkpass::Error;
 
 use sparkpass::cmd;
 use cmd::cmd_add::cmd_add;
 use cmd::cmd_copy::cmd_copy;
+use cmd::cmd_edit::cmd_edit;
 use cmd::cmd_generate::cmd_generate;
+use cmd::cmd_grep::cmd_grep;
 use cmd::cmd_list::cmd_list_tree;
 use cmd::cmd_move::cmd_move;
+use cmd::cmd_otp::cmd_otp;
+use cmd::cmd_rekey::{cmd_rekey, cmd_migrate};
 use cmd::cmd_remove::cmd_remove;
 use cmd::cmd_search::cmd_search_fuzzy;
 use cmd::cmd_show::cmd_show;
@@ -34,12 +41,70 @@ use util::Options;
 
 fn read_key_from_terminal() -> String {
     println!("Enter key to repo (it is recommended to use SPARKPASS_KEY instead of interactive entering): ");
-    let pass = rpassword::read_password().unwrap();
-    return pass.to_owned();
+    rpassword::read_password().unwrap()
 }
 
-fn main() {
-    let mut options = Options {
+// the exit codes let scripts tell the failures apart. 1 is used for everything without a code of its own
+fn exit_code(e: &Error) -> i32 {
+    match e {
+        Error::Usage(_) | Error::InvalidArgument(_) => 2,
+        Error::NotFound(_) | Error::IsDir(_) => 3,
+        Error::WrongKey => 4,
+        Error::Exists(_) => 5,
+        Error::CorruptEntry(_) | Error::NonUtf8Name(_) | Error::Header(_) | Error::UnsupportedVersion(_) => 6,
+        Error::Io(_) | Error::Import(_) => 1,
+    }
+}
+
+fn exit_with_error(e: Error) -> ! {
+    eprintln!("{}", e);
+    std::process::exit(exit_code(&e));
+}
+
+// the messages of the automatic commits. They must not contain entry names, those are only encrypted in the files
+fn commit_message(command: &str) -> Option<&'static str> {
+    match command {
+        "add" | "insert" => Some("Add entry"),
+        "edit" => Some("Edit entry"),
+        "generate" => Some("Generate entry"),
+        "rm" | "remove" | "delete" => Some("Remove entry"),
+        "mv" | "rename" => Some("Move entry"),
+        "cp" | "copy" => Some("Copy entry"),
+        "rekey" | "passwd" => Some("Rekey repo"),
+        "migrate" => Some("Migrate repo"),
+        "import" => Some("Import entries"),
+        "otp" => Some("Update one-time password"),
+        _ => None,
+    }
+}
+
+fn run_git_command(options: &Options, repopath: &path::Path) -> ! {
+    let status = match git::run_git(repopath, &options.args) {
+        Ok(s) => s,
+        Err(e) => exit_with_error(e),
+    };
+
+    //like pass, 'git init' also commits what is already in the repo
+    if status.success() && options.args.first().is_some_and(|a| a == "init") {
+        if let Err(e) = git::commit_all(repopath, "Add current contents of the repo") {
+            exit_with_error(e);
+        }
+    }
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+fn init_default_repo(options: &Options, repopath: &path::Path) -> Result<(), Error> {
+    let kdf = repo::Kdf::from_args(options.kdf.as_str(), options.kdf_params.as_str())?;
+    let cipher = match transform::EntryCipher::from_name(options.cipher.as_str()) {
+        Some(c) => c,
+        None => return Err(Error::InvalidArgument(format!("Unknown cipher: {}", options.cipher))),
+    };
+    repo::init_repo(repopath, kdf, cipher, options.key.as_bytes())?;
+    Ok(())
+}
+
+fn default_options() -> Options {
+    Options {
         args: std::vec::Vec::new(),
         key: String::new(),
         repo: String::new(),
@@ -50,87 +115,271 @@ fn main() {
         show_tree: true,
         interactive: true,
         multiline: false,
-    };
+        kdf: String::new(),
+        kdf_params: String::new(),
+        cipher: "aes-256-gcm".to_owned(),
+        new_key: String::new(),
+        ignore_case: false,
+        context: 0,
+        field: String::new(),
+        classes: String::new(),
+        custom_chars: String::new(),
+        require: String::new(),
+        no_ambiguous: false,
+        policy: String::new(),
+        save_policy: String::new(),
+        length: 0,
+        words: 0,
+        separator: "-".to_owned(),
+        capitalize: false,
+        word_digits: false,
+        wordlist: String::new(),
+        in_place: false,
+        keep_history: false,
+        clip: false,
+        clip_time: clipboard::clip_time(),
+        qrcode: false,
+        qrcode_out: String::new(),
+        dry_run: false,
+        csv_map: String::new(),
+        path_template: String::new(),
+        delimiter: ",".to_owned(),
+        on_conflict: String::new(),
+    }
+}
 
-    let mut command = String::new();
+// stop_on_first_argument passes everything after the command on as arguments, even if it looks like an option.
+// That is needed for the git passthrough, e.g. 'spass git log --oneline'
+fn parse_args(options: &mut Options, command: &mut String, stop_on_first_argument: bool, quiet: bool) -> Result<(), i32> {
+    let mut ap = ArgumentParser::new();
+    ap.set_description("Save and retrieve passwords.");
+
+    ap.refer(command)
+        .add_argument("command", Store,
+        "Command to run in the repo (see 'man pass' for a list. Some are not implemented)");
+
+    ap.refer(&mut options.args)
+        .add_argument("args", Collect,
+        "arguments depending on the command");
+
+     ap.refer(&mut options.verbose)
+        .add_option(&["--verbose", "-v"], StoreTrue,
+        "More print outs");
+
+    ap.refer(&mut options.force)
+        .add_option(&["--force", "-f"], StoreTrue,
+        "Force overwrites for copy/move/generate/add");
+
+    ap.refer(&mut options.interactive)
+        .add_option(&["--interactiveoff", "-i"], StoreFalse,
+        "Don't ask for key if not found in argument/SPAKRPASS_KEY");
+
+     ap.refer(&mut options.show_tree)
+        .add_option(&["--treeoff", "-t"], StoreFalse,
+        "Show output of search/list as flat list of entries");
+
+    ap.refer(&mut options.recursive)
+        .add_option(&["--recursive", "-r"], StoreTrue,
+        "Remove or copy contents of directories");
+
+    ap.refer(&mut options.repo)
+        .add_option(&["--repo", "-p"], Store,
+        "Path to the repo where your keys are");
+
+    ap.refer(&mut options.line)
+        .add_option(&["--line", "-l"], Store,
+        "Specify which line of multiline file you want to show. If set to -1 all lines will be printed. Default is line 0.");
+
+    ap.refer(&mut options.field)
+        .add_option(&["--field"], Store,
+        "Show only this field of the entry, e.g. password, username, url, notes or any 'key: value' line");
+
+    ap.refer(&mut options.classes)
+        .add_option(&["--classes"], Store,
+        "Character classes for generate, comma separated: lower, upper, digits, symbols. Default: lower,upper,digits");
+
+    ap.refer(&mut options.custom_chars)
+        .add_option(&["--custom-chars"], Store,
+        "Additional characters for generate");
+
+    ap.refer(&mut options.require)
+        .add_option(&["--require"], Store,
+        "Classes that must appear in a generated password, comma separated, or 'all'");
+
+    ap.refer(&mut options.no_ambiguous)
+        .add_option(&["--no-ambiguous"], StoreTrue,
+        "Leave out characters that are easily confused, like 0/O and 1/l/I, in generate");
+
+    ap.refer(&mut options.policy)
+        .add_option(&["--policy"], Store,
+        "Use a generator policy stored in the repo");
+
+    ap.refer(&mut options.save_policy)
+        .add_option(&["--save-policy"], Store,
+        "Store the generator options under this name in the repo");
+
+    ap.refer(&mut options.length)
+        .add_option(&["--length"], Store,
+        "Length of the generated password, the same as the length argument");
+
+    ap.refer(&mut options.words)
+        .add_option(&["--words"], Store,
+        "Generate a passphrase of this many words instead of random characters");
+
+    ap.refer(&mut options.separator)
+        .add_option(&["--separator"], Store,
+        "Separator between the words of a passphrase. Default: -");
+
+    ap.refer(&mut options.capitalize)
+        .add_option(&["--capitalize"], StoreTrue,
+        "Capitalize the words of a passphrase");
+
+    ap.refer(&mut options.word_digits)
+        .add_option(&["--word-digits"], StoreTrue,
+        "Append a random digit to every word of a passphrase");
+
+    ap.refer(&mut options.wordlist)
+        .add_option(&["--wordlist"], Store,
+        "File with one word per line to use for passphrases instead of the bundled list");
+
+    ap.refer(&mut options.in_place)
+        .add_option(&["--in-place"], StoreTrue,
+        "Replace only the password (first line) of an existing entry in generate and keep the rest");
+
+    ap.refer(&mut options.keep_history)
+        .add_option(&["--keep-history"], StoreTrue,
+        "With --in-place, keep the old password in an 'old-password:' line");
+
+    ap.refer(&mut options.clip)
+        .add_option(&["--clip", "-c"], StoreTrue,
+        "Copy the shown line to the clipboard instead of printing it");
+
+    ap.refer(&mut options.clip_time)
+        .add_option(&["--clip-time"], Store,
+        "Seconds until the previous clipboard content is restored. Default: SPARKPASS_CLIP_TIME or 45");
+
+    ap.refer(&mut options.qrcode)
+        .add_option(&["--qrcode"], StoreTrue,
+        "Show the line as QR code in the terminal instead of printing it");
 
-    {  // this block limits scope of borrows by ap.refer() method
-        let mut ap = ArgumentParser::new();
-        ap.set_description("Save and retrieve passwords.");
+    ap.refer(&mut options.qrcode_out)
+        .add_option(&["--qrcode-out"], Store,
+        "Write the line as QR code to this .svg or .png file");
 
-        ap.refer(&mut command)
-            .add_argument("command", Store,
-            "Command to run in the repo (see 'man pass' for a list. Some are not implemented)");
+    ap.refer(&mut options.dry_run)
+        .add_option(&["--dry-run"], StoreTrue,
+        "Only print which records an import would write to which entries");
 
-        ap.refer(&mut options.args)
-            .add_argument("args", Collect,
-            "arguments depending on the command");
+    ap.refer(&mut options.on_conflict)
+        .add_option(&["--on-conflict"], Store,
+        "What an import does with entries that exist: skip, overwrite, rename or abort. Default is skip, overwrite with --force");
 
-         ap.refer(&mut options.verbose)
-            .add_option(&["--verbose", "-v"], StoreTrue,
-            "More print outs");
+    ap.refer(&mut options.csv_map)
+        .add_option(&["--map"], Store,
+        "Columns of a csv import as 'field=Column,...', e.g. 'title=Name,password=Passwd,group=Folder'");
 
-        ap.refer(&mut options.force)
-            .add_option(&["--force", "-f"], StoreTrue,
-            "Force overwrites for copy/move/generate/add");
+    ap.refer(&mut options.path_template)
+        .add_option(&["--path-template"], Store,
+        "Entry path of a csv import with {field} placeholders, e.g. '{group}/{domain}/{title}'");
 
-        ap.refer(&mut options.interactive)
-            .add_option(&["--interactiveoff", "-i"], StoreFalse,
-            "Don't ask for key if not found in argument/SPAKRPASS_KEY");
+    ap.refer(&mut options.delimiter)
+        .add_option(&["--delimiter"], Store,
+        "Column delimiter of a csv import, a single character or 'tab'");
 
-         ap.refer(&mut options.show_tree)
-            .add_option(&["--treeoff", "-t"], StoreFalse,
-            "Show output of search/list as flat list of entries");
+    ap.refer(&mut options.multiline)
+        .add_option(&["--multiline", "-m"], StoreTrue,
+        "Add a new multiline content");
 
-        ap.refer(&mut options.recursive)
-            .add_option(&["--recursive", "-r"], StoreTrue,
-            "Remove contents of directories");
+    ap.refer(&mut options.ignore_case)
+        .add_option(&["--ignore-case"], StoreTrue,
+        "Match case insensitive in grep");
 
-        ap.refer(&mut options.repo)
-            .add_option(&["--repo", "-p"], Store,
-            "Path to the repo where your keys are");
+    ap.refer(&mut options.context)
+        .add_option(&["--context", "-C"], Store,
+        "Number of lines before and after each match printed by grep");
 
-        ap.refer(&mut options.line)
-            .add_option(&["--line", "-l"], Store,
-            "Specify which line of multiline file you want to show. If set to -1 all lines will be printed. Default is line 0.");
+    ap.refer(&mut options.key)
+        .add_option(&["--key", "-k"], Store,
+        "Your master key");
 
-        ap.refer(&mut options.multiline)
-            .add_option(&["--multiline", "-m"], StoreTrue,
-            "Add a new multiline content");
+    ap.refer(&mut options.new_key)
+        .add_option(&["--new-key"], Store,
+        "The new master key for rekey. Asked for interactively if not given");
 
-        ap.refer(&mut options.key)
-            .add_option(&["--key", "-k"], Store,
-            "Your master key");
-        ap.parse_args_or_exit();
+    ap.refer(&mut options.kdf)
+        .add_option(&["--kdf"], Store,
+        "Key derivation function for init, rekey and migrate: argon2id (default on init), scrypt or pbkdf2. Rekey and migrate keep the kdf of the repo if not given");
+
+    ap.refer(&mut options.kdf_params)
+        .add_option(&["--kdf-params"], Store,
+        "Cost parameters for the kdf used by init, rekey and migrate, e.g. 'm=65536,t=3,p=1' for argon2id, 'n=32768,r=8,p=1' for scrypt, 'i=600000' for pbkdf2");
+
+    ap.refer(&mut options.cipher)
+        .add_option(&["--cipher"], Store,
+        "Cipher for the entries of a new repo: aes-256-gcm (default) or chacha20-poly1305");
+
+    ap.stop_on_first_argument(stop_on_first_argument);
+
+    let args = std::env::args().collect();
+    if quiet {
+        ap.parse(args, &mut std::io::sink(), &mut std::io::sink())
+    } else {
+        ap.parse(args, &mut std::io::stdout(), &mut std::io::stderr())
+    }
+}
+
+fn main() {
+    //the background process started by show --clip to restore the clipboard
+    if std::env::var(clipboard::RESTORE_ENV).is_ok() {
+        if let Err(e) = clipboard::restore_from_stdin() {
+            exit_with_error(e);
+        }
+        return;
+    }
+
+    let mut options = default_options();
+    let mut command = String::new();
+
+    //git gets everything after the command, all other commands take the options anywhere on the command line
+    let is_git = parse_args(&mut options, &mut command, true, true).is_ok() && command == "git";
+    if !is_git {
+        options = default_options();
+        command = String::new();
+        if let Err(code) = parse_args(&mut options, &mut command, false, false) {
+            std::process::exit(code);
+        }
     }
 
-    if options.key == "" || options.repo == "" {
+    if options.key.is_empty() || options.repo.is_empty() {
         //search for env variabales if not given by options
         for (var, val) in std::env::vars() {
             match var.as_str() {
-                "SPARKPASS_KEY" => {
-                    if options.key == "" {
-                        options.key = val;
-                    }else{
-                        //ignore
-                    }
-                },
-                "SPARKPASS_REPO" => {
-                    if options.repo == "" {
-                        options.repo = val;
-                    }else{
-                        //ignore
-                    }
-                },
+                //options given on the command line win over the env
+                "SPARKPASS_KEY" if options.key.is_empty() => options.key = val,
+                "SPARKPASS_REPO" if options.repo.is_empty() => options.repo = val,
                 _ => {},
             }
         }
     }
 
-    if options.key == "" {
+    if options.repo.is_empty() {
+        let home = std::env::var("HOME").unwrap();
+        options.repo = path::Path::new(home.as_str()).join(".sparkpass/").to_str().unwrap().to_owned();
+        if options.verbose {
+            println!("Repo not specified (use either SPARKPASS_REPO or --repo/-r), falling back to default {}", options.repo)
+        }
+    }
+
+    let repopath = path::Path::new(options.repo.as_str());
+
+    //git only sees encrypted files, it needs no key
+    if is_git {
+        run_git_command(&options, repopath);
+    }
+
+    if options.key.is_empty() {
         if !options.interactive {
-            println!("No key given and interactive mode deactivated");
-            return;
+            exit_with_error(Error::Usage("No key given and interactive mode deactivated".to_owned()));
         }
         if options.verbose {
             println!("Need a key to retrieve passwords. Instead of interactive entering you can use either the --key/-k options or the SPARKPASS_KEY environment variable");
@@ -138,117 +387,137 @@ fn main() {
         options.key = read_key_from_terminal();
     }
 
-    if options.repo == "" {
-        let home = std::env::var("HOME").unwrap();
-        options.repo = path::Path::new(home.as_str()).join(".sparkpass/".to_owned()).to_str().unwrap().to_owned();
-        if options.verbose {
-            println!("Repo not specified (use either SPARKPASS_REPO or --repo/-r), falling back to default {}", options.repo)
+    //init works on the path given as argument and must not try to unlock the configured repo
+    if command == "init" {
+        if let Err(e) = cmd_init(&options) {
+            exit_with_error(e);
         }
+        return;
     }
 
-    let keyhash = sha256(options.key.as_bytes());
-    let enc_params = transform::EncryptionParams{
-        key: &keyhash,
-        //this iv is only used for encrypting the path. This must unfortunately be deterministic.
-        iv: transform::DEFAULT_IV,
-    };
-
-    let repopath = path::Path::new(options.repo.as_str());
-
+    //repos are initialized with the default kdf on the first command that adds entries. A dry run writes nothing,
+    //not even the repo
     match &(command)[..] {
-        "init" => {
-            cmd_init(&options);
+        "add" | "insert" | "generate" | "import" if !repopath.exists() && !options.dry_run => {
+            if let Err(e) = init_default_repo(&options, repopath) {
+                exit_with_error(e);
+            }
         },
+        _ => {},
+    }
 
+    let (header, key) = match repo::unlock(repopath, options.key.as_bytes()) {
+        Ok(hk) => hk,
+        Err(e) => exit_with_error(e),
+    };
+    let enc_params = header.encryption_params(&key);
+
+    let result = match &(command)[..] {
         "ls" => {
-            cmd_list_tree(&options, repopath, &enc_params);
+            cmd_list_tree(&options, repopath, &enc_params)
         },
         "list" => {
-            cmd_list_tree(&options, repopath, &enc_params);
+            cmd_list_tree(&options, repopath, &enc_params)
         }
 
         "grep" => {
-            println!("This command is currently not supported. Sorry");
-            return;
+            cmd_grep(&options, repopath, &enc_params)
         },
 
         "find" =>  {
-            cmd_search_fuzzy(&options, repopath, &enc_params);
+            cmd_search_fuzzy(&options, repopath, &enc_params)
         },
         "search" => {
-            cmd_search_fuzzy(&options, repopath, &enc_params);
+            cmd_search_fuzzy(&options, repopath, &enc_params)
         },
         
         "show" => {
-            cmd_show(&options, repopath, &enc_params);
+            cmd_show(&options, repopath, &enc_params)
         },
 
         "add" => {
-            cmd_add(&options, repopath, &enc_params);
+            cmd_add(&options, repopath, &enc_params)
         },
         "insert" => {
-            cmd_add(&options, repopath, &enc_params);
+            cmd_add(&options, repopath, &enc_params)
         },
 
         "edit" => {
-            println!("This command is currently not supported. Sorry");
-            return;
+            cmd_edit(&options, repopath, &enc_params)
         },
 
         "generate" => {
-            cmd_generate(&options, repopath, &enc_params);
+            cmd_generate(&options, repopath, &enc_params)
         },
 
         "rm" => {
-            cmd_remove(&options, repopath, &enc_params);
+            cmd_remove(&options, repopath, &enc_params)
         },
         "remove" => {
-            cmd_remove(&options, repopath, &enc_params);
+            cmd_remove(&options, repopath, &enc_params)
         },
         "delete" => {
-            cmd_remove(&options, repopath, &enc_params);
+            cmd_remove(&options, repopath, &enc_params)
         },
 
         "mv" => {
-            cmd_move(&options, repopath, &enc_params);
+            cmd_move(&options, repopath, &enc_params)
         },
         "rename" => {
-            cmd_move(&options, repopath, &enc_params);
+            cmd_move(&options, repopath, &enc_params)
         },
 
         "cp" => {
-            cmd_copy(&options, repopath, &enc_params);
+            cmd_copy(&options, repopath, &enc_params)
         },
         "copy" => {
-            cmd_copy(&options, repopath, &enc_params);
+            cmd_copy(&options, repopath, &enc_params)
         },
 
-        "git" => {
-            println!("#####################################");
-            println!("######         No.         ##########");
-            println!("#####################################");
-            return;
-        }
+        "otp" => {
+            cmd_otp(&options, repopath, &enc_params)
+        },
 
         ////// Commands special to sparkpass
+
+        "rekey" => {
+            cmd_rekey(&options, repopath, &enc_params)
+        },
+        "passwd" => {
+            cmd_rekey(&options, repopath, &enc_params)
+        },
+
+        "migrate" => {
+            cmd_migrate(&options, repopath, &enc_params)
+        },
         
         "import" => {
-            cmd_import(&options, repopath, &enc_params);
+            cmd_import(&options, repopath, &enc_params)
         }
 
         "export" => {
-            cmd_export(&options, repopath, &enc_params);
+            cmd_export(&options, repopath, &enc_params)
         }
 
         _ => {
-            if options.args.len() == 0 {
+            if options.args.is_empty() {
                 //no command was given and cmd collected the path to show/list
-                options.args.push(command);
-                cmd_show(&options,repopath,  &enc_params);
+                options.args.push(command.clone());
+                cmd_show(&options,repopath,  &enc_params)
             }else{
-                println!("Not implemented command: {}", command)
+                Err(Error::Usage(format!("Not implemented command: {}", command)))
             }
         }, 
     };
-}
 
+    if let Err(e) = result {
+        exit_with_error(e);
+    }
+
+    if let Some(message) = commit_message(command.as_str()) {
+        if let Err(e) = git::commit_all(repopath, message) {
+            eprintln!("The change was saved but could not be committed to git");
+            exit_with_error(e);
+        }
+    }
+}
@@ -0,0 +1,323 @@
+// This file deals with copying entries to the clipboard and restoring the previous content after a timeout.
+// The clipboard is accessed through external commands, so any clipboard tool can be plugged in
+extern crate libc;
+
+use crate::error::Error;
+
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+
+// commands for copying (content on stdin) and pasting (content on stdout), run with sh -c
+pub static COPY_ENV: &str = "SPARKPASS_CLIP_COPY";
+pub static PASTE_ENV: &str = "SPARKPASS_CLIP_PASTE";
+pub static TIME_ENV: &str = "SPARKPASS_CLIP_TIME";
+// set for the background process that restores the clipboard, see restore_from_stdin
+pub static RESTORE_ENV: &str = "SPARKPASS_CLIP_RESTORE";
+
+pub const DEFAULT_CLIP_TIME: u64 = 45;
+
+pub struct Backend {
+    pub copy: String,
+    pub paste: String,
+}
+
+fn command_exists(name: &str) -> bool {
+    match Command::new("sh").arg("-c").arg(format!("command -v {}", name)).stdout(Stdio::null()).status() {
+        Ok(s) => s.success(),
+        Err(_) => false,
+    }
+}
+
+impl Backend {
+    // the commands from the environment, otherwise wl-copy on wayland, then xclip, then xsel
+    pub fn detect() -> Result<Backend, Error> {
+        if let (Ok(copy), Ok(paste)) = (std::env::var(COPY_ENV), std::env::var(PASTE_ENV)) {
+            return Ok(Backend { copy, paste });
+        }
+
+        if std::env::var("WAYLAND_DISPLAY").is_ok() && command_exists("wl-copy") {
+            return Ok(Backend { copy: "wl-copy".to_owned(), paste: "wl-paste -n".to_owned() });
+        }
+        if command_exists("xclip") {
+            return Ok(Backend { copy: "xclip -selection clipboard".to_owned(), paste: "xclip -selection clipboard -o".to_owned() });
+        }
+        if command_exists("xsel") {
+            return Ok(Backend { copy: "xsel --clipboard --input".to_owned(), paste: "xsel --clipboard --output".to_owned() });
+        }
+        Err(Error::InvalidArgument(format!(
+            "No clipboard tool found. Install wl-copy, xclip or xsel, or set {} and {}",
+            COPY_ENV, PASTE_ENV
+        )))
+    }
+
+    pub fn copy(&self, content: &str) -> Result<(), Error> {
+        //xclip keeps running to serve the selection, so its output must not keep our pipes open
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(self.copy.as_str())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(content.as_bytes())?;
+        }
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(Error::Io(std::io::Error::other(format!("Clipboard command failed: {}", self.copy))));
+        }
+        Ok(())
+    }
+
+    // None if the clipboard is empty or can't be read
+    pub fn paste(&self) -> Option<String> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(self.paste.as_str())
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output();
+        match output {
+            Ok(o) if o.status.success() => String::from_utf8(o.stdout).ok(),
+            _ => None,
+        }
+    }
+}
+
+pub fn clip_time() -> u64 {
+    match std::env::var(TIME_ENV) {
+        Ok(t) => t.trim().parse().unwrap_or(DEFAULT_CLIP_TIME),
+        Err(_) => DEFAULT_CLIP_TIME,
+    }
+}
+
+// what the restoring process gets on stdin: "<seconds>\n<length of previous>\n<previous><copied>", with "-" as length if
+// the clipboard was empty. The length prefix keeps line breaks in the previous content intact
+#[derive(Debug, PartialEq)]
+struct RestoreMessage {
+    seconds: u64,
+    previous: Option<String>,
+    copied: String,
+}
+
+impl RestoreMessage {
+    fn encode(&self) -> String {
+        let mut message = format!("{}\n", self.seconds);
+        match &self.previous {
+            Some(p) => message.push_str(format!("{}\n{}", p.len(), p).as_str()),
+            None => message.push_str("-\n"),
+        }
+        message.push_str(self.copied.as_str());
+        message
+    }
+
+    fn parse(input: &str) -> Result<RestoreMessage, Error> {
+        let malformed = || Error::InvalidArgument("Malformed clipboard restore input".to_owned());
+
+        let (seconds, rest) = match input.find('\n') {
+            Some(idx) => (&input[..idx], &input[idx + 1..]),
+            None => return Err(malformed()),
+        };
+        let seconds: u64 = seconds.parse().map_err(|_| malformed())?;
+
+        let (previous_len, rest) = match rest.find('\n') {
+            Some(idx) => (&rest[..idx], &rest[idx + 1..]),
+            None => return Err(malformed()),
+        };
+        let (previous, copied) = if previous_len == "-" {
+            (None, rest)
+        } else {
+            let len: usize = previous_len.parse().map_err(|_| malformed())?;
+            if len > rest.len() || !rest.is_char_boundary(len) {
+                return Err(malformed());
+            }
+            (Some(rest[..len].to_owned()), &rest[len..])
+        };
+
+        Ok(RestoreMessage { seconds, previous, copied: copied.to_owned() })
+    }
+}
+
+// puts the previous content back, but only if the clipboard still holds what was copied, so something the user copied
+// in the meantime is not overwritten. Returns whether it restored
+fn restore(backend: &Backend, message: &RestoreMessage) -> Result<bool, Error> {
+    if backend.paste().as_deref() != Some(message.copied.as_str()) {
+        return Ok(false);
+    }
+    backend.copy(message.previous.as_deref().unwrap_or(""))?;
+    Ok(true)
+}
+
+// copies the content and starts a detached copy of this program that restores the previous clipboard after the timeout
+pub fn copy_with_restore(content: &str, seconds: u64) -> Result<(), Error> {
+    let backend = Backend::detect()?;
+    let previous = backend.paste();
+    backend.copy(content)?;
+
+    //the clipboard contents are passed on stdin, never in arguments or the environment where other users could see them
+    let message = RestoreMessage { seconds, previous, copied: content.to_owned() }.encode();
+
+    let exe = std::env::current_exe()?;
+    let mut child = unsafe {
+        Command::new(exe)
+            .env(RESTORE_ENV, "1")
+            .env(COPY_ENV, backend.copy.as_str())
+            .env(PASTE_ENV, backend.paste.as_str())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            //a new session, so closing the terminal does not kill it before it restored the clipboard
+            .pre_exec(|| {
+                libc::setsid();
+                Ok(())
+            })
+            .spawn()?
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(message.as_bytes())?;
+    }
+    Ok(())
+}
+
+// run by the process started in copy_with_restore
+pub fn restore_from_stdin() -> Result<(), Error> {
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input)?;
+    let message = RestoreMessage::parse(input.as_str())?;
+
+    std::thread::sleep(std::time::Duration::from_secs(message.seconds));
+
+    restore(&Backend::detect()?, &message)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // a clipboard that is a file in its own temp dir
+    struct FileClipboard {
+        dir: PathBuf,
+    }
+
+    impl FileClipboard {
+        fn new(name: &str) -> FileClipboard {
+            let dir = std::env::temp_dir().join(format!("sparkpass-clip-{}-{}", std::process::id(), name));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            FileClipboard { dir }
+        }
+
+        fn file(&self) -> PathBuf {
+            self.dir.join("clipboard")
+        }
+
+        // the paste command fails on an empty clipboard, like xclip does
+        fn backend(&self) -> Backend {
+            let file = self.file();
+            Backend {
+                copy: format!("cat > '{}'", file.display()),
+                paste: format!("cat '{}'", file.display()),
+            }
+        }
+
+        fn content(&self) -> String {
+            std::fs::read_to_string(self.file()).unwrap()
+        }
+    }
+
+    impl Drop for FileClipboard {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn copy_and_paste() {
+        let clip = FileClipboard::new("copy");
+        let backend = clip.backend();
+        assert_eq!(backend.paste(), None);
+
+        backend.copy("secret\nwith lines\n").unwrap();
+        assert_eq!(clip.content(), "secret\nwith lines\n");
+        assert_eq!(backend.paste().as_deref(), Some("secret\nwith lines\n"));
+    }
+
+    #[test]
+    fn failing_copy_command_is_an_error() {
+        let backend = Backend { copy: "exit 1".to_owned(), paste: "exit 1".to_owned() };
+        assert!(backend.copy("x").is_err());
+        assert_eq!(backend.paste(), None);
+    }
+
+    #[test]
+    fn backend_from_environment() {
+        let clip = FileClipboard::new("env");
+        let expected = clip.backend();
+        std::env::set_var(COPY_ENV, expected.copy.as_str());
+        std::env::set_var(PASTE_ENV, expected.paste.as_str());
+        let backend = Backend::detect().unwrap();
+        assert_eq!((backend.copy.as_str(), backend.paste.as_str()), (expected.copy.as_str(), expected.paste.as_str()));
+
+        backend.copy("from env").unwrap();
+        assert_eq!(clip.content(), "from env");
+    }
+
+    #[test]
+    fn restore_message_round_trip() {
+        let messages = [
+            RestoreMessage { seconds: 45, previous: Some("old\n2\nlines".to_owned()), copied: "new\n".to_owned() },
+            RestoreMessage { seconds: 0, previous: Some(String::new()), copied: "pässwörd".to_owned() },
+            RestoreMessage { seconds: 10, previous: None, copied: "-\n3\n".to_owned() },
+        ];
+        for m in &messages {
+            assert_eq!(&RestoreMessage::parse(m.encode().as_str()).unwrap(), m);
+        }
+        assert_eq!(messages[0].encode(), "45\n11\nold\n2\nlinesnew\n");
+        assert_eq!(messages[2].encode(), "10\n-\n-\n3\n");
+    }
+
+    #[test]
+    fn malformed_restore_messages() {
+        for input in &["", "45", "x\n-\n", "45\n", "45\n5\nabc", "45\nx\nabc", "1\n1\nü"] {
+            assert!(RestoreMessage::parse(input).is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn restores_the_previous_content() {
+        let clip = FileClipboard::new("restore");
+        let backend = clip.backend();
+        backend.copy("secret").unwrap();
+
+        let message = RestoreMessage { seconds: 0, previous: Some("old".to_owned()), copied: "secret".to_owned() };
+        assert!(restore(&backend, &message).unwrap());
+        assert_eq!(clip.content(), "old");
+    }
+
+    #[test]
+    fn clears_if_the_clipboard_was_empty() {
+        let clip = FileClipboard::new("clear");
+        let backend = clip.backend();
+        backend.copy("secret").unwrap();
+
+        let message = RestoreMessage { seconds: 0, previous: None, copied: "secret".to_owned() };
+        assert!(restore(&backend, &message).unwrap());
+        assert_eq!(clip.content(), "");
+    }
+
+    #[test]
+    fn keeps_content_copied_in_the_meantime() {
+        let clip = FileClipboard::new("changed");
+        let backend = clip.backend();
+        backend.copy("copied by the user").unwrap();
+
+        let message = RestoreMessage { seconds: 0, previous: Some("old".to_owned()), copied: "secret".to_owned() };
+        assert!(!restore(&backend, &message).unwrap());
+        assert_eq!(clip.content(), "copied by the user");
+    }
+}
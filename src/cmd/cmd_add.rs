@@ -1,14 +1,15 @@
 extern crate rpassword;
 
 use crate::transform;
+use crate::error::Error;
 use crate::util::{add_entry, prepare_entry_path, Options};
 
 use std::io::{self, BufRead, BufReader, Read};
 
 use std::path;
 
-fn read_password_from_terminal() -> String {
-    rpassword::read_password().unwrap().to_owned()
+fn read_password_from_terminal() -> Result<String, Error> {
+    Ok(rpassword::read_password()?.to_owned())
 }
 
 fn read_multiline<T: Read>(reader: T) -> String {
@@ -21,20 +22,18 @@ fn read_multiline<T: Read>(reader: T) -> String {
         .collect::<Vec<String>>()
         .join("")
 }
-pub fn cmd_add(opts: &Options, prefix: &path::Path, enc_params: &transform::EncryptionParams) {
-    if opts.args.len() < 1 || opts.args.len() > 2 {
-        println!(
+pub fn cmd_add(opts: &Options, prefix: &path::Path, enc_params: &transform::EncryptionParams) -> Result<(), Error> {
+    if opts.args.is_empty() || opts.args.len() > 2 {
+        return Err(Error::Usage(format!(
             "Incorrect number of arguments. Want: 'path_new, [content]'  Got: {}",
             opts.args.len()
-        );
-        return;
+        )));
     }
 
     let relative_path = prepare_entry_path(opts.args[0].as_str());
     let pwd = if opts.args.len() == 1 {
         if !opts.interactive {
-            println!("No key given and interactive mode deactivated");
-            return;
+            return Err(Error::Usage("No content given and interactive mode deactivated".to_owned()));
         }
 
         if opts.verbose {
@@ -45,11 +44,11 @@ pub fn cmd_add(opts: &Options, prefix: &path::Path, enc_params: &transform::Encr
             if opts.verbose {
                 println!("Multiline is set, requesting multiline content");
             }
-            println!("Enter multiline content for {}: ", opts.args[0].to_string());
+            println!("Enter multiline content for {}: ", opts.args[0]);
             read_multiline(io::stdin())
         } else {
-            println!("Enter content for {}: ", opts.args[0].to_string());
-            read_password_from_terminal()
+            println!("Enter content for {}: ", opts.args[0]);
+            read_password_from_terminal()?
         }
     } else {
         opts.args[1].to_string()
@@ -59,17 +58,11 @@ pub fn cmd_add(opts: &Options, prefix: &path::Path, enc_params: &transform::Encr
         println!("Adding Entry: {}", relative_path);
     }
 
-    match add_entry(
+    add_entry(
         prefix,
         path::Path::new(relative_path),
         pwd.as_str(),
         opts.force,
         enc_params,
-    ) {
-        Ok(_) => {}
-        Err(e) => {
-            println!("An error occurred while adding the entry: {}", e);
-            return;
-        }
-    }
+    )
 }
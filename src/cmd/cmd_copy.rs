@@ -1,52 +1,146 @@
-use crate::util::{Options, prepare_entry_path};
+use crate::util::{Options, add_entry, get_entries_in_dir, prepare_entry_path, show_entry, write_file_atomic};
 use crate::transform;
+use crate::error::Error;
 
 use std::path;
 use std::fs;
 
-pub fn cmd_copy(opts: &Options, prefix: &path::Path , enc_params: &transform::EncryptionParams) {
+pub fn cmd_copy(opts: &Options, prefix: &path::Path , enc_params: &transform::EncryptionParams) -> Result<(), Error> {
     if opts.args.len() != 2 {
-        println!("Incorrect number of arguments. Want: 'old_path, new_path'  Got: {}", opts.args.len());
-        return;
+        return Err(Error::Usage(format!("Incorrect number of arguments. Want: 'old_path, new_path'  Got: {}", opts.args.len())));
     }
 
     let relative_path_old = prepare_entry_path(opts.args[0].as_str());
-    let trans_path_old = transform::transform_path(enc_params, relative_path_old);
+    let trans_path_old = transform::transform_path(enc_params, relative_path_old)?;
     let full_path_old = prefix.join(trans_path_old.join("/"));
 
     let relative_path_new = prepare_entry_path(opts.args[1].as_str());
-    let trans_path_new = transform::transform_path(enc_params, relative_path_new);
+    let trans_path_new = transform::transform_path(enc_params, relative_path_new)?;
     let full_path_new = prefix.join(trans_path_new.join("/"));
 
 
     if opts.verbose {println!("Copying Entry: {}, To: {}", relative_path_old, relative_path_new);}
 
-    if full_path_new.exists() && !opts.force {
-        println!("Target exists already!");
-        return;
+    if !full_path_old.exists() {
+        return Err(Error::NotFound(relative_path_old.to_owned()));
     }
 
     if full_path_old.is_dir() {
-        println!("Copying dirs is not yet supported");
-    }else{
-        match full_path_new.parent() {
-            Some(p) => {
-                match fs::create_dir_all(p){
-                    Ok(_) => {},
-                        Err(e) => {println!("An error occurred while creating the needed directories: {}", e);
-                        return;
-                    },
-                }
-            },
-            None => {},
-        };
-
-        match fs::copy(full_path_old, full_path_new){
-            Ok(_) => {},
-            Err(e) => {
-                println!("An error occurred while copying to new location: {}", e);
-                return;
+        if !opts.recursive {
+            return Err(Error::Usage(format!("{} is a dir, use --recursive/-r to copy it", relative_path_old)));
+        }
+        return copy_dir(opts, relative_path_old, relative_path_new, prefix, enc_params);
+    }
+
+    if full_path_new.exists() && !opts.force {
+        return Err(Error::Exists(relative_path_new.to_owned()));
+    }
+
+    if let Some(p) = full_path_new.parent() {
+        fs::create_dir_all(p)?
+    }
+
+    //the content is not bound to the name, so the encrypted file can be copied as it is
+    let content = fs::read(full_path_old)?;
+    write_file_atomic(full_path_new.as_path(), content.as_slice())
+}
+
+// every entry is decrypted and encrypted again under the new path, so the copies get fresh ivs and names bound to the new parent
+fn copy_dir(opts: &Options, relative_path_old: &str, relative_path_new: &str, prefix: &path::Path, enc_params: &transform::EncryptionParams) -> Result<(), Error> {
+    let entries = get_entries_in_dir(prefix, relative_path_old, enc_params)?;
+
+    let mut skipped = 0;
+    for entry in entries {
+        let old = transform::join_entry_path(relative_path_old, entry.as_str());
+        let new = transform::join_entry_path(relative_path_new, entry.as_str());
+
+        let content = show_entry(prefix, path::Path::new(old.as_str()), enc_params)?;
+        match add_entry(prefix, path::Path::new(new.as_str()), content.as_str(), opts.force, enc_params) {
+            Ok(_) => println!("{} -> {}", old, new),
+            Err(Error::Exists(_)) => {
+                eprintln!("Skipped {}, it already exists (use --force to overwrite)", new);
+                skipped += 1;
             },
-        };
+            Err(e) => return Err(e),
+        }
+    }
+
+    if skipped > 0 {
+        return Err(Error::Exists(format!("{} entries in {} were not copied", skipped, relative_path_new)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{add, enc_params, entries, expect, TempDir};
+
+    fn cp(repo: &TempDir, old: &str, new: &str, recursive: bool, force: bool) -> Result<(), Error> {
+        let opts = Options { args: vec![old.to_owned(), new.to_owned()], recursive, force, ..Default::default() };
+        cmd_copy(&opts, repo.path(), &enc_params())
+    }
+
+    #[test]
+    fn copy_errors() {
+        let repo = TempDir::new("copy-errors");
+        add(repo.path(), "d/a", "pw a");
+        add(repo.path(), "e", "pw e");
+
+        assert!(matches!(cp(&repo, "d", "n", false, false), Err(Error::Usage(_))));
+        assert!(matches!(cp(&repo, "missing", "n", true, false), Err(Error::NotFound(_))));
+        assert!(matches!(cp(&repo, "e", "d/a", false, false), Err(Error::Exists(_))));
+        let opts = Options { args: vec!["e".to_owned()], ..Default::default() };
+        assert!(matches!(cmd_copy(&opts, repo.path(), &enc_params()), Err(Error::Usage(_))));
+        assert_eq!(entries(repo.path()), expect(&[("d/a", "pw a"), ("e", "pw e")]));
+
+        cp(&repo, "e", "d/a", false, true).unwrap();
+        assert_eq!(entries(repo.path()), expect(&[("d/a", "pw e"), ("e", "pw e")]));
+    }
+
+    #[test]
+    fn copy_dir_skips_existing_entries() {
+        let repo = TempDir::new("copy-dir-existing");
+        add(repo.path(), "d/a", "new a");
+        add(repo.path(), "d/b", "new b");
+        add(repo.path(), "n/a", "old a");
+
+        //the other entries are still copied, the skipped ones are reported as an error
+        assert!(matches!(cp(&repo, "d", "n", true, false), Err(Error::Exists(_))));
+        assert_eq!(entries(repo.path()), expect(&[("d/a", "new a"), ("d/b", "new b"), ("n/a", "old a"), ("n/b", "new b")]));
+
+        cp(&repo, "d", "n", true, true).unwrap();
+        assert_eq!(entries(repo.path()), expect(&[("d/a", "new a"), ("d/b", "new b"), ("n/a", "new a"), ("n/b", "new b")]));
+    }
+
+    #[test]
+    fn copy_dir_recursively() {
+        let repo = TempDir::new("copy-dir");
+        add(repo.path(), "d/a", "pw a");
+        add(repo.path(), "d/sub/b", "pw b");
+        add(repo.path(), "d/sub/deeper/c", "pw c");
+        add(repo.path(), "other", "pw other");
+
+        cp(&repo, "d", "n/copy", true, false).unwrap();
+        assert_eq!(entries(repo.path()), expect(&[
+            ("d/a", "pw a"),
+            ("d/sub/b", "pw b"),
+            ("d/sub/deeper/c", "pw c"),
+            ("n/copy/a", "pw a"),
+            ("n/copy/sub/b", "pw b"),
+            ("n/copy/sub/deeper/c", "pw c"),
+            ("other", "pw other"),
+        ]));
+
+        //the copies are encrypted again, not the same files under a new name
+        let file = |entry: &str| fs::read(repo.path().join(transform::transform_path(&enc_params(), entry).unwrap().join("/"))).unwrap();
+        assert_ne!(file("d/sub/b"), file("n/copy/sub/b"));
+
+        //a trailing '/' names the same dir
+        cp(&repo, "d/sub/", "e/", true, false).unwrap();
+        let all = entries(repo.path());
+        assert_eq!(all.len(), 9);
+        assert!(all.contains(&("e/b".to_owned(), "pw b".to_owned())));
+        assert!(all.contains(&("e/deeper/c".to_owned(), "pw c".to_owned())));
     }
-}
\ No newline at end of file
+}
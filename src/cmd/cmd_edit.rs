@@ -0,0 +1,144 @@
+extern crate base64;
+
+use crate::transform;
+use crate::error::Error;
+use crate::util::{add_entry, prepare_entry_path, show_entry, Options};
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path;
+use std::process::Command;
+
+// a dir for the clear content given by the user, e.g. a ramdisk on systems without /dev/shm
+pub static TMPDIR_ENV: &str = "SPARKPASS_TMPDIR";
+
+// the clear content must not end up on a disk, so only the tmpfs in /dev/shm or a dir the user chose is used
+fn secure_temp_dir() -> Result<path::PathBuf, Error> {
+    choose_temp_dir(std::env::var_os(TMPDIR_ENV).map(path::PathBuf::from), path::Path::new("/dev/shm"))
+}
+
+fn choose_temp_dir(chosen: Option<path::PathBuf>, shm: &path::Path) -> Result<path::PathBuf, Error> {
+    if let Some(dir) = chosen {
+        if !dir.is_dir() {
+            return Err(Error::InvalidArgument(format!("{} is not a dir: {}", TMPDIR_ENV, dir.display())));
+        }
+        return Ok(dir);
+    }
+    if shm.is_dir() {
+        return Ok(shm.to_path_buf());
+    }
+    Err(Error::InvalidArgument(format!(
+        "There is no tmpfs at {} to hold the clear entry while editing. Set {} to a dir that is not written to disk",
+        shm.display(),
+        TMPDIR_ENV
+    )))
+}
+
+// overwrites the clear content before unlinking the file. Editors that save by renaming leave nothing behind either
+fn wipe_and_remove(p: &path::Path) {
+    if let Ok(meta) = fs::metadata(p) {
+        if let Ok(mut f) = fs::OpenOptions::new().write(true).open(p) {
+            let _ = f.write_all(&vec![0u8; meta.len() as usize]);
+            let _ = f.sync_all();
+        }
+    }
+    let _ = fs::remove_file(p);
+}
+
+fn run_editor(file: &path::Path) -> Result<(), Error> {
+    let editor = match std::env::var("VISUAL").or_else(|_| std::env::var("EDITOR")) {
+        Ok(e) => e,
+        Err(_) => "vi".to_owned(),
+    };
+
+    //run through the shell so EDITOR may contain arguments like "code --wait"
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(file)
+        .status()?;
+
+    if !status.success() {
+        return Err(Error::Usage(format!("Editor exited with {}, the entry was not changed", status)));
+    }
+    Ok(())
+}
+
+pub fn cmd_edit(opts: &Options, prefix: &path::Path, enc_params: &transform::EncryptionParams) -> Result<(), Error> {
+    if opts.args.len() != 1 {
+        return Err(Error::Usage(format!("Incorrect number of arguments. Want: 'path'  Got: {}", opts.args.len())));
+    }
+
+    let relative_path = prepare_entry_path(opts.args[0].as_str());
+
+    //like pass, editing an entry that does not exist yet creates it
+    let old_content = match show_entry(prefix, path::Path::new(relative_path), enc_params) {
+        Ok(c) => Some(c),
+        Err(Error::NotFound(_)) => None,
+        Err(e) => return Err(e),
+    };
+
+    let tmp_name = format!("sparkpass-{}.txt", base64::encode_config(&transform::get_random_bytes(8)?, base64::URL_SAFE_NO_PAD));
+    let tmp_path = secure_temp_dir()?.join(tmp_name);
+
+    {
+        let mut f = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&tmp_path)?;
+        if let Some(c) = &old_content {
+            if let Err(e) = f.write_all(c.as_bytes()) {
+                drop(f);
+                wipe_and_remove(&tmp_path);
+                return Err(Error::Io(e));
+            }
+        }
+    }
+
+    if opts.verbose {println!("Editing Entry: {} in {}", relative_path, tmp_path.display());}
+
+    let new_content = run_editor(&tmp_path).and_then(|_| Ok(fs::read(&tmp_path)?));
+    wipe_and_remove(&tmp_path);
+
+    let new_content = match String::from_utf8(new_content?) {
+        Ok(c) => c,
+        Err(_) => return Err(Error::InvalidArgument("The edited content is not UTF-8, the entry was not changed".to_owned())),
+    };
+
+    if old_content.as_ref() == Some(&new_content) {
+        if opts.verbose {println!("Content unchanged, not rewriting {}", relative_path);}
+        return Ok(());
+    }
+    if old_content.is_none() && new_content.is_empty() {
+        if opts.verbose {println!("No content entered, not creating {}", relative_path);}
+        return Ok(());
+    }
+
+    add_entry(prefix, path::Path::new(relative_path), new_content.as_str(), true, enc_params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn temp_dir_is_never_the_disk() {
+        let dir = TempDir::new("edit-tmp");
+        let shm = dir.path().join("shm");
+        let chosen = dir.path().join("chosen");
+
+        //without /dev/shm and without a chosen dir editing fails instead of using the temp dir
+        assert!(choose_temp_dir(None, &shm).is_err());
+        assert!(choose_temp_dir(Some(chosen.clone()), &shm).is_err());
+
+        fs::create_dir(&shm).unwrap();
+        assert_eq!(choose_temp_dir(None, &shm).unwrap(), shm);
+
+        fs::create_dir(&chosen).unwrap();
+        assert_eq!(choose_temp_dir(Some(chosen.clone()), &shm).unwrap(), chosen);
+    }
+}
@@ -1,35 +1,134 @@
-use crate::util::{Options, prepare_entry_path, add_entry};
+use crate::util::{Options, prepare_entry_path, add_entry, show_entry};
+use crate::entry::{first_line, replace_first_line, append_line, HISTORY_FIELD};
 use crate::transform;
 use crate::generate;
+use crate::error::Error;
 
 use std::path;
 
-pub fn cmd_generate(opts: &Options, prefix: &path::Path , enc_params: &transform::EncryptionParams) {
-    if opts.args.len() > 2 || opts.args.len() < 1 {
-        println!("Incorrect number of arguments. Want: 'path, [length]'  Got: {}", opts.args.len());
-        return;
+// the stored policy (or the default one) with the generator flags applied on top
+fn policy_from_options(opts: &Options, prefix: &path::Path) -> Result<generate::Policy, Error> {
+    let mut policy = if !opts.policy.is_empty() {
+        generate::load_policy(prefix, opts.policy.as_str())?
+    } else {
+        generate::Policy::default()
+    };
+
+    if !opts.classes.is_empty() {
+        policy.classes = generate::split_list(opts.classes.as_str());
+    }
+    if !opts.custom_chars.is_empty() {
+        policy.custom = opts.custom_chars.clone();
+    }
+    if opts.require == "all" {
+        policy.require = policy.classes.clone();
+        if !policy.custom.is_empty() {
+            policy.require.push("custom".to_owned());
+        }
+    } else if !opts.require.is_empty() {
+        policy.require = generate::split_list(opts.require.as_str());
+    }
+    if opts.no_ambiguous {
+        policy.no_ambiguous = true;
+    }
+    Ok(policy)
+}
+
+fn generate_passphrase(opts: &Options) -> Result<String, Error> {
+    if opts.args.len() == 2 || opts.length > 0 {
+        return Err(Error::Usage("Give either a length or --words, not both".to_owned()));
     }
 
-    let relative_path = prepare_entry_path(opts.args[0].as_str());
-    
-    let passwd = if opts.args.len() == 2 {
-        let length = match opts.args[1].trim().parse() {
+    let wordlist = if !opts.wordlist.is_empty() {
+        generate::load_wordlist(Some(path::Path::new(opts.wordlist.as_str())))?
+    } else {
+        generate::load_wordlist(None)?
+    };
+
+    let passphrase_opts = generate::PassphraseOptions {
+        words: opts.words,
+        separator: opts.separator.clone(),
+        capitalize: opts.capitalize,
+        digits: opts.word_digits,
+    };
+    let (passphrase, entropy) = generate::generate_passphrase(&passphrase_opts, &wordlist)?;
+    //on stderr, so only the passphrase is on stdout when it isn't stored
+    eprintln!("Estimated entropy: {:.1} bits ({} words from a list of {})", entropy, opts.words, wordlist.len());
+    Ok(passphrase)
+}
+
+// without a path the password is only printed, with --save-policy and without a path only the policy is stored
+pub fn cmd_generate(opts: &Options, prefix: &path::Path , enc_params: &transform::EncryptionParams) -> Result<(), Error> {
+    if opts.args.len() > 2 {
+        return Err(Error::Usage(format!("Incorrect number of arguments. Want: '[path], [length]'  Got: {}", opts.args.len())));
+    }
+
+    if opts.words > 0 {
+        if !opts.save_policy.is_empty() {
+            return Err(Error::Usage("--save-policy stores policies for random characters, it can't be used with --words".to_owned()));
+        }
+        let passphrase = generate_passphrase(opts)?;
+        if opts.args.is_empty() {
+            println!("{}", passphrase);
+            return Ok(());
+        }
+        return store_password(opts, prefix, passphrase.as_str(), enc_params);
+    }
+
+    //with --save-policy a lone number is the length of the policy, not the name of an entry
+    let policy_only = !opts.save_policy.is_empty() && opts.args.len() == 1 && opts.args[0].trim().parse::<usize>().is_ok();
+    let length_arg = if policy_only {
+        Some(opts.args[0].as_str())
+    } else {
+        opts.args.get(1).map(|a| a.as_str())
+    };
+
+    let mut policy = policy_from_options(opts, prefix)?;
+    if let Some(arg) = length_arg {
+        if opts.length > 0 {
+            return Err(Error::Usage("Give either a length argument or --length, not both".to_owned()));
+        }
+        policy.length = match arg.trim().parse() {
             Ok(i) => i,
             Err(e) => {
-                println!("Error while converting argument to number: {}", e);
-                return;
+                return Err(Error::Usage(format!("Error while converting argument to number: {}", e)));
             },
         };
-        generate::generate_passwd(length)
-    }else{
-        generate::generate_passwd(64)
-    };
+    } else if opts.length > 0 {
+        policy.length = opts.length;
+    }
+    policy.validate()?;
+
+    if !opts.save_policy.is_empty() {
+        generate::save_policy(prefix, opts.save_policy.as_str(), &policy)?;
+        if opts.verbose {println!("Saved policy {}: {}", opts.save_policy, policy.to_spec());}
+        if opts.args.is_empty() || policy_only {
+            return Ok(());
+        }
+    }
 
-    match add_entry(prefix, path::Path::new(relative_path), passwd.as_str(), opts.force, enc_params) {
-        Ok(_) => {},
-        Err(e) => {
-            println!("An error occurred while adding the entry: {}", e);
-            return;
-        },
+    let passwd = generate::generate_with_policy(&policy)?;
+    if opts.args.is_empty() {
+        println!("{}", passwd);
+        return Ok(());
     }
-}
\ No newline at end of file
+    store_password(opts, prefix, passwd.as_str(), enc_params)
+}
+
+// with --in-place only the first line of the existing entry is replaced, the rest of the entry stays as it is
+fn store_password(opts: &Options, prefix: &path::Path, passwd: &str, enc_params: &transform::EncryptionParams) -> Result<(), Error> {
+    let relative_path = path::Path::new(prepare_entry_path(opts.args[0].as_str()));
+
+    if !opts.in_place {
+        return add_entry(prefix, relative_path, passwd, opts.force, enc_params);
+    }
+
+    let content = show_entry(prefix, relative_path, enc_params)?;
+    let old = first_line(content.as_str()).to_owned();
+    let mut new_content = replace_first_line(content.as_str(), passwd);
+    if opts.keep_history && !old.is_empty() {
+        new_content = append_line(new_content.as_str(), format!("{}: {}", HISTORY_FIELD, old).as_str());
+    }
+
+    add_entry(prefix, relative_path, new_content.as_str(), true, enc_params)
+}
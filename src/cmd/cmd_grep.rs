@@ -0,0 +1,84 @@
+extern crate regex;
+
+use crate::util::{Options, TreeNode, flatten_tree, get_tree_from_path, prepare_entry_path, show_entry};
+use crate::transform;
+use crate::error::Error;
+
+use regex::RegexBuilder;
+
+use std::path;
+
+// prints the matches grep style: "entry:line" for matching lines, "entry-line" for context and "--" between separate groups
+pub fn cmd_grep(opts: &Options, prefix: &path::Path, enc_params: &transform::EncryptionParams) -> Result<(), Error> {
+    if opts.args.len() != 1 {
+        return Err(Error::Usage(format!("Incorrect number of arguments. Want: 'regex'  Got: {}", opts.args.len())));
+    }
+
+    let re = match RegexBuilder::new(opts.args[0].as_str()).case_insensitive(opts.ignore_case).build() {
+        Ok(re) => re,
+        Err(e) => return Err(Error::InvalidArgument(format!("Invalid regex: {}", e))),
+    };
+
+    let tree = get_tree_from_path(prefix, None, enc_params)?;
+    let renamed_tree = match tree {
+        TreeNode::Node(_, children) => TreeNode::Node("".to_owned(), children),
+        TreeNode::Leaf(_) => TreeNode::Leaf("".to_owned()),
+    };
+
+    let mut found = false;
+    let mut printed_group = false;
+
+    for entry in flatten_tree(&renamed_tree, "".to_owned()) {
+        let relative_path = prepare_entry_path(entry.as_str());
+        if opts.verbose {println!("Searching in Entry: {}", relative_path);}
+
+        let content = show_entry(prefix, path::Path::new(relative_path), enc_params)?;
+        let lines: Vec<&str> = content.lines().collect();
+
+        //the lines to print, with the flag if they matched or are only context
+        let mut selected: Vec<Option<bool>> = vec![None; lines.len()];
+        for (i, line) in lines.iter().enumerate() {
+            if !re.is_match(line) {
+                continue;
+            }
+            let start = i.saturating_sub(opts.context);
+            let end = std::cmp::min(i + opts.context, lines.len() - 1);
+            for sel in selected[start..=end].iter_mut() {
+                if sel.is_none() {
+                    *sel = Some(false);
+                }
+            }
+            selected[i] = Some(true);
+        }
+
+        let mut last_printed: Option<usize> = None;
+        for (i, sel) in selected.iter().enumerate() {
+            let is_match = match sel {
+                Some(m) => *m,
+                None => continue,
+            };
+
+            let new_group = match last_printed {
+                Some(l) => l + 1 != i,
+                None => true,
+            };
+            if opts.context > 0 && new_group && printed_group {
+                println!("--");
+            }
+
+            if is_match {
+                println!("{}:{}", relative_path, lines[i]);
+            } else {
+                println!("{}-{}", relative_path, lines[i]);
+            }
+            last_printed = Some(i);
+            printed_group = true;
+            found = true;
+        }
+    }
+
+    if !found {
+        return Err(Error::NotFound(format!("no entry matches {}", opts.args[0])));
+    }
+    Ok(())
+}
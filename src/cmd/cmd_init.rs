@@ -1,19 +1,27 @@
 use crate::util::Options;
+use crate::repo;
+use crate::transform;
+use crate::error::Error;
 
-pub fn cmd_init(opts: &Options) {
+pub fn cmd_init(opts: &Options) -> Result<(), Error> {
     if opts.args.len() != 1 {
-        println!("Too many arguments. Want: '[path_to_dir]'  Got: {}", opts.args.len());
-        return;
+        return Err(Error::Usage(format!("Too many arguments. Want: '[path_to_dir]'  Got: {}", opts.args.len())));
     }
 
-    let path = std::path::Path::new(opts.args[0].as_str()); 
-    match std::fs::create_dir_all(path) {
-        Ok(()) => {
-            println!("Created: {}",  path.to_str().unwrap());
-            println!("This command is not necessary with sparkpass. The repo would have been initialzied with your first add/insert command");
+    let kdf = repo::Kdf::from_args(opts.kdf.as_str(), opts.kdf_params.as_str())?;
+
+    let cipher = match transform::EntryCipher::from_name(opts.cipher.as_str()) {
+        Some(c) => c,
+        None => {
+            return Err(Error::InvalidArgument(format!("Unknown cipher: {}. Known are: aes-256-gcm, chacha20-poly1305", opts.cipher)));
         },
-        Err(e) => {
-            println!("There was an error while creating the repo: {}, {}", path.to_str().unwrap(), e);
-        }
+    };
+
+    let path = std::path::Path::new(opts.args[0].as_str()); 
+    let header = repo::init_repo(path, kdf, cipher, opts.key.as_bytes())?;
+    println!("Created: {}",  path.to_str().unwrap());
+    if opts.verbose {
+        println!("Using kdf: {}, cipher: {}", header.kdf.name(), header.cipher.name());
     }
-}
\ No newline at end of file
+    Ok(())
+}
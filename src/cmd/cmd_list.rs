@@ -1,24 +1,26 @@
-use crate::util::{TreeNode, Options, prepare_entry_path,  get_tree_from_path, print_tree, flatten_tree};
+use crate::util::{TreeNode, Options, prepare_entry_path, parent_entry_path, get_tree_from_path, print_tree, flatten_tree};
 use crate::transform;
+use crate::error::Error;
 
 use std::path;
 
 
 
 
-pub fn cmd_list_tree(opts: &Options, prefix: &path::Path , enc_params: &transform::EncryptionParams) {
+pub fn cmd_list_tree(opts: &Options, prefix: &path::Path , enc_params: &transform::EncryptionParams) -> Result<(), Error> {
     if opts.args.len() > 1 {
-        println!("Too many arguments. Want: '[path_to_dir]'  Got: {}", opts.args.len());
-        return;
+        return Err(Error::Usage(format!("Too many arguments. Want: '[path_to_dir]'  Got: {}", opts.args.len())));
     }
 
     let mut is_root = false;
+    let mut parent = "";
 
     //check if any path needs to be appended to the prefix
-    let pp = if opts.args.len() > 0 && opts.args[0].len() > 0 && opts.args[0] != "/" {
+    let pp = if !opts.args.is_empty() && !opts.args[0].is_empty() && opts.args[0] != "/" {
         let relative_path = prepare_entry_path(opts.args[0].as_str());
+        parent = parent_entry_path(relative_path);
 
-        let trans_path_tmp = transform::transform_path(enc_params, relative_path);
+        let trans_path_tmp = transform::transform_path(enc_params, relative_path)?;
         prefix.join(trans_path_tmp.join("/"))
     } else{
         is_root = true;
@@ -29,13 +31,11 @@ pub fn cmd_list_tree(opts: &Options, prefix: &path::Path , enc_params: &transfor
     
     if opts.verbose {println!("Listing in: {}", full_path.to_str().unwrap());}
 
-    let tree = match get_tree_from_path(full_path, is_root, enc_params){
-        Ok(t) => t,
-        Err(err) => {
-            println!("An error occurred while listing entries: {}", err);
-            return;
-        },
-    };
+    if !full_path.exists() {
+        return Err(Error::NotFound(opts.args.first().map_or("/", |a| a.as_str()).to_owned()));
+    }
+
+    let tree = get_tree_from_path(full_path, if is_root { None } else { Some(parent) }, enc_params)?;
 
     let renamed_tree = if is_root {
         match tree {
@@ -56,6 +56,7 @@ pub fn cmd_list_tree(opts: &Options, prefix: &path::Path , enc_params: &transfor
             println!("{}", prepare_entry_path(&e));
         }
     }
+    Ok(())
 }
 
 //fn cmd_list(opts: &Options, prefix: &path::Path , enc_params: &transform::EncryptionParams) {
@@ -1,33 +1,228 @@
-use crate::util::{Options, prepare_entry_path};
+use crate::util::{Options, prepare_entry_path, get_entries_in_dir, sync_dir};
 use crate::transform;
+use crate::error::Error;
 
 use std::path;
 use std::fs;
 
-pub fn cmd_move(opts: &Options, prefix: &path::Path , enc_params: &transform::EncryptionParams) {
+pub fn cmd_move(opts: &Options, prefix: &path::Path , enc_params: &transform::EncryptionParams) -> Result<(), Error> {
     if opts.args.len() != 2 {
-        println!("Incorrect number of arguments. Want: 'old_path, new_path'  Got: {}", opts.args.len());
-        return;
+        return Err(Error::Usage(format!("Incorrect number of arguments. Want: 'old_path, new_path'  Got: {}", opts.args.len())));
     }
 
     let relative_path_old = prepare_entry_path(opts.args[0].as_str());
-    let trans_path_old = transform::transform_path(enc_params, relative_path_old);
+    let trans_path_old = transform::transform_path(enc_params, relative_path_old)?;
     let full_path_old = prefix.join(trans_path_old.join("/"));
 
-    let relative_path_new = prepare_entry_path(opts.args[1].as_str());
-    let trans_path_new = transform::transform_path(enc_params, relative_path_new);
+    if relative_path_old.is_empty() {
+        return Err(Error::Usage("Can't move the repo root".to_owned()));
+    }
+
+    //a trailing '/' moves the entry into that dir, keeping its name
+    let relative_path_new = if opts.args[1].ends_with("/") {
+        let name = match relative_path_old.rfind("/") {
+            Some(idx) => &relative_path_old[idx + 1..],
+            None => relative_path_old,
+        };
+        transform::join_entry_path(prepare_entry_path(opts.args[1].as_str()), name)
+    } else {
+        prepare_entry_path(opts.args[1].as_str()).to_owned()
+    };
+    let relative_path_new = relative_path_new.as_str();
+    let trans_path_new = transform::transform_path(enc_params, relative_path_new)?;
     let full_path_new = prefix.join(trans_path_new.join("/"));
 
 
     if opts.verbose {println!("Moving Entry: {}, To: {}", relative_path_old, relative_path_new);}
 
-    if full_path_new.exists() && !opts.force {
-        println!("Target exists already!");
-        return;
+    if !full_path_old.exists() {
+        return Err(Error::NotFound(relative_path_old.to_owned()));
+    }
+
+    if relative_path_new == relative_path_old {
+        return Ok(());
+    }
+
+    if full_path_old.is_dir() {
+        if relative_path_new.starts_with(format!("{}/", relative_path_old).as_str()) {
+            return Err(Error::InvalidArgument(format!("Can't move {} into itself", relative_path_old)));
+        }
+        if full_path_new.is_file() {
+            return Err(Error::Exists(format!("{} is an entry, can't move the dir {} onto it", relative_path_new, relative_path_old)));
+        }
+
+        //with siv names the names of the entries depend on the path of the dir, so they have to be moved one by one.
+        //the same goes for merging into an existing dir
+        if full_path_new.exists() || enc_params.names == transform::NameScheme::Siv {
+            return move_dir(opts, relative_path_old, relative_path_new, prefix, enc_params);
+        }
+    } else {
+        if full_path_new.is_dir() {
+            return Err(Error::IsDir(format!("{} (add a trailing '/' to move the entry into it)", relative_path_new)));
+        }
+        if full_path_new.exists() && !opts.force {
+            return Err(Error::Exists(relative_path_new.to_owned()));
+        }
+    }
+
+    fs::create_dir_all(full_path_new.parent().unwrap())?;
+    rename_synced(full_path_old.as_path(), full_path_new.as_path())
+}
+
+// rename is atomic, the fsync of both dirs makes sure it survives a crash
+fn rename_synced(old: &path::Path, new: &path::Path) -> Result<(), Error> {
+    fs::rename(old, new)?;
+    sync_dir(new.parent().unwrap())?;
+    sync_dir(old.parent().unwrap())
+}
+
+// moves the entries one by one and merges them into the target dir if it exists.
+// Entries that collide with existing ones are left in place and reported, unless --force is given
+fn move_dir(opts: &Options, relative_path_old: &str, relative_path_new: &str, prefix: &path::Path, enc_params: &transform::EncryptionParams) -> Result<(), Error> {
+    let entries = get_entries_in_dir(prefix, relative_path_old, enc_params)?;
+
+    let mut conflicts = 0;
+    for entry in entries {
+        let old = transform::join_entry_path(relative_path_old, entry.as_str());
+        let new = transform::join_entry_path(relative_path_new, entry.as_str());
+        let full_path_old = prefix.join(transform::transform_path(enc_params, old.as_str())?.join("/"));
+        let full_path_new = prefix.join(transform::transform_path(enc_params, new.as_str())?.join("/"));
+
+        if full_path_new.is_dir() {
+            eprintln!("Conflict: {} is a dir, {} was not moved", new, old);
+            conflicts += 1;
+            continue;
+        }
+        if full_path_new.exists() && !opts.force {
+            eprintln!("Conflict: {} already exists, {} was not moved (use --force to overwrite)", new, old);
+            conflicts += 1;
+            continue;
+        }
+
+        if let Some(p) = full_path_new.parent() {
+            if p.is_file() {
+                eprintln!("Conflict: a parent of {} is an entry, {} was not moved", new, old);
+                conflicts += 1;
+                continue;
+            }
+            fs::create_dir_all(p)?
+        }
+        rename_synced(full_path_old.as_path(), full_path_new.as_path())?;
+    }
+
+    //only the dirs are left, and the entries that could not be moved
+    let full_path_old = prefix.join(transform::transform_path(enc_params, relative_path_old)?.join("/"));
+    remove_empty_dirs(full_path_old.as_path())?;
+
+    if conflicts > 0 {
+        return Err(Error::Exists(format!("{} entries of {} were not moved", conflicts, relative_path_old)));
+    }
+    Ok(())
+}
+
+fn remove_empty_dirs(dir: &path::Path) -> Result<(), Error> {
+    for entry in fs::read_dir(dir)? {
+        let p = entry?.path();
+        if p.is_dir() {
+            remove_empty_dirs(p.as_path())?;
+        }
     }
+    if fs::read_dir(dir)?.next().is_none() {
+        fs::remove_dir(dir)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{add, enc_params, entries, expect, TempDir};
+
+    fn mv(repo: &TempDir, old: &str, new: &str, force: bool) -> Result<(), Error> {
+        let opts = Options { args: vec![old.to_owned(), new.to_owned()], force, ..Default::default() };
+        cmd_move(&opts, repo.path(), &enc_params())
+    }
+
+    fn dir_exists(repo: &TempDir, dir: &str) -> bool {
+        repo.path().join(transform::transform_path(&enc_params(), dir).unwrap().join("/")).is_dir()
+    }
+
+    #[test]
+    fn move_entry() {
+        let repo = TempDir::new("move-entry");
+        add(repo.path(), "a", "pw a");
+        add(repo.path(), "d/b", "pw b");
+
+        mv(&repo, "a", "d/", false).unwrap();
+        mv(&repo, "d/b", "e/c", false).unwrap();
+        assert_eq!(entries(repo.path()), expect(&[("d/a", "pw a"), ("e/c", "pw b")]));
+
+        assert!(matches!(mv(&repo, "d/a", "e/c", false), Err(Error::Exists(_))));
+        assert!(matches!(mv(&repo, "d/a", "e", false), Err(Error::IsDir(_))));
+        assert!(matches!(mv(&repo, "missing", "x", false), Err(Error::NotFound(_))));
+        assert!(matches!(mv(&repo, "/", "x", false), Err(Error::Usage(_))));
+        mv(&repo, "d/a", "e/c", true).unwrap();
+        assert_eq!(entries(repo.path()), expect(&[("e/c", "pw a")]));
+    }
+
+    #[test]
+    fn move_dir_onto_existing_dir_merges() {
+        let repo = TempDir::new("move-merge");
+        add(repo.path(), "old/a", "new a");
+        add(repo.path(), "old/sub/b", "new b");
+        add(repo.path(), "old/c", "new c");
+        add(repo.path(), "target/c", "old c");
+        add(repo.path(), "target/x", "old x");
+
+        //the entry that exists in the target stays where it is
+        assert!(matches!(mv(&repo, "old", "target", false), Err(Error::Exists(_))));
+        assert_eq!(entries(repo.path()), expect(&[
+            ("old/c", "new c"),
+            ("target/a", "new a"),
+            ("target/c", "old c"),
+            ("target/sub/b", "new b"),
+            ("target/x", "old x"),
+        ]));
+        assert!(!dir_exists(&repo, "old/sub"));
+
+        mv(&repo, "old", "target", true).unwrap();
+        assert_eq!(entries(repo.path()), expect(&[
+            ("target/a", "new a"),
+            ("target/c", "new c"),
+            ("target/sub/b", "new b"),
+            ("target/x", "old x"),
+        ]));
+        assert!(!dir_exists(&repo, "old"));
+    }
+
+    #[test]
+    fn move_dir_conflicts() {
+        let repo = TempDir::new("move-dir-conflicts");
+        add(repo.path(), "d/e/f", "pw f");
+        add(repo.path(), "entry", "pw");
+
+        assert!(matches!(mv(&repo, "d", "d/e/g", false), Err(Error::InvalidArgument(_))));
+        assert!(matches!(mv(&repo, "d", "entry", false), Err(Error::Exists(_))));
+        mv(&repo, "d", "d", false).unwrap();
+        mv(&repo, "d", "n/d", false).unwrap();
+        assert_eq!(entries(repo.path()), expect(&[("entry", "pw"), ("n/d/e/f", "pw f")]));
+    }
+
+    #[test]
+    fn empty_dirs_are_removed() {
+        let dir = TempDir::new("move-empty-dirs");
+        let root = dir.path().join("root");
+        fs::create_dir_all(root.join("a/b/c")).unwrap();
+        fs::create_dir_all(root.join("d/e")).unwrap();
+        fs::write(root.join("d/file"), "x").unwrap();
+
+        remove_empty_dirs(&root).unwrap();
+        assert!(!root.join("a").exists());
+        assert!(!root.join("d/e").exists());
+        assert!(root.join("d/file").is_file());
 
-    match fs::rename(full_path_old, full_path_new) {
-        Ok(_) => {},
-        Err(e) => println!("An error occurred while moving: {}", e),
+        fs::remove_file(root.join("d/file")).unwrap();
+        remove_empty_dirs(&root).unwrap();
+        assert!(!root.exists());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,92 @@
+extern crate rpassword;
+
+use crate::util::{Options, add_entry, prepare_entry_path, show_entry};
+use crate::transform;
+use crate::entry::Entry;
+use crate::otp;
+use crate::clipboard;
+use crate::error::Error;
+
+use std::path;
+
+pub fn cmd_otp(opts: &Options, prefix: &path::Path, enc_params: &transform::EncryptionParams) -> Result<(), Error> {
+    if !opts.args.is_empty() && opts.args[0] == "insert" {
+        return cmd_otp_insert(opts, prefix, enc_params);
+    }
+
+    if opts.args.len() != 1 {
+        return Err(Error::Usage(format!("Incorrect number of arguments. Want: 'path' or 'insert, path, [uri]'  Got: {}", opts.args.len())));
+    }
+
+    let relative_path = path::Path::new(prepare_entry_path(opts.args[0].as_str()));
+    let content = show_entry(prefix, relative_path, enc_params)?;
+    let params = otp::find_params(&Entry::parse(content.as_str()))?;
+
+    match params.kind {
+        otp::OtpKind::Totp { period } => {
+            let now = otp::now();
+            output(opts, otp::totp_at(&params, now)?.as_str())?;
+            if opts.verbose {
+                println!("Valid for {} more seconds", period - now % period);
+            }
+            Ok(())
+        },
+        otp::OtpKind::Hotp { counter } => {
+            //the counter moves on with every code, so the entry is written before the code is shown
+            let code = otp::hotp(&params.secret, counter, params.digits, params.algorithm)?;
+            let updated = otp::set_hotp_counter(content.as_str(), counter + 1)?;
+            add_entry(prefix, relative_path, updated.as_str(), true, enc_params)?;
+            output(opts, code.as_str())
+        },
+    }
+}
+
+// with --clip the code goes to the clipboard like show -c does, the previous content is restored after --clip-time
+fn output(opts: &Options, code: &str) -> Result<(), Error> {
+    if opts.clip {
+        clipboard::copy_with_restore(code, opts.clip_time)?;
+        println!("Copied the one-time password to the clipboard. The previous content is restored in {} seconds.", opts.clip_time);
+        return Ok(());
+    }
+
+    print!("{}", code);
+    if opts.verbose {
+        println!();
+    }
+    Ok(())
+}
+
+// adds the URI to an existing entry or creates an entry that only holds the URI, like pass-otp does
+fn cmd_otp_insert(opts: &Options, prefix: &path::Path, enc_params: &transform::EncryptionParams) -> Result<(), Error> {
+    if opts.args.len() < 2 || opts.args.len() > 3 {
+        return Err(Error::Usage(format!("Incorrect number of arguments. Want: 'insert, path, [uri]'  Got: {}", opts.args.len())));
+    }
+
+    let relative_path = path::Path::new(prepare_entry_path(opts.args[1].as_str()));
+    let uri = if opts.args.len() == 3 {
+        opts.args[2].clone()
+    } else {
+        if !opts.interactive {
+            return Err(Error::Usage("No URI given and interactive mode deactivated".to_owned()));
+        }
+        println!("Enter otpauth:// URI for {}: ", opts.args[1]);
+        rpassword::read_password()?
+    };
+
+    //refuse URIs that can't produce codes
+    otp::parse_uri(uri.as_str())?;
+
+    let content = match show_entry(prefix, relative_path, enc_params) {
+        Ok(c) => {
+            if otp::find_uri(&Entry::parse(c.as_str())).is_some() && !opts.force {
+                return Err(Error::Exists(format!("otpauth URI in {}", opts.args[1])));
+            }
+            otp::replace_uri(c.as_str(), uri.trim())
+        },
+        Err(Error::NotFound(_)) => uri.trim().to_owned(),
+        Err(e) => return Err(e),
+    };
+
+    if opts.verbose {println!("Adding otpauth URI to Entry: {}", relative_path.display());}
+    add_entry(prefix, relative_path, content.as_str(), true, enc_params)
+}
@@ -0,0 +1,332 @@
+extern crate rpassword;
+extern crate libc;
+
+use crate::util::{Options, TreeNode, add_entry, flatten_tree, get_tree_from_path_strict, is_hidden, prepare_entry_path, show_entry};
+use crate::transform;
+use crate::repo;
+use crate::error::Error;
+
+use std::path;
+use std::fs;
+
+fn read_new_key_from_terminal() -> Result<String, Error> {
+    println!("Enter new key for the repo: ");
+    let first = rpassword::read_password()?;
+    println!("Repeat the new key: ");
+    let second = rpassword::read_password()?;
+
+    if first != second {
+        return Err(Error::Usage("Keys did not match".to_owned()));
+    }
+    Ok(first)
+}
+
+// appends a suffix to the last component of the repo path, so the staging dirs end up next to the repo
+fn sibling_path(prefix: &path::Path, suffix: &str) -> Result<path::PathBuf, Error> {
+    let mut name = match prefix.file_name() {
+        Some(n) => n.to_os_string(),
+        None => return Err(Error::InvalidArgument(format!("{} has no parent dir to put the staging dir in", prefix.display()))),
+    };
+    name.push(suffix);
+    Ok(prefix.with_file_name(name))
+}
+
+pub fn cmd_rekey(opts: &Options, prefix: &path::Path, enc_params: &transform::EncryptionParams) -> Result<(), Error> {
+    if !opts.args.is_empty() {
+        return Err(Error::Usage(format!("Incorrect number of arguments. Want: ''  Got: {}", opts.args.len())));
+    }
+
+    let new_key = if !opts.new_key.is_empty() {
+        opts.new_key.clone()
+    } else {
+        if !opts.interactive {
+            return Err(Error::Usage("No new key given and interactive mode deactivated".to_owned()));
+        }
+        read_new_key_from_terminal()?
+    };
+
+    rewrite_repo(opts, prefix, enc_params, new_key.as_str())
+}
+
+// rewrites the repo in the current format (kdf, name scheme) but keeps the master key
+pub fn cmd_migrate(opts: &Options, prefix: &path::Path, enc_params: &transform::EncryptionParams) -> Result<(), Error> {
+    if !opts.args.is_empty() {
+        return Err(Error::Usage(format!("Incorrect number of arguments. Want: ''  Got: {}", opts.args.len())));
+    }
+
+    rewrite_repo(opts, prefix, enc_params, opts.key.as_str())
+}
+
+// re-encrypts all names and contents under the new key into a staging dir and swaps it with the repo
+fn rewrite_repo(opts: &Options, prefix: &path::Path, enc_params: &transform::EncryptionParams, new_key: &str) -> Result<(), Error> {
+    //a repo path like '.' has no name to build the staging dirs from and can't be renamed
+    let prefix = fs::canonicalize(prefix)?;
+    let prefix = prefix.as_path();
+
+    let old_header = match repo::RepoHeader::read(prefix)? {
+        Some(h) => h,
+        None => repo::RepoHeader::legacy(),
+    };
+
+    //legacy repos get the default kdf. Otherwise the kdf chosen on init is kept, unless --kdf or --kdf-params ask for another one
+    let kdf = match old_header.kdf {
+        ref kdf if opts.kdf.is_empty() && *kdf != repo::Kdf::Sha256 => kdf.with_params(opts.kdf_params.as_str())?,
+        _ => repo::Kdf::from_args(opts.kdf.as_str(), opts.kdf_params.as_str())?,
+    };
+
+    let new_header = repo::RepoHeader::new(kdf, old_header.cipher, new_key.as_bytes())?;
+    let new_key = new_header.derive_key(new_key.as_bytes())?;
+    let new_enc_params = new_header.encryption_params(&new_key);
+
+    //everything is written into a staging dir first and then swapped with the repo
+    let staging = sibling_path(prefix, ".rekey")?;
+    let old = sibling_path(prefix, ".rekey-old")?;
+    if staging.exists() || old.exists() {
+        return Err(Error::Exists(format!(
+            "leftovers of an earlier rekey: {} or {}. Check which one is complete and remove the other before trying again",
+            staging.display(),
+            old.display()
+        )));
+    }
+
+    //files that are not entries would be lost with the old repo
+    let tree = match get_tree_from_path_strict(prefix, None, enc_params) {
+        Ok(t) => t,
+        Err(e @ Error::CorruptEntry(_)) | Err(e @ Error::NonUtf8Name(_)) => {
+            eprintln!("The repo contains a file that is not an entry. Move it out of the repo before rekeying");
+            return Err(e);
+        },
+        Err(e) => return Err(e),
+    };
+    let renamed_tree = match tree {
+        TreeNode::Node(_, children) => TreeNode::Node("".to_owned(), children),
+        TreeNode::Leaf(_) => TreeNode::Leaf("".to_owned()),
+    };
+
+    fs::create_dir_all(&staging)?;
+
+    for entry in flatten_tree(&renamed_tree, "".to_owned()) {
+        let relative_path = path::Path::new(prepare_entry_path(entry.as_str()));
+        if opts.verbose {println!("Rekeying Entry: {}", relative_path.display());}
+
+        let result = show_entry(prefix, relative_path, enc_params)
+            .and_then(|content| add_entry(&staging, relative_path, content.as_str(), false, &new_enc_params));
+        if let Err(e) = result {
+            eprintln!("An error occurred while rekeying {}", relative_path.display());
+            eprintln!("The repo was not changed. The partial result is in: {}", staging.display());
+            return Err(e);
+        }
+    }
+
+    if let Err(e) = new_header.write(&staging) {
+        eprintln!("The repo was not changed. The partial result is in: {}", staging.display());
+        return Err(e);
+    }
+
+    //swapping the dirs in one step means an interruption leaves either the old or the new repo in place
+    let old_repo = match exchange_dirs(&staging, prefix) {
+        Ok(_) => staging.clone(),
+        Err(e) => {
+            if opts.verbose {println!("Couldn't swap the dirs atomically ({}), falling back to two renames", e);}
+            if let Err(e) = fs::rename(prefix, &old) {
+                eprintln!("The repo was not changed. The rekeyed repo is in: {}", staging.display());
+                return Err(Error::Io(e));
+            }
+            if let Err(e) = fs::rename(&staging, prefix) {
+                match fs::rename(&old, prefix) {
+                    Ok(_) => eprintln!("The repo was not changed. The rekeyed repo is in: {}", staging.display()),
+                    Err(_) => eprintln!("The old repo is at: {}, the rekeyed one at: {}", old.display(), staging.display()),
+                }
+                return Err(Error::Io(e));
+            }
+            old.clone()
+        },
+    };
+
+    //hidden files like a .git dir are not entries. They are moved over only now, so a failed rekey never takes them
+    //out of the repo
+    if let Err(e) = carry_over_hidden_files(&old_repo, prefix, "", enc_params, &new_enc_params) {
+        eprintln!("The repo was rekeyed, but not all hidden files (like .git) could be moved over. Move the rest from {} to {} and remove {} afterwards",
+            old_repo.display(), prefix.display(), old_repo.display());
+        return Err(e);
+    }
+    if let Err(e) = fs::remove_dir_all(&old_repo) {
+        eprintln!("The repo was rekeyed but the old repo could not be removed: {}, {}", old_repo.display(), e);
+    }
+    Ok(())
+}
+
+// 'dir' is the clear path of 'from'. Hidden files in a dir end up in the dir with the same clear path in the new repo,
+// which has a different encrypted name
+fn carry_over_hidden_files(from: &path::Path, to: &path::Path, dir: &str, enc_params: &transform::EncryptionParams, new_enc_params: &transform::EncryptionParams) -> Result<(), Error> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let p = entry.path();
+        if is_hidden(&p) {
+            if dir.is_empty() && entry.file_name() == repo::HEADER_FILE {
+                continue;
+            }
+            //a dir with only hidden files in it has no entries, so it was not created by the rekey
+            fs::create_dir_all(to)?;
+            fs::rename(&p, to.join(entry.file_name()))?;
+        } else if p.is_dir() {
+            let name = match entry.file_name().to_str() {
+                Some(n) => transform::retransform_name(enc_params, dir, n)?,
+                None => return Err(Error::NonUtf8Name(p)),
+            };
+            let new_name = transform::transform_name(new_enc_params, dir, name.as_str())?;
+            carry_over_hidden_files(&p, &to.join(new_name), transform::join_entry_path(dir, name.as_str()).as_str(), enc_params, new_enc_params)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn exchange_dirs(a: &path::Path, b: &path::Path) -> std::io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let a = CString::new(a.as_os_str().as_bytes()).unwrap();
+    let b = CString::new(b.as_os_str().as_bytes()).unwrap();
+    let res = unsafe {
+        libc::renameat2(libc::AT_FDCWD, a.as_ptr(), libc::AT_FDCWD, b.as_ptr(), libc::RENAME_EXCHANGE)
+    };
+    if res != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn exchange_dirs(_a: &path::Path, _b: &path::Path) -> std::io::Result<()> {
+    Err(std::io::Error::other("not supported on this platform"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestRepo {
+        dir: crate::test_util::TempDir,
+    }
+
+    impl TestRepo {
+        fn path(&self) -> path::PathBuf {
+            self.dir.path().join("repo")
+        }
+
+        fn add(&self, password: &str, entry: &str, content: &str) {
+            let (header, key) = repo::unlock(&self.path(), password.as_bytes()).unwrap();
+            add_entry(&self.path(), path::Path::new(entry), content, false, &header.encryption_params(&key)).unwrap();
+        }
+
+        fn show(&self, password: &str, entry: &str) -> String {
+            let (header, key) = repo::unlock(&self.path(), password.as_bytes()).unwrap();
+            show_entry(&self.path(), path::Path::new(entry), &header.encryption_params(&key)).unwrap()
+        }
+
+        // the encrypted path of a dir, for putting hidden files into it
+        fn dir(&self, password: &str, dir: &str) -> path::PathBuf {
+            let (header, key) = repo::unlock(&self.path(), password.as_bytes()).unwrap();
+            self.path().join(transform::transform_path(&header.encryption_params(&key), dir).unwrap().join("/"))
+        }
+
+        fn run(&self, password: &str, opts: Options, cmd: fn(&Options, &path::Path, &transform::EncryptionParams) -> Result<(), Error>) -> Result<(), Error> {
+            let (header, key) = repo::unlock(&self.path(), password.as_bytes()).unwrap();
+            let opts = Options { key: password.to_owned(), ..opts };
+            cmd(&opts, &self.path(), &header.encryption_params(&key))
+        }
+    }
+
+    fn test_repo(name: &str) -> TestRepo {
+        let repo = TestRepo { dir: crate::test_util::TempDir::new(name) };
+        repo::init_repo(&repo.path(), repo::Kdf::Pbkdf2 { iterations: 1000 }, transform::EntryCipher::Aes256Gcm, b"old").unwrap();
+        repo.add("old", "mail/work", "pw work");
+        repo.add("old", "mail/private/home", "pw home");
+        repo.add("old", "bank", "pw bank");
+        repo
+    }
+
+    fn rekey_opts(new_key: &str) -> Options {
+        Options { new_key: new_key.to_owned(), ..Default::default() }
+    }
+
+    #[test]
+    fn rekey() {
+        let repo = test_repo("rekey");
+        fs::create_dir(repo.path().join(".git")).unwrap();
+        fs::write(repo.path().join(".git/config"), "git").unwrap();
+        fs::write(repo.dir("old", "mail/private").join(".keep"), "keep").unwrap();
+
+        repo.run("old", rekey_opts("new"), cmd_rekey).unwrap();
+
+        assert!(matches!(repo::unlock(&repo.path(), b"old"), Err(Error::WrongKey)));
+        let header = repo::RepoHeader::read(&repo.path()).unwrap().unwrap();
+        assert_eq!(header.kdf, repo::Kdf::Pbkdf2 { iterations: 1000 });
+        assert_eq!(repo.show("new", "mail/work"), "pw work");
+        assert_eq!(repo.show("new", "mail/private/home"), "pw home");
+        assert_eq!(repo.show("new", "bank"), "pw bank");
+
+        //hidden files keep their place, also in dirs whose encrypted names changed
+        assert_eq!(fs::read_to_string(repo.path().join(".git/config")).unwrap(), "git");
+        assert_eq!(fs::read_to_string(repo.dir("new", "mail/private").join(".keep")).unwrap(), "keep");
+        assert!(!sibling_path(&repo.path(), ".rekey").unwrap().exists());
+        assert!(!sibling_path(&repo.path(), ".rekey-old").unwrap().exists());
+    }
+
+    #[test]
+    fn rekey_changes_the_kdf() {
+        let repo = test_repo("rekey-kdf");
+
+        let opts = Options { kdf_params: "i=2000".to_owned(), ..rekey_opts("new") };
+        repo.run("old", opts, cmd_rekey).unwrap();
+        let header = repo::RepoHeader::read(&repo.path()).unwrap().unwrap();
+        assert_eq!(header.kdf, repo::Kdf::Pbkdf2 { iterations: 2000 });
+
+        let opts = Options { kdf: "scrypt".to_owned(), kdf_params: "n=1024,r=8,p=1".to_owned(), ..Default::default() };
+        repo.run("new", opts, cmd_migrate).unwrap();
+        let header = repo::RepoHeader::read(&repo.path()).unwrap().unwrap();
+        assert_eq!(header.kdf, repo::Kdf::Scrypt { n: 1024, r: 8, p: 1 });
+        assert_eq!(repo.show("new", "mail/private/home"), "pw home");
+
+        let opts = Options { kdf: "md5".to_owned(), ..rekey_opts("newer") };
+        assert!(matches!(repo.run("new", opts, cmd_rekey), Err(Error::InvalidArgument(_))));
+        assert_eq!(repo.show("new", "bank"), "pw bank");
+    }
+
+    #[test]
+    fn migrate_legacy_repo() {
+        let repo = TestRepo { dir: crate::test_util::TempDir::new("migrate-legacy") };
+        let legacy = repo::RepoHeader::legacy();
+        let key = legacy.derive_key(b"pw").unwrap();
+        let legacy_params = legacy.encryption_params(&key);
+        for (entry, content) in &[("mail/work", "pw work"), ("bank", "pw bank")] {
+            add_entry(&repo.path(), path::Path::new(entry), content, false, &legacy_params).unwrap();
+        }
+
+        let opts = Options { kdf: "pbkdf2".to_owned(), kdf_params: "i=1000".to_owned(), ..Default::default() };
+        repo.run("pw", opts, cmd_migrate).unwrap();
+
+        let header = repo::RepoHeader::read(&repo.path()).unwrap().unwrap();
+        assert_eq!(header.version, repo::REPO_VERSION);
+        assert_eq!(header.names, transform::NameScheme::Siv);
+        assert_eq!(header.kdf, repo::Kdf::Pbkdf2 { iterations: 1000 });
+        assert_eq!(repo.show("pw", "mail/work"), "pw work");
+        assert_eq!(repo.show("pw", "bank"), "pw bank");
+    }
+
+    #[test]
+    fn rekey_refuses_stray_files() {
+        let repo = test_repo("rekey-stray");
+        fs::write(repo.path().join("notes.txt"), "not an entry").unwrap();
+
+        assert!(matches!(repo.run("old", rekey_opts("new"), cmd_rekey), Err(Error::CorruptEntry(_))));
+        assert_eq!(repo.show("old", "bank"), "pw bank");
+        assert!(!sibling_path(&repo.path(), ".rekey").unwrap().exists());
+    }
+
+    #[test]
+    fn staging_dirs_need_a_parent() {
+        assert!(matches!(sibling_path(path::Path::new("/"), ".rekey"), Err(Error::InvalidArgument(_))));
+    }
+}
@@ -1,42 +1,30 @@
 use crate::util::{Options, prepare_entry_path};
 use crate::transform;
+use crate::error::Error;
 
 use std::path;
 use std::fs;
 
-pub fn cmd_remove(opts: &Options, prefix: &path::Path , enc_params: &transform::EncryptionParams) {
+pub fn cmd_remove(opts: &Options, prefix: &path::Path , enc_params: &transform::EncryptionParams) -> Result<(), Error> {
     if opts.args.len() != 1 {
-        println!("Incorrect number of arguments. Want: 'path'  Got: {}", opts.args.len());
-        return;
+        return Err(Error::Usage(format!("Incorrect number of arguments. Want: 'path'  Got: {}", opts.args.len())));
     }
 
     let relative_path = prepare_entry_path(opts.args[0].as_str());
     if opts.verbose {println!("Removing Entry: {}", relative_path);}
 
-    let trans_path = transform::transform_path(enc_params, relative_path);
+    let trans_path = transform::transform_path(enc_params, relative_path)?;
     let full_path = prefix.join(trans_path.join("/"));
 
     if full_path.is_file() {
-        match fs::remove_file(full_path) {
-            Ok(_) => {},
-            Err(e) => {
-                println!("An error occurred while removing: {}", e);
-                return;
-            },
-        }
-    } else { 
-        if full_path.is_dir() {
-            if opts.recursive {
-                match fs::remove_dir_all(full_path) {
-                    Ok(_) => {},
-                    Err(e) => {
-                        println!("An error occurred while removing: {}", e);
-                        return;
-                    },
-                }
-            } else {
-                println!("Tried to remove directory without recursive flag set");
-            }
+        fs::remove_file(full_path)?;
+    } else if full_path.is_dir() {
+        if !opts.recursive {
+            return Err(Error::Usage("Tried to remove directory without recursive flag set".to_owned()));
         }
+        fs::remove_dir_all(full_path)?;
+    } else {
+        return Err(Error::NotFound(relative_path.to_owned()));
     }
-}
\ No newline at end of file
+    Ok(())
+}
@@ -1,22 +1,22 @@
 use crate::util::{Options, prepare_entry_path, get_all_entries_in_path, get_tree_from_path, print_tree, flatten_tree, sort_tree_leveshtein};
 use crate::transform;
+use crate::error::Error;
 
 use std::path;
 
-pub fn cmd_search_fuzzy (opts: &Options, prefix: &path::Path, enc_params: &transform::EncryptionParams) {
+pub fn cmd_search_fuzzy (opts: &Options, prefix: &path::Path, enc_params: &transform::EncryptionParams) -> Result<(), Error> {
     if opts.args.len() > 1 {
-        println!("Too many arguments. Want: '[pattern]'  Got: {}", opts.args.len());
-        return;
+        return Err(Error::Usage(format!("Too many arguments. Want: '[pattern]'  Got: {}", opts.args.len())));
     }
 
-    let relative_path = if opts.args.len() == 0 {
+    let relative_path = if opts.args.is_empty() {
         ""
     } else {
         prepare_entry_path(opts.args[0].as_str())
     };
     let words: Vec<&str> = relative_path.split("/").collect();
 
-    let tree = get_tree_from_path(prefix, true, enc_params).unwrap();
+    let tree = get_tree_from_path(prefix, None, enc_params)?;
 
     let sorted_tree = sort_tree_leveshtein(&tree, words);
 
@@ -28,15 +28,15 @@ pub fn cmd_search_fuzzy (opts: &Options, prefix: &path::Path, enc_params: &trans
             println!("{}", e);
         }
     }       
+    Ok(())
 }
 
-pub fn cmd_search(opts: &Options, prefix: &path::Path, enc_params: &transform::EncryptionParams) {
+pub fn cmd_search(opts: &Options, prefix: &path::Path, enc_params: &transform::EncryptionParams) -> Result<(), Error> {
     if opts.args.len() > 1 {
-        println!("Too many arguments. Want: '[pattern]'  Got: {}", opts.args.len());
-        return;
+        return Err(Error::Usage(format!("Too many arguments. Want: '[pattern]'  Got: {}", opts.args.len())));
     }
 
-    let relative_path = if opts.args.len() == 0 {
+    let relative_path = if opts.args.is_empty() {
         ""
     } else {
         prepare_entry_path(opts.args[0].as_str())
@@ -57,8 +57,8 @@ pub fn cmd_search(opts: &Options, prefix: &path::Path, enc_params: &transform::E
         None => ""
     };
 
-    let trans_path = if dir.len() > 0 {
-        transform::transform_path(enc_params, dir)
+    let trans_path = if !dir.is_empty() {
+        transform::transform_path(enc_params, dir)?
     }else{
         Vec::new()
     };
@@ -69,42 +69,39 @@ pub fn cmd_search(opts: &Options, prefix: &path::Path, enc_params: &transform::E
     let trans_path_dir = trans_path_temp.as_path();
 
     if opts.verbose {println!("Searching in: {}", trans_path_dir.to_str().unwrap());}
-    let entries = match get_all_entries_in_path(trans_path_dir){
-        Ok(vec) => vec,
-        Err(err) => {
-            println!("An error occurred while listing entries: {}", err);
-            return;
-        },
-    };
+    if !trans_path_dir.is_dir() {
+        return Err(Error::NotFound(relative_path.to_owned()));
+    }
+    let entries = get_all_entries_in_path(trans_path_dir)?;
 
 
     let mut filtered = Vec::new();
-    for (e, dir) in entries {
-        let clear_entry = match transform::retransform_entry(enc_params, &(e[..])) {
-            Ok(s) => s,
-            Err(e) => {
-                println!("Error occured while decrypting: {}", e); 
-                return
+    for (e, is_dir) in entries {
+        let clear_entry = match transform::retransform_name(enc_params, dir, &(e[..])) {
+            Ok(name) => name,
+            //a file in the repo that was not written by sparkpass, like get_tree_from_path does
+            Err(err @ Error::CorruptEntry(_)) => {
+                eprintln!("Skipping {}, it is not an entry. {}", trans_path_dir.join(&e).display(), err);
+                continue;
             },
+            Err(err) => return Err(err),
         };
-        if !clear_entry.contains(last) && !(clear_entry == last) {
+        if !clear_entry.contains(last) {
             continue;
         }
-        filtered.push((clear_entry, dir));
+        filtered.push((clear_entry, is_dir));
     }
 
-    if filtered.len() == 0 {
-        println!("No matching entries found");
-        return;
+    if filtered.is_empty() {
+        return Err(Error::NotFound(relative_path.to_owned()));
     }
     if opts.verbose {println!("Found Entries:");}
 
     if filtered.len() == 1 {
         let (_,dir) = & filtered[0];
         if *dir {
-            crate::cmd::cmd_list::cmd_list_tree(opts, prefix, enc_params)
+            return crate::cmd::cmd_list::cmd_list_tree(opts, prefix, enc_params);
         }
-        return;
     }
 
     for (e, dir) in filtered {
@@ -115,4 +112,40 @@ pub fn cmd_search(opts: &Options, prefix: &path::Path, enc_params: &transform::E
         }
         println!("{}", e);
     }
-}
\ No newline at end of file
+    Ok(())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{add, enc_params, TempDir};
+    use std::os::unix::ffi::OsStrExt;
+
+    fn search(prefix: &path::Path, pattern: &str) -> Result<(), Error> {
+        let opts = Options { args: vec![pattern.to_owned()], ..Default::default() };
+        cmd_search(&opts, prefix, &enc_params())
+    }
+
+    #[test]
+    fn stray_files_are_skipped() {
+        let repo = TempDir::new("search-stray");
+        add(repo.path(), "mail/work", "pw");
+        add(repo.path(), "bank", "pw");
+        std::fs::write(repo.path().join("notes.txt"), "not an entry").unwrap();
+        std::fs::write(repo.path().join("README~x"), "not an entry").unwrap();
+        std::fs::write(repo.path().join(std::ffi::OsStr::from_bytes(b"\xff\xfe")), "not UTF-8").unwrap();
+
+        search(repo.path(), "ban").unwrap();
+        search(repo.path(), "").unwrap();
+        match search(repo.path(), "nothing") {
+            Err(Error::NotFound(_)) => {},
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        //show falls back to the search and still reports the missing entry
+        let opts = Options { args: vec!["mai".to_owned()], line: -1, ..Default::default() };
+        match crate::cmd::cmd_show::cmd_show(&opts, repo.path(), &enc_params()) {
+            Err(Error::NotFound(_)) => {},
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+}
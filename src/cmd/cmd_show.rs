@@ -1,39 +1,74 @@
 use crate::util::{Options, prepare_entry_path, show_entry};
 use crate::transform;
+use crate::error::Error;
+use crate::entry::Entry;
+use crate::clipboard;
+use crate::qr;
 use crate::cmd::cmd_search::cmd_search;
 
 use std::path;
 
-pub fn cmd_show(opts: &Options, prefix: &path::Path, enc_params: &transform::EncryptionParams) {
+pub fn cmd_show(opts: &Options, prefix: &path::Path, enc_params: &transform::EncryptionParams) -> Result<(), Error> {
     if opts.args.len() != 1 {
-        println!("Incorrect number of arguments. Want: 'path_to_entry'  Got: {}", opts.args.len());
-        return;
+        return Err(Error::Usage(format!("Incorrect number of arguments. Want: 'path_to_entry'  Got: {}", opts.args.len())));
     }
 
     let relative_path = prepare_entry_path(opts.args[0].as_str());
     let mut content = match show_entry(prefix, path::Path::new(relative_path), enc_params) {
         Ok(c) => c,
-        Err(_) => {
-            //entry doesn't exist. Search for it instead
-            cmd_search(opts, prefix, enc_params);
-            return;
+        Err(e @ Error::NotFound(_)) | Err(e @ Error::IsDir(_)) => {
+            //entry doesn't exist. List the candidates, but it is still an error
+            match cmd_search(opts, prefix, enc_params) {
+                Ok(()) | Err(Error::NotFound(_)) => return Err(e),
+                Err(search_err) => return Err(search_err),
+            }
         },
+        Err(e) => return Err(e),
     };
 
+    if !opts.field.is_empty() {
+        content = match Entry::parse(content.as_str()).get(opts.field.as_str()) {
+            Some(v) => v,
+            None => return Err(Error::NotFound(format!("field {} in {}", opts.field, relative_path))),
+        };
+        return output(opts, relative_path, content.as_str());
+    }
+
     let lines: Vec<&str> = content.split("\n").collect();
     let idx: usize = opts.line as usize;
     if opts.line >= 0 && idx >= lines.len() {
-        println!("Line too big. Given: {}, max line in entry: {}", idx, lines.len());
-        return;
+        return Err(Error::Usage(format!("Line too big. Given: {}, max line in entry: {}", idx, lines.len())));
     }
     if opts.line >= 0 {
         content = lines[idx].to_owned();
     }
 
     if opts.verbose {println!("Showing entry: {}", relative_path);}
+    output(opts, relative_path, content.as_str())
+}
+
+fn output(opts: &Options, relative_path: &str, content: &str) -> Result<(), Error> {
+    if opts.qrcode || !opts.qrcode_out.is_empty() {
+        if !opts.qrcode_out.is_empty() {
+            qr::write_file(content, path::Path::new(opts.qrcode_out.as_str()))?;
+            if opts.verbose {println!("Wrote QR code of {} to {}", relative_path, opts.qrcode_out);}
+        }
+        if opts.qrcode {
+            print!("{}", qr::render_terminal(content)?);
+        }
+        return Ok(());
+    }
+
+    if opts.clip {
+        clipboard::copy_with_restore(content, opts.clip_time)?;
+        println!("Copied {} to the clipboard. The previous content is restored in {} seconds.", relative_path, opts.clip_time);
+        return Ok(());
+    }
+
     if opts.verbose {
         println!("Content: {}", content);
     }else{
         print!("{}", content);
     }
-}
\ No newline at end of file
+    Ok(())
+}
@@ -1,9 +1,13 @@
 pub mod cmd_add;
 pub mod cmd_copy;
+pub mod cmd_edit;
 pub mod cmd_generate;
+pub mod cmd_grep;
 pub mod cmd_init;
 pub mod cmd_list;
 pub mod cmd_move;
+pub mod cmd_otp;
+pub mod cmd_rekey;
 pub mod cmd_remove;
 pub mod cmd_search;
 pub mod cmd_show;
\ No newline at end of file
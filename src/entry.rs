@@ -0,0 +1,234 @@
+// The structure of the content of an entry, following the pass conventions: the first line is the password,
+// the following lines are either "key: value" fields or free form notes
+use std::fmt;
+
+pub enum EntryLine {
+    Field(String, String),
+    Note(String),
+}
+
+// the field the old passwords are kept in by generate --in-place --keep-history, oldest first
+pub static HISTORY_FIELD: &str = "old-password";
+
+pub struct Entry {
+    pub password: String,
+    pub lines: Vec<EntryLine>,
+}
+
+// the name used for a field in exports and columns, other names of the common fields are mapped to it
+pub fn canonical_field(name: &str) -> String {
+    let lower = name.to_lowercase();
+    field_aliases(lower.as_str())[0].to_owned()
+}
+
+// other names for the common fields used by pass and its extensions
+fn field_aliases(name: &str) -> Vec<&str> {
+    match name {
+        "username" | "user" | "login" => vec!["username", "user", "login"],
+        "url" | "website" => vec!["url", "website"],
+        _ => vec![name],
+    }
+}
+
+// a line is a field if it starts with a key without whitespace followed by ':' and whitespace or the end of the line.
+// That keeps lines like "otpauth://..." or "https://..." as notes
+fn parse_field(line: &str) -> Option<(String, String)> {
+    let idx = line.find(':')?;
+    let key = &line[..idx];
+    let value = &line[idx + 1..];
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        return None;
+    }
+    if !value.is_empty() && !value.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some((key.to_owned(), value.trim().to_owned()))
+}
+
+// the first line of the content, without the line break
+pub fn first_line(content: &str) -> &str {
+    let line = content.split('\n').next().unwrap_or("");
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+// replaces the first line and keeps the rest of the content byte for byte, unlike parsing and formatting it with Entry
+pub fn replace_first_line(content: &str, line: &str) -> String {
+    let old = first_line(content);
+    format!("{}{}", line, &content[old.len()..])
+}
+
+// replaces the line with this index (the password is line 0) and keeps everything else byte for byte
+pub fn replace_line(content: &str, index: usize, line: &str) -> String {
+    let mut start = 0;
+    for _ in 0..index {
+        match content[start..].find('\n') {
+            Some(i) => start += i + 1,
+            None => return append_line(content, line),
+        }
+    }
+    let old = first_line(&content[start..]);
+    format!("{}{}{}", &content[..start], line, &content[start + old.len()..])
+}
+
+// adds a line at the end, keeping a final line break
+pub fn append_line(content: &str, line: &str) -> String {
+    match content.strip_suffix('\n') {
+        Some(rest) => format!("{}\n{}\n", rest, line),
+        None => format!("{}\n{}", content, line),
+    }
+}
+
+impl Entry {
+    pub fn new(password: &str) -> Entry {
+        Entry {
+            password: password.to_owned(),
+            lines: Vec::new(),
+        }
+    }
+
+    pub fn parse(content: &str) -> Entry {
+        let mut it = content.lines();
+        let password = it.next().unwrap_or("").to_owned();
+
+        let mut lines = Vec::new();
+        for line in it {
+            match parse_field(line) {
+                Some((k, v)) => lines.push(EntryLine::Field(k, v)),
+                None => lines.push(EntryLine::Note(line.to_owned())),
+            }
+        }
+
+        Entry { password, lines }
+    }
+
+    // "password" and "notes" are the first line and the lines that are no fields, everything else is looked up by key.
+    // Keys are case insensitive
+    pub fn get(&self, name: &str) -> Option<String> {
+        let name = name.to_lowercase();
+        match name.as_str() {
+            "password" => return Some(self.password.clone()),
+            "notes" => {
+                let notes = self.notes();
+                if notes.is_empty() {
+                    return None;
+                }
+                return Some(notes);
+            },
+            _ => {},
+        }
+
+        let aliases = field_aliases(name.as_str());
+        for line in &self.lines {
+            if let EntryLine::Field(k, v) = line {
+                if aliases.contains(&k.to_lowercase().as_str()) {
+                    return Some(v.clone());
+                }
+            }
+        }
+        None
+    }
+
+    // replaces the first field with this key or appends a new one. Empty values are not stored
+    pub fn set(&mut self, name: &str, value: &str) {
+        match name.to_lowercase().as_str() {
+            "password" => {
+                self.password = value.to_owned();
+                return;
+            },
+            "notes" => {
+                self.set_notes(value);
+                return;
+            },
+            _ => {},
+        }
+        if value.is_empty() {
+            return;
+        }
+
+        let lower = name.to_lowercase();
+        let aliases = field_aliases(lower.as_str());
+        for line in self.lines.iter_mut() {
+            if let EntryLine::Field(k, v) = line {
+                if aliases.contains(&k.to_lowercase().as_str()) {
+                    *v = value.to_owned();
+                    return;
+                }
+            }
+        }
+        self.lines.push(EntryLine::Field(name.to_owned(), value.to_owned()));
+    }
+
+    // appends a field even if one with this key exists, e.g. for the password history
+    pub fn add(&mut self, name: &str, value: &str) {
+        self.lines.push(EntryLine::Field(name.to_owned(), value.to_owned()));
+    }
+
+    pub fn fields(&self) -> Vec<(&str, &str)> {
+        let mut res = Vec::new();
+        for line in &self.lines {
+            if let EntryLine::Field(k, v) = line {
+                res.push((k.as_str(), v.as_str()));
+            }
+        }
+        res
+    }
+
+    pub fn notes(&self) -> String {
+        let mut notes = Vec::new();
+        for line in &self.lines {
+            if let EntryLine::Note(n) = line {
+                notes.push(n.as_str());
+            }
+        }
+        notes.join("\n")
+    }
+
+    fn set_notes(&mut self, notes: &str) {
+        self.lines.retain(|l| match l {
+            EntryLine::Note(_) => false,
+            EntryLine::Field(_, _) => true,
+        });
+        for n in notes.lines() {
+            self.lines.push(EntryLine::Note(n.to_owned()));
+        }
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.password)?;
+        for line in &self.lines {
+            match line {
+                EntryLine::Field(k, v) => write!(f, "\n{}: {}", k, v)?,
+                EntryLine::Note(n) => write!(f, "\n{}", n)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replace_first_line_keeps_the_rest() {
+        assert_eq!(replace_first_line("pw\nkey:   spaced  \nnotes:", "new"), "new\nkey:   spaced  \nnotes:");
+        assert_eq!(replace_first_line("pw\r\nnote\n", "new"), "new\r\nnote\n");
+        assert_eq!(replace_first_line("pw", "new"), "new");
+        assert_eq!(replace_first_line("", "new"), "new");
+    }
+
+    #[test]
+    fn replace_line_keeps_the_rest() {
+        assert_eq!(replace_line("pw\nkey:   spaced  \nuri\n\n", 2, "new"), "pw\nkey:   spaced  \nnew\n\n");
+        assert_eq!(replace_line("pw\nuri", 1, "new"), "pw\nnew");
+        assert_eq!(replace_line("pw", 0, "new"), "new");
+    }
+
+    #[test]
+    fn append_line_keeps_the_final_line_break() {
+        assert_eq!(append_line("pw\nkey:   spaced  \nnotes:", "old-password: pw"), "pw\nkey:   spaced  \nnotes:\nold-password: pw");
+        assert_eq!(append_line("pw\n", "old-password: pw"), "pw\nold-password: pw\n");
+    }
+}
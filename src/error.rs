@@ -0,0 +1,65 @@
+// This file defines the error type returned by the library functions, so callers can tell failures apart
+use std::fmt;
+use std::io;
+use std::path;
+
+#[derive(Debug)]
+pub enum Error {
+    //a file in the repo that can't be parsed as entry name or content. E.g. a file that was not written by sparkpass
+    CorruptEntry(String),
+    //the mac/tag did not match. Either the key is wrong or the data was tampered with
+    WrongKey,
+    Io(io::Error),
+    NonUtf8Name(path::PathBuf),
+    NotFound(String),
+    Exists(String),
+    IsDir(String),
+    //the repo header is malformed
+    Header(String),
+    UnsupportedVersion(u32),
+    //a kdf, cipher or other parameter given by the user that makes no sense
+    InvalidArgument(String),
+    //wrong number of arguments, unknown subcommands and the like
+    Usage(String),
+    //a record of an import that can't be read
+    Import(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::CorruptEntry(msg) => write!(f, "Malformed entry: {}", msg),
+            Error::WrongKey => write!(f, "Wrong master key or corrupted data, the mac did not match"),
+            Error::Io(e) => write!(f, "IO error: {}", e),
+            Error::NonUtf8Name(p) => write!(f, "Conversion error. Not UTF-8? {}", p.display()),
+            Error::NotFound(name) => write!(f, "Entry does not exist: {}", name),
+            Error::Exists(name) => write!(f, "Already exists: {}", name),
+            Error::IsDir(name) => write!(f, "Is dir: {}", name),
+            Error::Header(msg) => write!(f, "Malformed repo header: {}", msg),
+            Error::UnsupportedVersion(v) => write!(
+                f,
+                "Repo has format version {} but this version of sparkpass only understands up to version {}",
+                v,
+                crate::repo::REPO_VERSION
+            ),
+            Error::InvalidArgument(msg) => write!(f, "{}", msg),
+            Error::Usage(msg) => write!(f, "{}", msg),
+            Error::Import(msg) => write!(f, "Import failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+// openssl only fails on internal errors (e.g. no memory), wrong keys are detected by comparing macs and tags
+impl From<openssl::error::ErrorStack> for Error {
+    fn from(e: openssl::error::ErrorStack) -> Error {
+        Error::Io(io::Error::other(e))
+    }
+}
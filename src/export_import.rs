@@ -1,22 +1,38 @@
 use csv;
-use crate::util::{Options, add_entry, get_tree_from_path, show_entry, prepare_entry_path};
+use crate::util::{Options, add_entry, entry_file_path, get_tree_from_path, is_hidden, show_entry, prepare_entry_path, write_file_atomic};
 use crate::transform;
+use crate::error::Error;
+use crate::entry::{Entry, canonical_field};
 use crate::util::TreeNode;
+use crate::kdbx;
 
+extern crate base64;
+extern crate rpassword;
+extern crate serde_json;
 extern crate url;
+extern crate zip;
 use url::Url;
+use std::io::Read;
 use std::str::FromStr;
 
-pub fn cmd_import(opts: &Options, prefix: &std::path::Path ,enc_params: &transform::EncryptionParams)  {
-    match opts.args[0].as_str() {
-        "keepass_csv" => {
-            let p = std::path::Path::new(opts.args[1].as_str());
-            import_from_keepass_csv(prefix, p, enc_params).unwrap();
-        }
-        _ => {
-            println!("Unknown import type: {}", opts.args[0]);
-        }
+pub fn cmd_import(opts: &Options, prefix: &std::path::Path ,enc_params: &transform::EncryptionParams) -> Result<(), Error> {
+    if opts.args.len() != 2 {
+        return Err(Error::Usage(format!("Incorrect number of arguments. Want: 'type, path_to_file'  Got: {}", opts.args.len())));
     }
+
+    let p = std::path::Path::new(opts.args[1].as_str());
+    let plan = match opts.args[0].as_str() {
+        "keepass_csv" => plan_keepass_csv(p)?,
+        "fields_csv" => plan_fields_csv(p)?,
+        "pass" => plan_pass(p)?,
+        "kdbx" => plan_kdbx(opts, p)?,
+        "bitwarden_json" => plan_bitwarden_json(p)?,
+        "1password_1pux" => plan_1password_1pux(p)?,
+        "1password_csv" => plan_1password_csv(p)?,
+        "csv" => plan_generic_csv(opts, p)?,
+        _ => return Err(Error::Usage(format!("Unknown import type: {}", opts.args[0]))),
+    };
+    run_import(opts, prefix, p, plan, enc_params)
 }
 
 pub fn build_entry_list(tree: &TreeNode, prefix: String) -> Vec<String> {
@@ -41,16 +57,15 @@ pub fn build_entry_list(tree: &TreeNode, prefix: String) -> Vec<String> {
         }
     }
 
-    return v;
+    v
 }
 
-pub fn cmd_export(opts: &Options, prefix: &std::path::Path ,enc_params: &transform::EncryptionParams) {
-    if opts.args.len() > 2 {
-        println!("Too many arguments. Want: 'path_to_dir'  Got: {}", opts.args.len());
-        return;
+pub fn cmd_export(opts: &Options, prefix: &std::path::Path ,enc_params: &transform::EncryptionParams) -> Result<(), Error> {
+    if opts.args.len() != 2 {
+        return Err(Error::Usage(format!("Incorrect number of arguments. Want: 'type, path_to_file'  Got: {}", opts.args.len())));
     }
 
-    let tree = get_tree_from_path(prefix, true, enc_params).unwrap();
+    let tree = get_tree_from_path(prefix, None, enc_params)?;
     let renamed_tree = match tree {
         TreeNode::Node(_, children) => {
             TreeNode::Node("".to_owned(), children)
@@ -63,34 +78,1020 @@ pub fn cmd_export(opts: &Options, prefix: &std::path::Path ,enc_params: &transfo
     match opts.args[0].as_str() {
         "csv" => {
             let p = std::path::Path::new(opts.args[1].as_str());
-            export_to_csv(list, prefix, p, enc_params).unwrap();
+            export_to_csv(list, prefix, p, enc_params)
+        }
+        "fields_csv" => {
+            let p = std::path::Path::new(opts.args[1].as_str());
+            export_to_fields_csv(list, prefix, p, enc_params)
         }
         _ => {
-            println!("Unknown import type: {}", opts.args[0]);
+            Err(Error::Usage(format!("Unknown export type: {}", opts.args[0])))
         }
     }
 }
 
-fn export_to_csv(entries: Vec<String>, prefix: &std::path::Path ,p: &std::path::Path, enc_params: &transform::EncryptionParams) -> Result<(), String> {
-    let mut w = csv::Writer::from_path(p).unwrap();
+fn export_to_csv(entries: Vec<String>, prefix: &std::path::Path ,p: &std::path::Path, enc_params: &transform::EncryptionParams) -> Result<(), Error> {
+    let mut w = csv::Writer::from_path(p).map_err(csv_error)?;
 
-    w.write_record(&["name", "content"]).unwrap();
+    w.write_record(["name", "content"]).map_err(csv_error)?;
 
     for e in entries {
         let prep_entry = prepare_entry_path(e.as_str());
-        let content = show_entry(prefix, std::path::Path::new(prep_entry), enc_params).unwrap();
-        w.write_record(&[prep_entry, content.as_str()]).unwrap();
+        let content = show_entry(prefix, std::path::Path::new(prep_entry), enc_params)?;
+        w.write_record([prep_entry, content.as_str()]).map_err(csv_error)?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+// one column per field: name, password, username, url, notes and then the other keys found in the entries
+fn export_to_fields_csv(entries: Vec<String>, prefix: &std::path::Path ,p: &std::path::Path, enc_params: &transform::EncryptionParams) -> Result<(), Error> {
+    let mut parsed = Vec::new();
+    let mut columns: Vec<String> = vec!["password".to_owned(), "username".to_owned(), "url".to_owned(), "notes".to_owned()];
+
+    for e in entries {
+        let prep_entry = prepare_entry_path(e.as_str()).to_owned();
+        let content = show_entry(prefix, std::path::Path::new(prep_entry.as_str()), enc_params)?;
+        let entry = Entry::parse(content.as_str());
+        for (k, _) in entry.fields() {
+            let k = canonical_field(k);
+            if !columns.contains(&k) {
+                columns.push(k);
+            }
+        }
+        parsed.push((prep_entry, entry));
+    }
+
+    let mut w = csv::Writer::from_path(p).map_err(csv_error)?;
+
+    let mut header = vec!["name".to_owned()];
+    header.extend(columns.iter().cloned());
+    w.write_record(&header).map_err(csv_error)?;
+
+    for (name, entry) in parsed {
+        let mut record = vec![name];
+        for c in &columns {
+            record.push(entry.get(c.as_str()).unwrap_or_default());
+        }
+        w.write_record(&record).map_err(csv_error)?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+// reads the format written by the fields_csv export. The name column is the entry path, all other columns become fields
+fn plan_fields_csv(p: &std::path::Path) -> Result<ImportPlan, Error> {
+    let mut r = csv::Reader::from_path(p).map_err(csv_error)?;
+    let headers = r.headers().map_err(csv_error)?.clone();
+
+    let name_idx = match headers.iter().position(|h| h == "name") {
+        Some(i) => i,
+        None => return Err(Error::Import("Missing column: name".to_owned())),
+    };
+
+    let mut plan = ImportPlan::new();
+    for rcrd in r.records() {
+        let record = match csv_record(rcrd, &mut plan)? {
+            Some(r) => r,
+            None => continue,
+        };
+
+        let mut e = Entry::new("");
+        for (i, column) in headers.iter().enumerate() {
+            if i == name_idx {
+                continue;
+            }
+            e.set(column, record.get(i).unwrap_or(""));
+        }
+
+        let name = prepare_entry_path(record.get(name_idx).unwrap_or(""));
+        if name.is_empty() {
+            plan.failed.push(format!("{} (no name)", csv_source(&record)));
+            continue;
+        }
+        plan.add(csv_source(&record), name.to_owned(), e.to_string());
+    }
+    Ok(plan)
+}
+
+// the .gpg files below dir, as paths relative to the store. Other files are reported as failed
+fn collect_pass_files(store: &std::path::Path, dir: &std::path::Path, files: &mut Vec<String>, failed: &mut Vec<String>) -> Result<(), Error> {
+    for entry in std::fs::read_dir(dir)? {
+        let p = entry?.path();
+        //.git, .gpg-id and the like belong to pass, not to the entries
+        if is_hidden(&p) {
+            continue;
+        }
+
+        let relative = match p.strip_prefix(store).ok().and_then(|r| r.to_str()) {
+            Some(r) => r.to_owned(),
+            None => {
+                failed.push(format!("{} (name is not UTF-8)", p.display()));
+                continue;
+            },
+        };
+
+        if p.is_dir() {
+            collect_pass_files(store, &p, files, failed)?;
+        } else if relative.ends_with(".gpg") {
+            files.push(relative);
+        } else {
+            failed.push(format!("{} (not a .gpg file)", relative));
+        }
+    }
+    Ok(())
+}
+
+// decrypts with gpg, or the command in SPARKPASS_DECRYPT_CMD which gets the file as "$1" and prints the content
+fn decrypt_pass_file(p: &std::path::Path) -> Result<String, String> {
+    let cmd = match std::env::var("SPARKPASS_DECRYPT_CMD") {
+        Ok(c) => c,
+        Err(_) => "gpg --quiet --yes --decrypt".to_owned(),
+    };
+
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", cmd))
+        .arg("sh")
+        .arg(p)
+        .stdin(std::process::Stdio::inherit())
+        .output();
+
+    let output = match output {
+        Ok(o) => o,
+        Err(e) => return Err(format!("couldn't run the decrypt command: {}", e)),
+    };
+    if !output.status.success() {
+        return Err(format!("decrypt command failed: {}", String::from_utf8_lossy(&output.stderr).trim().replace("\n", " ")));
+    }
+
+    match String::from_utf8(output.stdout) {
+        Ok(c) => Ok(c),
+        Err(_) => Err("content is not UTF-8".to_owned()),
+    }
+}
+
+// an entry an importer wants to create. The source names the record it comes from, e.g. "line 3" or "Work/Mail"
+struct PlannedEntry {
+    source: String,
+    path: String,
+    content: String,
+}
+
+// what an importer read: the entries in the order they are written and the records it couldn't read
+struct ImportPlan {
+    entries: Vec<PlannedEntry>,
+    failed: Vec<String>,
+}
+
+impl ImportPlan {
+    fn new() -> ImportPlan {
+        ImportPlan { entries: Vec::new(), failed: Vec::new() }
+    }
+
+    fn add(&mut self, source: String, path: String, content: String) {
+        self.entries.push(PlannedEntry { source, path, content });
+    }
+}
+
+// what happens to an entry that exists in the repo or earlier in the same import
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConflictPolicy {
+    Skip,
+    Overwrite,
+    // the entry is stored as "<name> (2)", "<name> (3)", ...
+    Rename,
+    // nothing is imported
+    Abort,
+}
+
+impl ConflictPolicy {
+    // --on-conflict, otherwise overwrite with --force and skip without
+    pub fn from_options(opts: &Options) -> Result<ConflictPolicy, Error> {
+        match opts.on_conflict.as_str() {
+            "" => Ok(if opts.force { ConflictPolicy::Overwrite } else { ConflictPolicy::Skip }),
+            "skip" => Ok(ConflictPolicy::Skip),
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            "rename" => Ok(ConflictPolicy::Rename),
+            "abort" => Ok(ConflictPolicy::Abort),
+            _ => Err(Error::InvalidArgument(format!("Unknown conflict policy: {}. Known are: skip, overwrite, rename, abort", opts.on_conflict))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ImportAction {
+    Create,
+    Overwrite,
+    // with the reason, e.g. "exists"
+    Skip(&'static str),
+}
+
+// the planned entries with what happens to them, and the records that can't be imported
+type ResolvedImport = (Vec<(PlannedEntry, ImportAction)>, Vec<String>);
+
+// how a planned entry path collides with the repo or with the entries planned before it
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Collision {
+    Entry,
+    Dir,
+    // the first index components of the path are an entry, so the path can't be a dir below it
+    Parent(usize),
+}
+
+impl Collision {
+    fn reason(&self) -> &'static str {
+        match self {
+            Collision::Entry => "exists",
+            Collision::Dir => "is a dir",
+            Collision::Parent(_) => "a parent is an entry",
+        }
+    }
+}
+
+// the entries and dirs the repo will have after the planned entries are written
+struct PlannedPaths<'a> {
+    prefix: &'a std::path::Path,
+    enc_params: &'a transform::EncryptionParams<'a>,
+    entries: std::collections::HashSet<String>,
+    dirs: std::collections::HashSet<String>,
+}
+
+impl<'a> PlannedPaths<'a> {
+    fn file_path(&self, path: &str) -> Result<std::path::PathBuf, Error> {
+        entry_file_path(self.prefix, std::path::Path::new(path), self.enc_params)
+    }
+
+    fn collision(&self, components: &[String]) -> Result<Option<Collision>, Error> {
+        for i in 1..components.len() {
+            let parent = components[..i].join("/");
+            if self.entries.contains(&parent) || self.file_path(parent.as_str())?.is_file() {
+                return Ok(Some(Collision::Parent(i)));
+            }
+        }
+
+        let path = components.join("/");
+        let full_path = self.file_path(path.as_str())?;
+        if self.dirs.contains(&path) || full_path.is_dir() {
+            return Ok(Some(Collision::Dir));
+        }
+        if self.entries.contains(&path) || full_path.exists() {
+            return Ok(Some(Collision::Entry));
+        }
+        Ok(None)
+    }
+
+    fn insert(&mut self, path: &str) {
+        let mut parent = path;
+        while let Some(idx) = parent.rfind('/') {
+            parent = &parent[..idx];
+            self.dirs.insert(parent.to_owned());
+        }
+        self.entries.insert(path.to_owned());
+    }
+}
+
+// decides what happens to every planned entry. All conflicts are resolved before the first write, so an abort leaves
+// the repo untouched and a dry run shows what is written. Besides entries with the same path, an entry conflicts with
+// a dir at its path and with an entry at one of its parents. Rename renames the component that conflicts
+fn resolve_conflicts(plan: ImportPlan, policy: ConflictPolicy, prefix: &std::path::Path, enc_params: &transform::EncryptionParams) -> Result<ResolvedImport, Error> {
+    let mut planned = PlannedPaths {
+        prefix,
+        enc_params,
+        entries: std::collections::HashSet::new(),
+        dirs: std::collections::HashSet::new(),
+    };
+
+    let mut res = Vec::new();
+    let mut failed = plan.failed;
+    for mut e in plan.entries {
+        let mut components: Vec<String> = e.path.split('/').map(|c| c.to_owned()).collect();
+        let collision = match planned.collision(&components)? {
+            Some(c) => c,
+            None => {
+                planned.insert(e.path.as_str());
+                res.push((e, ImportAction::Create));
+                continue;
+            },
+        };
+
+        match policy {
+            ConflictPolicy::Skip => res.push((e, ImportAction::Skip(collision.reason()))),
+            ConflictPolicy::Overwrite => {
+                //only an entry can be overwritten, not a dir and not the entry at a parent
+                if collision != Collision::Entry {
+                    failed.push(format!("{} -> {} ({})", e.source, e.path, collision.reason()));
+                    continue;
+                }
+                planned.insert(e.path.as_str());
+                res.push((e, ImportAction::Overwrite));
+            },
+            ConflictPolicy::Rename => {
+                let idx = match collision {
+                    Collision::Parent(i) => i - 1,
+                    _ => components.len() - 1,
+                };
+                let base = components[idx].clone();
+                let mut n = 2;
+                loop {
+                    components[idx] = format!("{} ({})", base, n);
+                    if planned.collision(&components)?.is_none() {
+                        break;
+                    }
+                    n += 1;
+                }
+                e.path = components.join("/");
+                planned.insert(e.path.as_str());
+                res.push((e, ImportAction::Create));
+            },
+            ConflictPolicy::Abort => {
+                return Err(Error::Exists(format!("{} ({}, import aborted, nothing was written)", e.path, collision.reason())));
+            },
+        }
+    }
+    Ok((res, failed))
+}
+
+// how to undo one change of a running import
+enum ImportUndo {
+    RemoveFile(std::path::PathBuf),
+    // a dir the import created, with everything in it
+    RemoveDir(std::path::PathBuf),
+    Restore(std::path::PathBuf, Vec<u8>),
+}
+
+fn write_planned_entry(prefix: &std::path::Path, e: &PlannedEntry, action: ImportAction, enc_params: &transform::EncryptionParams, journal: &mut Vec<ImportUndo>) -> Result<(), Error> {
+    let full_path = entry_file_path(prefix, std::path::Path::new(e.path.as_str()), enc_params)?;
+    if action == ImportAction::Overwrite {
+        journal.push(ImportUndo::Restore(full_path.clone(), std::fs::read(&full_path)?));
+    } else {
+        //only the top most of the dirs created for the entry has to be removed
+        let mut created_dir = None;
+        let mut dir = full_path.parent();
+        while let Some(d) = dir {
+            if d.exists() {
+                break;
+            }
+            created_dir = Some(d.to_path_buf());
+            dir = d.parent();
+        }
+        if let Some(d) = created_dir {
+            journal.push(ImportUndo::RemoveDir(d));
+        }
+    }
+    add_entry(prefix, std::path::Path::new(e.path.as_str()), e.content.as_str(), action == ImportAction::Overwrite, enc_params)?;
+    if action == ImportAction::Create {
+        journal.push(ImportUndo::RemoveFile(full_path));
+    }
+    Ok(())
+}
+
+fn rollback_import(journal: Vec<ImportUndo>) {
+    for undo in journal.into_iter().rev() {
+        let (p, res) = match undo {
+            ImportUndo::RemoveFile(p) => {
+                let res = std::fs::remove_file(&p).map_err(Error::Io);
+                (p, res)
+            },
+            ImportUndo::RemoveDir(p) => {
+                let res = std::fs::remove_dir_all(&p).map_err(Error::Io);
+                (p, res)
+            },
+            ImportUndo::Restore(p, content) => {
+                let res = write_file_atomic(&p, &content);
+                (p, res)
+            },
+        };
+        match res {
+            Ok(_) => {},
+            //the write that failed may not have created its dirs
+            Err(Error::Io(ref e)) if e.kind() == std::io::ErrorKind::NotFound => {},
+            Err(e) => eprintln!("Rolling back {} failed: {}", p.display(), e),
+        }
+    }
+}
+
+fn print_import_records(title: &str, records: &[String]) {
+    if !records.is_empty() {
+        eprintln!("{} {} records:", title, records.len());
+        for r in records {
+            eprintln!("  {}", r);
+        }
+    }
+}
+
+// writes the planned entries with add_entry, conflicts are handled by --on-conflict. With --dry-run only the mapping of
+// records to entry paths is printed. If a write fails, everything the import wrote before is rolled back
+fn run_import(opts: &Options, prefix: &std::path::Path, source: &std::path::Path, plan: ImportPlan, enc_params: &transform::EncryptionParams) -> Result<(), Error> {
+    let policy = ConflictPolicy::from_options(opts)?;
+    let (actions, failed) = resolve_conflicts(plan, policy, prefix, enc_params)?;
+
+    let mut created = 0;
+    let mut overwritten = 0;
+    let mut skipped = Vec::new();
+    let mut journal = Vec::new();
+    for (e, action) in &actions {
+        match action {
+            ImportAction::Skip(reason) => {
+                skipped.push(format!("{} -> {} ({})", e.source, e.path, reason));
+                continue;
+            },
+            ImportAction::Create => created += 1,
+            ImportAction::Overwrite => overwritten += 1,
+        }
+
+        if opts.dry_run {
+            let note = if *action == ImportAction::Overwrite { " (overwrite)" } else { "" };
+            println!("{} -> {}{}", e.source, e.path, note);
+            continue;
+        }
+        if let Err(err) = write_planned_entry(prefix, e, *action, enc_params, &mut journal) {
+            rollback_import(journal);
+            eprintln!("Writing {} failed, the import was rolled back", e.path);
+            return Err(err);
+        }
+        if opts.verbose {println!("Imported: {} -> {}", e.source, e.path);}
+    }
+
+    let summary = format!("{} created, {} overwritten, {} skipped, {} failed", created, overwritten, skipped.len(), failed.len());
+    if opts.dry_run {
+        println!("Dry run of {}, nothing was written: {}", source.display(), summary);
+    } else {
+        println!("Imported {}: {}", source.display(), summary);
+    }
+    print_import_records("Skipped", &skipped);
+    print_import_records("Failed", &failed);
+    Ok(())
+}
+
+// the records of a csv file that can't be read are failed, only io errors end the import
+fn csv_record(rcrd: Result<csv::StringRecord, csv::Error>, plan: &mut ImportPlan) -> Result<Option<csv::StringRecord>, Error> {
+    match rcrd {
+        Ok(r) => Ok(Some(r)),
+        Err(e) => {
+            if e.is_io_error() {
+                return Err(csv_error(e));
+            }
+            plan.failed.push(e.to_string());
+            Ok(None)
+        },
+    }
+}
+
+fn csv_source(record: &csv::StringRecord) -> String {
+    match record.position() {
+        Some(pos) => format!("line {}", pos.line()),
+        None => "record".to_owned(),
+    }
+}
+
+fn read_import_file(p: &std::path::Path) -> Result<Vec<u8>, Error> {
+    match std::fs::read(p) {
+        Ok(d) => Ok(d),
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Err(Error::NotFound(format!("import file {}", p.display()))),
+        Err(e) => Err(Error::Io(e)),
+    }
+}
+
+// group, folder and entry names become path components, so they can't contain '/' or start with '.' (hidden files are not listed)
+fn import_path_component(name: &str, fallback: &str) -> String {
+    let name = name.trim().replace("/", "_");
+    if name.is_empty() {
+        return fallback.to_owned();
+    }
+    if let Some(rest) = name.strip_prefix('.') {
+        return format!("_{}", rest);
+    }
+    name
+}
+
+// password managers allow several entries with the same title in a folder, the later ones get a " (2)", " (3)", ... suffix
+fn unique_import_path(used: &mut std::collections::HashSet<String>, dir: &str, title: &str) -> String {
+    let title = import_path_component(title, "untitled");
+    let base = if dir.is_empty() { title } else { format!("{}/{}", dir, title) };
+    let mut name = base.clone();
+    let mut n = 2;
+    while used.contains(&name) {
+        name = format!("{} ({})", base, n);
+        n += 1;
+    }
+    used.insert(name.clone());
+    name
+}
+
+// custom fields of the password managers. Their names may contain spaces and colons which would break the "key: value"
+// lines, multi line values can't be fields at all and are appended to the notes
+fn add_custom_field(entry: &mut Entry, notes: &mut String, key: &str, value: &str) {
+    if value.is_empty() {
+        return;
+    }
+    if value.contains("\n") {
+        if !notes.is_empty() {
+            notes.push('\n');
+        }
+        notes.push_str(format!("{}:\n{}", key, value).as_str());
+        return;
+    }
+    let key: String = key.trim().chars().map(|c| if c.is_whitespace() || c == ':' { '_' } else { c }).collect();
+    let key = if key.is_empty() { "field".to_owned() } else { key };
+    entry.add(key.as_str(), value);
+}
+
+// imports a password store with its dir hierarchy below pass_import/. The whole content is kept, including all lines
+fn plan_pass(store: &std::path::Path) -> Result<ImportPlan, Error> {
+    if !store.is_dir() {
+        return Err(Error::NotFound(format!("password store {}", store.display())));
+    }
+
+    let mut files = Vec::new();
+    let mut plan = ImportPlan::new();
+    collect_pass_files(store, store, &mut files, &mut plan.failed)?;
+    files.sort();
+
+    for file in files {
+        let name = &file[..file.len() - ".gpg".len()];
+        let mut content = match decrypt_pass_file(&store.join(file.as_str())) {
+            Ok(c) => c,
+            Err(e) => {
+                plan.failed.push(format!("{} ({})", file, e));
+                continue;
+            },
+        };
+        //pass ends the content with a newline, sparkpass entries don't
+        if content.ends_with("\n") {
+            content.pop();
+        }
+        plan.add(file.clone(), format!("pass_import/{}", name), content);
+    }
+    Ok(plan)
+}
+
+// the password for the database from SPARKPASS_IMPORT_PASSWORD or the terminal
+fn kdbx_password(opts: &Options, p: &std::path::Path) -> Result<String, Error> {
+    if let Ok(pw) = std::env::var("SPARKPASS_IMPORT_PASSWORD") {
+        return Ok(pw);
+    }
+    if !opts.interactive {
+        return Err(Error::Usage("No database password given and interactive mode deactivated".to_owned()));
+    }
+    println!("Enter password for {}: ", p.display());
+    Ok(rpassword::read_password()?)
+}
+
+// turns a KeePass entry into a sparkpass entry. Standard strings become the usual fields, custom strings become
+// fields too, except multi line ones which are appended to the notes
+fn kdbx_to_entry(e: &kdbx::KdbxEntry) -> Entry {
+    let mut entry = Entry::new(e.get("Password").unwrap_or(""));
+    entry.set("username", e.get("UserName").unwrap_or(""));
+    entry.set("url", e.get("URL").unwrap_or(""));
+
+    let mut notes = e.get("Notes").unwrap_or("").to_owned();
+    for (k, v) in &e.strings {
+        match k.as_str() {
+            "Title" | "Password" | "UserName" | "URL" | "Notes" => continue,
+            _ => add_custom_field(&mut entry, &mut notes, k, v),
+        }
+    }
+    for (name, _) in &e.attachments {
+        entry.add("attachment", name);
+    }
+    entry.set("notes", notes.as_str());
+    entry
+}
+
+// imports a KeePass 2 database below kdbx_import/, groups become dirs. Attachments are stored as entries in
+// "<entry>-attachments/", as text if they are UTF-8 and prefixed with "base64:" otherwise
+fn plan_kdbx(opts: &Options, p: &std::path::Path) -> Result<ImportPlan, Error> {
+    let data = read_import_file(p)?;
+    let password = kdbx_password(opts, p)?;
+    let entries = kdbx::read_database(&data, password.as_str())?;
+
+    let mut used = std::collections::HashSet::new();
+    let mut plan = ImportPlan::new();
+    for e in entries {
+        let mut source: Vec<&str> = e.groups.iter().map(|g| g.as_str()).collect();
+        source.push(e.get("Title").unwrap_or(""));
+        let source = source.join("/");
+
+        let mut dir = "kdbx_import".to_owned();
+        for g in &e.groups {
+            dir.push('/');
+            dir.push_str(import_path_component(g, "unnamed").as_str());
+        }
+
+        let name = unique_import_path(&mut used, dir.as_str(), e.get("Title").unwrap_or(""));
+        plan.add(source.clone(), name.clone(), kdbx_to_entry(&e).to_string());
+        for (file, data) in &e.attachments {
+            let content = match String::from_utf8(data.clone()) {
+                Ok(text) => text,
+                Err(_) => format!("base64:{}", base64::encode(data)),
+            };
+            plan.add(format!("{} attachment {}", source, file), format!("{}-attachments/{}", name, import_path_component(file, "unnamed")), content);
+        }
     }
-    return Ok(());
+    Ok(plan)
 }
 
-fn import_from_keepass_csv(prefix: &std::path::Path, p: &std::path::Path, enc_params: &transform::EncryptionParams) -> Result<(), String> {
-   let mut r = csv::Reader::from_path(p).unwrap();
-   for rcrd in r.records() {
-        let record = rcrd.unwrap();
+fn json_str<'a>(v: &'a serde_json::Value, key: &str) -> &'a str {
+    v.get(key).and_then(|x| x.as_str()).unwrap_or("")
+}
+
+fn json_array<'a>(v: &'a serde_json::Value, key: &str) -> &'a [serde_json::Value] {
+    match v.get(key).and_then(|x| x.as_array()) {
+        Some(a) => a.as_slice(),
+        None => &[],
+    }
+}
+
+fn parse_import_json(data: &[u8], p: &std::path::Path) -> Result<serde_json::Value, Error> {
+    match serde_json::from_slice(data) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(Error::Import(format!("Invalid JSON in {}: {}", p.display(), e))),
+    }
+}
+
+// the unencrypted JSON export of Bitwarden. Folders become dirs below bitwarden_import/, nested folders are written as
+// "parent/child" by Bitwarden and become nested dirs
+fn plan_bitwarden_json(p: &std::path::Path) -> Result<ImportPlan, Error> {
+    let doc = parse_import_json(&read_import_file(p)?, p)?;
+    if doc.get("encrypted").and_then(|e| e.as_bool()) == Some(true) {
+        return Err(Error::Import("The Bitwarden export is encrypted, export it as unencrypted JSON".to_owned()));
+    }
+    let items = match doc.get("items").and_then(|i| i.as_array()) {
+        Some(i) => i,
+        None => return Err(Error::Import("No items in the Bitwarden export".to_owned())),
+    };
+
+    let mut folders = std::collections::HashMap::new();
+    for f in json_array(&doc, "folders") {
+        folders.insert(json_str(f, "id"), json_str(f, "name"));
+    }
+
+    let mut used = std::collections::HashSet::new();
+    let mut plan = ImportPlan::new();
+    for item in items {
+        let mut dir = "bitwarden_import".to_owned();
+        if let Some(folder) = folders.get(json_str(item, "folderId")) {
+            for part in folder.split("/") {
+                dir.push('/');
+                dir.push_str(import_path_component(part, "unnamed").as_str());
+            }
+        }
+
+        let mut entry = Entry::new("");
+        let mut notes = json_str(item, "notes").to_owned();
+        //1 login, 2 secure note, 3 card, 4 identity
+        match item.get("type").and_then(|t| t.as_u64()) {
+            Some(1) => {
+                let login = &item["login"];
+                entry.password = json_str(login, "password").to_owned();
+                entry.set("username", json_str(login, "username"));
+                for (i, uri) in json_array(login, "uris").iter().enumerate() {
+                    if i == 0 {
+                        entry.set("url", json_str(uri, "uri"));
+                    } else {
+                        add_custom_field(&mut entry, &mut notes, "url", json_str(uri, "uri"));
+                    }
+                }
+                entry.set("totp", json_str(login, "totp"));
+            },
+            Some(3) => {
+                let card = &item["card"];
+                entry.password = json_str(card, "code").to_owned();
+                entry.set("cardholder", json_str(card, "cardholderName"));
+                entry.set("brand", json_str(card, "brand"));
+                entry.set("number", json_str(card, "number"));
+                let (month, year) = (json_str(card, "expMonth"), json_str(card, "expYear"));
+                if !month.is_empty() || !year.is_empty() {
+                    entry.set("expiry", format!("{}/{}", month, year).as_str());
+                }
+            },
+            Some(4) => {
+                if let Some(identity) = item["identity"].as_object() {
+                    for (k, v) in identity {
+                        add_custom_field(&mut entry, &mut notes, k, v.as_str().unwrap_or(""));
+                    }
+                }
+            },
+            _ => {},
+        }
+
+        for field in json_array(item, "fields") {
+            let value = match &field["value"] {
+                serde_json::Value::String(s) => s.clone(),
+                serde_json::Value::Null => String::new(),
+                v => v.to_string(),
+            };
+            add_custom_field(&mut entry, &mut notes, json_str(field, "name"), value.as_str());
+        }
+        entry.set("notes", notes.as_str());
+
+        let source = match folders.get(json_str(item, "folderId")) {
+            Some(folder) => format!("{}/{}", folder, json_str(item, "name")),
+            None => json_str(item, "name").to_owned(),
+        };
+        plan.add(source, unique_import_path(&mut used, dir.as_str(), json_str(item, "name")), entry.to_string());
+    }
+    Ok(plan)
+}
+
+// the value of a 1PUX section field is an object with the type as only key, e.g. {"concealed": "..."} or {"totp": "otpauth://..."}
+fn onepux_value(v: &serde_json::Value) -> Option<(String, String)> {
+    let (kind, inner) = v.as_object()?.iter().next()?;
+    let value = match inner {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Number(n) => n.to_string(),
+        serde_json::Value::Bool(b) => b.to_string(),
+        //emails and addresses are objects of strings
+        serde_json::Value::Object(o) => o.values().filter_map(|x| x.as_str()).filter(|x| !x.is_empty()).collect::<Vec<&str>>().join(", "),
+        _ => return None,
+    };
+    if value.is_empty() {
+        return None;
+    }
+    Some((kind.clone(), value))
+}
+
+// the 1PUX export of 1Password 8, a zip file with the items in export.data. Vaults become dirs below 1password_import/
+fn plan_1password_1pux(p: &std::path::Path) -> Result<ImportPlan, Error> {
+    let file = match std::fs::File::open(p) {
+        Ok(f) => f,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Err(Error::NotFound(format!("import file {}", p.display()))),
+        Err(e) => return Err(Error::Io(e)),
+    };
+    let mut archive = match zip::ZipArchive::new(file) {
+        Ok(a) => a,
+        Err(e) => return Err(Error::Import(format!("Not a 1PUX file {}: {}", p.display(), e))),
+    };
+    let mut data = Vec::new();
+    match archive.by_name("export.data") {
+        Ok(mut f) => {f.read_to_end(&mut data)?;},
+        Err(e) => return Err(Error::Import(format!("No export.data in {}: {}", p.display(), e))),
+    }
+    let doc = parse_import_json(&data, p)?;
+
+    let mut used = std::collections::HashSet::new();
+    let mut plan = ImportPlan::new();
+    for account in json_array(&doc, "accounts") {
+        for vault in json_array(account, "vaults") {
+            let dir = format!("1password_import/{}", import_path_component(json_str(&vault["attrs"], "name"), "unnamed"));
+            for item in json_array(vault, "items") {
+                let details = &item["details"];
+                let overview = &item["overview"];
+
+                let mut entry = Entry::new(json_str(details, "password"));
+                let mut notes = json_str(details, "notesPlain").to_owned();
+                for field in json_array(details, "loginFields") {
+                    let value = json_str(field, "value");
+                    match json_str(field, "designation") {
+                        "password" => entry.password = value.to_owned(),
+                        "username" => entry.set("username", value),
+                        //other form fields, checkboxes and buttons are left out
+                        _ => if ["T", "E", "P", "U"].contains(&json_str(field, "fieldType")) {
+                            add_custom_field(&mut entry, &mut notes, json_str(field, "name"), value);
+                        },
+                    }
+                }
+
+                let url = json_str(overview, "url");
+                entry.set("url", url);
+                for u in json_array(overview, "urls") {
+                    if json_str(u, "url") != url {
+                        add_custom_field(&mut entry, &mut notes, "url", json_str(u, "url"));
+                    }
+                }
+
+                for section in json_array(details, "sections") {
+                    for field in json_array(section, "fields") {
+                        let (kind, value) = match onepux_value(&field["value"]) {
+                            Some(v) => v,
+                            None => continue,
+                        };
+                        if kind == "totp" {
+                            entry.set("totp", value.as_str());
+                            continue;
+                        }
+                        let title = match json_str(field, "title") {
+                            "" => json_str(field, "id"),
+                            t => t,
+                        };
+                        add_custom_field(&mut entry, &mut notes, title, value.as_str());
+                    }
+                }
+                entry.set("notes", notes.as_str());
+
+                let source = format!("{}/{}", json_str(&vault["attrs"], "name"), json_str(overview, "title"));
+                plan.add(source, unique_import_path(&mut used, dir.as_str(), json_str(overview, "title")), entry.to_string());
+            }
+        }
+    }
+    Ok(plan)
+}
+
+// the CSV export of 1Password. The columns differ between versions, so they are found by their header. Columns that
+// are no standard field become custom fields. An optional vault column becomes the dir below 1password_import/
+fn plan_1password_csv(p: &std::path::Path) -> Result<ImportPlan, Error> {
+    let mut r = csv::Reader::from_path(p).map_err(csv_error)?;
+    let headers: Vec<String> = r.headers().map_err(csv_error)?.iter().map(|h| h.trim().to_owned()).collect();
+
+    let mut used = std::collections::HashSet::new();
+    let mut plan = ImportPlan::new();
+    for rcrd in r.records() {
+        let record = match csv_record(rcrd, &mut plan)? {
+            Some(r) => r,
+            None => continue,
+        };
+
+        let mut entry = Entry::new("");
+        let mut notes = String::new();
+        let mut title = "";
+        let mut dir = "1password_import".to_owned();
+        for (i, column) in headers.iter().enumerate() {
+            let value = record.get(i).unwrap_or("");
+            match column.to_lowercase().as_str() {
+                "title" => title = value,
+                "password" | "login password" => entry.password = value.to_owned(),
+                "username" | "login username" => entry.set("username", value),
+                "url" | "website" | "login url" => entry.set("url", value),
+                "otpauth" | "one-time password" => entry.set("totp", value),
+                "notes" | "notesplain" => notes = value.to_owned(),
+                "vault" => if !value.is_empty() {
+                    dir = format!("1password_import/{}", import_path_component(value, "unnamed"));
+                },
+                "favorite" | "archived" | "uuid" => {},
+                _ => add_custom_field(&mut entry, &mut notes, column, value),
+            }
+        }
+        entry.set("notes", notes.as_str());
+
+        plan.add(csv_source(&record), unique_import_path(&mut used, dir.as_str(), title), entry.to_string());
+    }
+    Ok(plan)
+}
+
+// the header names the generic csv import recognizes without --map, for the fields that are not stored as custom fields
+static CSV_COLUMN_ALIASES: &[(&str, &[&str])] = &[
+    ("title", &["title", "name", "account", "entry"]),
+    ("password", &["password", "passwd", "pass", "login_password", "login password"]),
+    ("username", &["username", "user", "login", "login_username", "login username"]),
+    ("url", &["url", "website", "uri", "login_uri", "login url", "web site"]),
+    ("notes", &["notes", "note", "comment", "comments", "extra"]),
+    ("group", &["group", "folder", "grouping", "vault"]),
+];
+
+pub static DEFAULT_PATH_TEMPLATE: &str = "csv_import/{group}/{domain}/{title}";
+
+// target field and column index for every used column. With --map only the mapped columns are used, otherwise the
+// columns are recognized by their header and all others become custom fields
+fn csv_column_mapping(map: &str, headers: &[String]) -> Result<Vec<(String, usize)>, Error> {
+    let mut res = Vec::new();
+    if !map.is_empty() {
+        for item in map.split(",").filter(|i| !i.trim().is_empty()) {
+            let (field, column) = match item.find("=") {
+                Some(idx) => (item[..idx].trim(), item[idx + 1..].trim()),
+                None => return Err(Error::InvalidArgument(format!("Invalid column mapping, want 'field=Column': {}", item))),
+            };
+            let idx = match headers.iter().position(|h| h == column) {
+                Some(i) => i,
+                None => return Err(Error::Import(format!("Missing column: {}", column))),
+            };
+            res.push((canonical_field(field), idx));
+        }
+        return Ok(res);
+    }
+
+    for (field, aliases) in CSV_COLUMN_ALIASES {
+        if let Some(idx) = headers.iter().position(|h| aliases.contains(&h.to_lowercase().as_str())) {
+            res.push((field.to_string(), idx));
+        }
+    }
+    for (idx, h) in headers.iter().enumerate() {
+        if !res.iter().any(|(_, i)| *i == idx) {
+            res.push((h.clone(), idx));
+        }
+    }
+    Ok(res)
+}
+
+fn csv_delimiter(delimiter: &str) -> Result<u8, Error> {
+    match delimiter {
+        "tab" | "\\t" => Ok(b'\t'),
+        d if d.len() == 1 => Ok(d.as_bytes()[0]),
+        _ => Err(Error::InvalidArgument(format!("The delimiter has to be a single character or 'tab': {}", delimiter))),
+    }
+}
+
+fn url_domain(url: &str) -> String {
+    match Url::from_str(url) {
+        Ok(u) => u.domain().unwrap_or("").to_owned(),
+        Err(_) => String::new(),
+    }
+}
+
+// fills the path template with the field values of a record, "domain" is the host of the url. The group may be a path
+// itself. Dirs that come out empty are left out, so a record without group ends up one dir higher
+fn render_path_template(template: &str, values: &std::collections::HashMap<String, String>) -> Result<(String, String), Error> {
+    let mut components = Vec::new();
+    for segment in template.split("/") {
+        let mut rendered = String::new();
+        let mut rest = segment;
+        while let Some(start) = rest.find("{") {
+            rendered.push_str(&rest[..start]);
+            let end = match rest[start..].find("}") {
+                Some(e) => start + e,
+                None => return Err(Error::InvalidArgument(format!("Unclosed placeholder in path template: {}", template))),
+            };
+            let name = rest[start + 1..end].to_lowercase();
+            let value = match values.get(&name) {
+                Some(v) => v,
+                None => return Err(Error::InvalidArgument(format!("Unknown placeholder {{{}}} in path template, the record has no such field", name))),
+            };
+            if name == "group" {
+                let parts: Vec<String> = value.split("/").map(|p| import_path_component(p, "")).filter(|p| !p.is_empty()).collect();
+                rendered.push_str(parts.join("/").as_str());
+            } else {
+                rendered.push_str(import_path_component(value, "").as_str());
+            }
+            rest = &rest[end + 1..];
+        }
+        rendered.push_str(rest);
+        components.push(rendered);
+    }
+
+    let title = components.pop().unwrap_or_default();
+    let dir: Vec<String> = components.into_iter().filter(|c| !c.is_empty()).collect();
+    Ok((dir.join("/"), title))
+}
+
+// csv files of any origin: the columns are mapped to fields by their header or by --map, the entry path comes from
+// the --path-template. Records may have fewer or more columns than the header
+fn plan_generic_csv(opts: &Options, p: &std::path::Path) -> Result<ImportPlan, Error> {
+    let template = if !opts.path_template.is_empty() { opts.path_template.as_str() } else { DEFAULT_PATH_TEMPLATE };
+    let mut r = csv::ReaderBuilder::new()
+        .delimiter(csv_delimiter(opts.delimiter.as_str())?)
+        .flexible(true)
+        .from_path(p)
+        .map_err(csv_error)?;
+    let headers: Vec<String> = r.headers().map_err(csv_error)?.iter().map(|h| h.trim().to_owned()).collect();
+    let mapping = csv_column_mapping(opts.csv_map.as_str(), &headers)?;
+
+    let mut used = std::collections::HashSet::new();
+    let mut plan = ImportPlan::new();
+    for rcrd in r.records() {
+        let record = match csv_record(rcrd, &mut plan)? {
+            Some(r) => r,
+            None => continue,
+        };
+
+        let mut values = std::collections::HashMap::new();
+        for field in &["title", "group", "password", "username", "url", "notes"] {
+            values.insert(field.to_string(), String::new());
+        }
+        let mut entry = Entry::new("");
+        let mut notes = String::new();
+        for (field, idx) in &mapping {
+            let value = record.get(*idx).unwrap_or("").to_owned();
+            match field.as_str() {
+                "password" => entry.password = value.clone(),
+                "username" | "url" => entry.set(field, value.as_str()),
+                "notes" => notes = value.clone(),
+                "title" | "group" => {},
+                _ => add_custom_field(&mut entry, &mut notes, field, value.as_str()),
+            }
+            values.insert(field.to_lowercase(), value);
+        }
+        entry.set("notes", notes.as_str());
+        let domain = url_domain(values["url"].as_str());
+        values.insert("domain".to_owned(), domain);
+
+        let (dir, title) = render_path_template(template, &values)?;
+        plan.add(csv_source(&record), unique_import_path(&mut used, dir.as_str(), title.as_str()), entry.to_string());
+    }
+    Ok(plan)
+}
+
+fn csv_error(e: csv::Error) -> Error {
+    if !e.is_io_error() {
+        return Error::Import(e.to_string());
+    }
+    match e.into_kind() {
+        csv::ErrorKind::Io(e) => Error::Io(e),
+        kind => Error::Import(format!("{:?}", kind)),
+    }
+}
+
+// the csv export of KeePass with the columns account, login name, password, web site, comments
+fn plan_keepass_csv(p: &std::path::Path) -> Result<ImportPlan, Error> {
+    let mut r = csv::ReaderBuilder::new().flexible(true).from_path(p).map_err(csv_error)?;
+    let mut plan = ImportPlan::new();
+    for rcrd in r.records() {
+        let record = match csv_record(rcrd, &mut plan)? {
+            Some(r) => r,
+            None => continue,
+        };
         if record.len() != 5 {
-            println!("Malformed entry: {:?}", record);
-            return Err("Malformed entry".to_owned());
+            plan.failed.push(format!("{} (needs 5 columns, has {})", csv_source(&record), record.len()));
+            continue;
         }
 
         let acc = &record[0];
@@ -99,20 +1100,14 @@ fn import_from_keepass_csv(prefix: &std::path::Path, p: &std::path::Path, enc_pa
         let url = &record[3];
         let comment = &record[4];
 
-        let mut content = String::new();
-        content.push_str(passwd);
-        content.push('\n');
-        content.push_str(name);
-        content.push('\n');
-        content.push_str(url);
-        content.push('\n');
-        content.push_str(acc);
-        content.push('\n');
-        content.push_str(comment);
-        content.push('\n');
+        let mut e = Entry::new(passwd);
+        e.set("username", name);
+        e.set("url", url);
+        e.set("notes", comment);
+        let content = e.to_string();
 
         let mut entry = "keepass_import/".to_owned();
-        if url != "" {
+        if !url.is_empty() {
             match Url::from_str(url) {
                 Err(_) => {
                     entry.push_str(url);
@@ -133,10 +1128,379 @@ fn import_from_keepass_csv(prefix: &std::path::Path, p: &std::path::Path, enc_pa
         }
         entry.push_str(acc);
 
-        match add_entry(prefix, std::path::Path::new(entry.as_str()), content.as_str(), false, enc_params) {
-            Ok(_) => {},
-            Err(e) => return Err(e),
+        plan.add(csv_source(&record), entry, content);
+    }
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{add, enc_params, entries, expect, TempDir};
+
+    fn plan(paths: &[&str]) -> ImportPlan {
+        let mut plan = ImportPlan::new();
+        for (i, p) in paths.iter().enumerate() {
+            plan.add(format!("record {}", i + 1), p.to_string(), format!("new {}", p));
+        }
+        plan
+    }
+
+    fn resolve(repo: &TempDir, paths: &[&str], policy: ConflictPolicy) -> (Vec<(String, ImportAction)>, Vec<String>) {
+        let (actions, failed) = resolve_conflicts(plan(paths), policy, repo.path(), &enc_params()).unwrap();
+        (actions.into_iter().map(|(e, a)| (e.path, a)).collect(), failed)
+    }
+
+    fn import(repo: &TempDir, paths: &[&str], policy: &str, dry_run: bool) -> Result<(), Error> {
+        let opts = Options { on_conflict: policy.to_owned(), dry_run, ..Default::default() };
+        run_import(&opts, repo.path(), std::path::Path::new("test"), plan(paths), &enc_params())
+    }
+
+    fn existing_repo(name: &str) -> TempDir {
+        let repo = TempDir::new(name);
+        add(repo.path(), "a", "old a");
+        add(repo.path(), "d/e", "old e");
+        repo
+    }
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/import").join(name)
+    }
+
+    fn planned(plan: ImportPlan) -> Vec<(String, String, String)> {
+        assert!(plan.failed.is_empty(), "{:?}", plan.failed);
+        plan.entries.into_iter().map(|e| (e.source, e.path, e.content)).collect()
+    }
+
+    fn expect_planned(entries: &[(&str, &str, &str)]) -> Vec<(String, String, String)> {
+        entries.iter().map(|(s, p, c)| (s.to_string(), p.to_string(), c.to_string())).collect()
+    }
+
+    #[test]
+    fn bitwarden_json_mapping() {
+        let entries = planned(plan_bitwarden_json(&fixture("bitwarden.json")).unwrap());
+        assert_eq!(entries, expect_planned(&[
+            ("Work/Mail/Exchange", "bitwarden_import/Work/Mail/Exchange", "pw1\n\
+                username: bob\n\
+                url: https://mail.example.com\n\
+                url: https://owa.example.com\n\
+                totp: otpauth://totp/Work:bob?secret=JBSWY3DPEHPK3PXP\n\
+                Recovery_Code: ABC-123\n\
+                pin__old: 1234\n\
+                2fa: true\n\
+                first note line\n\
+                second note line\n\
+                backup codes:\n\
+                111\n\
+                222"),
+            ("Social/Forum", "bitwarden_import/Social/Forum", "pw2\nusername: bob"),
+            ("Social/Forum", "bitwarden_import/Social/Forum (2)", "pw3\nusername: alice"),
+            ("Visa", "bitwarden_import/Visa", "123\ncardholder: Bob Example\nbrand: Visa\nnumber: 4111111111111111\nexpiry: 7/2030"),
+            ("Me", "bitwarden_import/Me", "\nfirstName: Bob\nlastName: Example"),
+            (".hidden/note", "bitwarden_import/_hidden_note", "\njust a note"),
+        ]));
+
+        let entry = Entry::parse(entries[0].2.as_str());
+        assert_eq!(entry.password, "pw1");
+        assert_eq!(entry.get("user").as_deref(), Some("bob"));
+        assert_eq!(entry.notes(), "first note line\nsecond note line\nbackup codes:\n111\n222");
+    }
+
+    #[test]
+    fn bitwarden_encrypted_export() {
+        let dir = TempDir::new("bitwarden-encrypted");
+        let p = dir.path().join("export.json");
+        std::fs::write(&p, r#"{"encrypted": true, "items": []}"#).unwrap();
+        assert!(plan_bitwarden_json(&p).is_err());
+        std::fs::write(&p, "not json").unwrap();
+        assert!(plan_bitwarden_json(&p).is_err());
+    }
+
+    #[test]
+    fn onepassword_1pux_mapping() {
+        let entries = planned(plan_1password_1pux(&fixture("1password.1pux")).unwrap());
+        assert_eq!(entries, expect_planned(&[
+            ("Private/Exchange", "1password_import/Private/Exchange", "pw1\n\
+                username: bob\n\
+                region: EU\n\
+                url: https://mail.example.com\n\
+                url: https://owa.example.com\n\
+                totp: otpauth://totp/Work:bob?secret=JBSWY3DPEHPK3PXP\n\
+                Recovery_Code: ABC-123\n\
+                pin: 1234\n\
+                support: help@example.com\n\
+                first note line\n\
+                second note line\n\
+                backup codes:\n\
+                111\n\
+                222"),
+            ("Private/Forum", "1password_import/Private/Forum", "pw2"),
+            ("Private/Forum", "1password_import/Private/Forum (2)", "pw3"),
+            ("Shared/Home/Wi-Fi", "1password_import/Shared_Home/Wi-Fi", "wifi pw\nnetwork_name: home\nport: 8080"),
+        ]));
+        assert!(plan_1password_1pux(&fixture("bitwarden.json")).is_err());
+    }
+
+    #[test]
+    fn onepassword_csv_mapping() {
+        let entries = planned(plan_1password_csv(&fixture("1password.csv")).unwrap());
+        assert_eq!(entries, expect_planned(&[
+            ("line 2", "1password_import/Work/Exchange", "pw1\n\
+                url: https://mail.example.com\n\
+                username: bob\n\
+                totp: otpauth://totp/Work:bob?secret=JBSWY3DPEHPK3PXP\n\
+                Tags: work\n\
+                first note line\n\
+                second note line"),
+            ("line 4", "1password_import/Personal/Forum", "pw2\nurl: https://forum.example.com\nusername: bob"),
+            ("line 5", "1password_import/Personal/Forum (2)", "pw3\nusername: alice"),
+            ("line 6", "1password_import/No Vault", "pw4"),
+        ]));
+    }
+
+    fn headers(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn mapping(pairs: &[(&str, usize)]) -> Vec<(String, usize)> {
+        pairs.iter().map(|(f, i)| (f.to_string(), *i)).collect()
+    }
+
+    #[test]
+    fn csv_columns_by_header() {
+        let h = headers(&["Name", "Login", "Web Site", "Passwd", "Extra", "Folder", "PIN"]);
+        assert_eq!(csv_column_mapping("", &h).unwrap(), mapping(&[
+            ("title", 0), ("password", 3), ("username", 1), ("url", 2), ("notes", 4), ("group", 5), ("PIN", 6),
+        ]));
+    }
+
+    #[test]
+    fn csv_columns_by_map() {
+        let h = headers(&["Site", "Secret", "Who", "Unused"]);
+        assert_eq!(csv_column_mapping("title=Site, password=Secret,user=Who,", &h).unwrap(), mapping(&[
+            ("title", 0), ("password", 1), ("username", 2),
+        ]));
+        match csv_column_mapping("title=Missing", &h) {
+            Err(Error::Import(msg)) => assert_eq!(msg, "Missing column: Missing"),
+            r => panic!("unexpected result {:?}", r),
         }
-   }
-   return Ok(());
-}
\ No newline at end of file
+        assert!(csv_column_mapping("title", &h).is_err());
+    }
+
+    #[test]
+    fn csv_delimiters() {
+        assert_eq!(csv_delimiter(",").unwrap(), b',');
+        assert_eq!(csv_delimiter(";").unwrap(), b';');
+        assert_eq!(csv_delimiter("tab").unwrap(), b'\t');
+        assert_eq!(csv_delimiter("\\t").unwrap(), b'\t');
+        assert!(csv_delimiter("").is_err());
+        assert!(csv_delimiter(";;").is_err());
+    }
+
+    #[test]
+    fn path_templates() {
+        let mut values = std::collections::HashMap::new();
+        for (k, v) in &[("title", "Mail/Box"), ("group", "Work//Team "), ("domain", "mail.example.com"), ("username", ".bob")] {
+            values.insert(k.to_string(), v.to_string());
+        }
+        let render = |t: &str| render_path_template(t, &values);
+
+        assert_eq!(render(DEFAULT_PATH_TEMPLATE).unwrap(), ("csv_import/Work/Team/mail.example.com".to_owned(), "Mail_Box".to_owned()));
+        assert_eq!(render("{domain}/{USERNAME}").unwrap(), ("mail.example.com".to_owned(), "_bob".to_owned()));
+        assert_eq!(render("x-{title}-y").unwrap(), ("".to_owned(), "x-Mail_Box-y".to_owned()));
+
+        //empty values leave out their dir
+        values.insert("group".to_owned(), "".to_owned());
+        values.insert("domain".to_owned(), "".to_owned());
+        assert_eq!(render_path_template(DEFAULT_PATH_TEMPLATE, &values).unwrap(), ("csv_import".to_owned(), "Mail_Box".to_owned()));
+
+        assert!(render_path_template("{title", &values).is_err());
+        assert!(render_path_template("{nope}/{title}", &values).is_err());
+    }
+
+    #[test]
+    fn generic_csv_import() {
+        let dir = TempDir::new("generic-csv");
+        let p = dir.path().join("export.csv");
+        std::fs::write(&p, "name;url;user;password;group;PIN\n\
+            Mail;https://mail.example.com/login;bob;pw1;Work;1234\n\
+            Mail;https://mail.example.com;alice;pw2;Work\n\
+            Bank;;;pw3;;;extra column\n").unwrap();
+
+        let opts = Options { delimiter: ";".to_owned(), ..Default::default() };
+        let entries = planned(plan_generic_csv(&opts, &p).unwrap());
+        assert_eq!(entries, expect_planned(&[
+            ("line 2", "csv_import/Work/mail.example.com/Mail", "pw1\nusername: bob\nurl: https://mail.example.com/login\nPIN: 1234"),
+            ("line 3", "csv_import/Work/mail.example.com/Mail (2)", "pw2\nusername: alice\nurl: https://mail.example.com"),
+            ("line 4", "csv_import/Bank", "pw3"),
+        ]));
+
+        let opts = Options { delimiter: ";".to_owned(), csv_map: "title=user,password=PIN".to_owned(), path_template: "{group}/{title}".to_owned(), ..Default::default() };
+        let entries = planned(plan_generic_csv(&opts, &p).unwrap());
+        let paths: Vec<&str> = entries.iter().map(|(_, p, _)| p.as_str()).collect();
+        assert_eq!(paths, vec!["bob", "alice", "untitled"]);
+        assert_eq!(entries[0].2, "1234");
+
+        //with the wrong delimiter the header is one column, which has no title
+        let opts = Options { delimiter: ",".to_owned(), csv_map: "title=name".to_owned(), ..Default::default() };
+        assert!(plan_generic_csv(&opts, &p).is_err());
+    }
+
+    #[test]
+    fn skip_policy() {
+        let repo = existing_repo("import-skip");
+        let (actions, failed) = resolve(&repo, &["a", "b", "b", "d", "a/x", "n/m", "n", "n/m/o"], ConflictPolicy::Skip);
+        assert_eq!(actions, vec![
+            ("a".to_owned(), ImportAction::Skip("exists")),
+            ("b".to_owned(), ImportAction::Create),
+            ("b".to_owned(), ImportAction::Skip("exists")),
+            ("d".to_owned(), ImportAction::Skip("is a dir")),
+            ("a/x".to_owned(), ImportAction::Skip("a parent is an entry")),
+            ("n/m".to_owned(), ImportAction::Create),
+            ("n".to_owned(), ImportAction::Skip("is a dir")),
+            ("n/m/o".to_owned(), ImportAction::Skip("a parent is an entry")),
+        ]);
+        assert!(failed.is_empty());
+
+        import(&repo, &["a", "b", "n/m", "n"], "skip", false).unwrap();
+        assert_eq!(entries(repo.path()), expect(&[("a", "old a"), ("b", "new b"), ("d/e", "old e"), ("n/m", "new n/m")]));
+    }
+
+    #[test]
+    fn overwrite_policy() {
+        let repo = existing_repo("import-overwrite");
+        let (actions, failed) = resolve(&repo, &["a", "d", "d/e/f", "b", "b"], ConflictPolicy::Overwrite);
+        assert_eq!(actions, vec![
+            ("a".to_owned(), ImportAction::Overwrite),
+            ("b".to_owned(), ImportAction::Create),
+            ("b".to_owned(), ImportAction::Overwrite),
+        ]);
+        assert_eq!(failed, vec!["record 2 -> d (is a dir)", "record 3 -> d/e/f (a parent is an entry)"]);
+
+        import(&repo, &["a", "d", "x/y", "x"], "overwrite", false).unwrap();
+        assert_eq!(entries(repo.path()), expect(&[("a", "new a"), ("d/e", "old e"), ("x/y", "new x/y")]));
+    }
+
+    #[test]
+    fn rename_policy() {
+        let repo = existing_repo("import-rename");
+        add(repo.path(), "a (2)", "old a (2)");
+        let (actions, _) = resolve(&repo, &["a", "a", "d", "a/x", "d/e/f", "n/m", "n", "n/m/o"], ConflictPolicy::Rename);
+        let paths: Vec<&str> = actions.iter().map(|(p, _)| p.as_str()).collect();
+        assert_eq!(paths, vec!["a (3)", "a (4)", "d (2)", "a (5)/x", "d/e (2)/f", "n/m", "n (2)", "n/m (2)/o"]);
+        assert!(actions.iter().all(|(_, a)| *a == ImportAction::Create));
+
+        import(&repo, &["a", "a/x", "n/m", "n"], "rename", false).unwrap();
+        assert_eq!(entries(repo.path()), expect(&[
+            ("a", "old a"),
+            ("a (2)", "old a (2)"),
+            ("a (3)", "new a"),
+            ("a (4)/x", "new a/x"),
+            ("d/e", "old e"),
+            ("n (2)", "new n"),
+            ("n/m", "new n/m"),
+        ]));
+    }
+
+    #[test]
+    fn abort_policy_writes_nothing() {
+        let repo = existing_repo("import-abort");
+        match import(&repo, &["b", "c/d", "c"], "abort", false) {
+            Err(Error::Exists(msg)) => assert!(msg.starts_with("c (is a dir"), "{}", msg),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(entries(repo.path()), expect(&[("a", "old a"), ("d/e", "old e")]));
+    }
+
+    #[test]
+    fn dry_run_writes_nothing() {
+        let repo = existing_repo("import-dry-run");
+        import(&repo, &["a", "b", "x/y", "x"], "overwrite", true).unwrap();
+        assert_eq!(entries(repo.path()), expect(&[("a", "old a"), ("d/e", "old e")]));
+    }
+
+    #[test]
+    fn rollback_restores_the_repo() {
+        let repo = existing_repo("import-rollback");
+        let params = enc_params();
+        let mut journal = Vec::new();
+        let written = [("a", ImportAction::Overwrite), ("b", ImportAction::Create), ("d/f", ImportAction::Create), ("x/y/z", ImportAction::Create)];
+        for (path, action) in &written {
+            let e = PlannedEntry { source: "test".to_owned(), path: path.to_string(), content: "new".to_owned() };
+            write_planned_entry(repo.path(), &e, *action, &params, &mut journal).unwrap();
+        }
+        assert_eq!(entries(repo.path()).len(), 5);
+
+        rollback_import(journal);
+        assert_eq!(entries(repo.path()), expect(&[("a", "old a"), ("d/e", "old e")]));
+        //the dirs the import created are gone too
+        assert!(!entry_file_path(repo.path(), std::path::Path::new("x"), &params).unwrap().exists());
+        assert!(entry_file_path(repo.path(), std::path::Path::new("d"), &params).unwrap().is_dir());
+    }
+
+    // a password store with a fake decrypt command: "cat", except for files named broken.gpg
+    fn pass_store(dir: &TempDir) -> std::path::PathBuf {
+        let store = dir.path().join("store");
+        for (file, content) in &[
+            (".gpg-id", "ABCDEF"),
+            (".git/config", "git"),
+            ("bank.gpg", "pw bank\n"),
+            ("email/work.gpg", "pw work\nusername: me\n"),
+            ("email/nested/deep.gpg", "pw deep"),
+            ("email/notes.txt", "not a pass entry"),
+            ("broken.gpg", "no key for this one"),
+        ] {
+            let p = store.join(file);
+            std::fs::create_dir_all(p.parent().unwrap()).unwrap();
+            std::fs::write(p, content).unwrap();
+        }
+        std::fs::write(store.join("binary.gpg"), b"\xff\xfe").unwrap();
+
+        let script = dir.path().join("decrypt.sh");
+        std::fs::write(&script, "case \"$1\" in *broken.gpg) echo 'no secret key' >&2; exit 2;; esac\ncat \"$1\"\n").unwrap();
+        std::env::set_var("SPARKPASS_DECRYPT_CMD", format!("sh {}", script.display()));
+        store
+    }
+
+    #[test]
+    fn pass_import() {
+        let dir = TempDir::new("pass-import");
+        let store = pass_store(&dir);
+
+        let mut files = Vec::new();
+        let mut failed = Vec::new();
+        collect_pass_files(&store, &store, &mut files, &mut failed).unwrap();
+        files.sort();
+        assert_eq!(files, vec!["bank.gpg", "binary.gpg", "broken.gpg", "email/nested/deep.gpg", "email/work.gpg"]);
+        assert_eq!(failed, vec!["email/notes.txt (not a .gpg file)"]);
+
+        assert_eq!(decrypt_pass_file(&store.join("bank.gpg")).unwrap(), "pw bank\n");
+        assert_eq!(decrypt_pass_file(&store.join("broken.gpg")).unwrap_err(), "decrypt command failed: no secret key");
+        assert_eq!(decrypt_pass_file(&store.join("binary.gpg")).unwrap_err(), "content is not UTF-8");
+
+        let mut plan = plan_pass(&store).unwrap();
+        let mut failed = std::mem::take(&mut plan.failed);
+        failed.sort();
+        assert_eq!(failed, vec![
+            "binary.gpg (content is not UTF-8)",
+            "broken.gpg (decrypt command failed: no secret key)",
+            "email/notes.txt (not a .gpg file)",
+        ]);
+        assert_eq!(planned(plan), expect_planned(&[
+            ("bank.gpg", "pass_import/bank", "pw bank"),
+            ("email/nested/deep.gpg", "pass_import/email/nested/deep", "pw deep"),
+            ("email/work.gpg", "pass_import/email/work", "pw work\nusername: me"),
+        ]));
+
+        let repo = TempDir::new("pass-import-repo");
+        let opts = Options { on_conflict: "skip".to_owned(), ..Default::default() };
+        run_import(&opts, repo.path(), &store, plan_pass(&store).unwrap(), &enc_params()).unwrap();
+        assert_eq!(entries(repo.path()), expect(&[
+            ("pass_import/bank", "pw bank"),
+            ("pass_import/email/nested/deep", "pw deep"),
+            ("pass_import/email/work", "pw work\nusername: me"),
+        ]));
+
+        assert!(matches!(plan_pass(&dir.path().join("missing")), Err(Error::NotFound(_))));
+    }
+}
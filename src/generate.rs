@@ -1,15 +1,486 @@
-use std::fs::File;
-use std::io::Read;
-extern crate base64;
+// This file deals with generating passwords from character classes and with the named policies stored in a repo
+use crate::transform;
+use crate::util;
+use crate::error::Error;
 
-pub fn generate_passwd(length: usize) -> String {
-    let mut f = File::open("/dev/urandom").unwrap();
-    let mut vbuf = vec![0u8;length];
-    let buf = vbuf.as_mut_slice();
+use std::fs;
+use std::path;
 
-    f.read_exact(buf).expect("Couldn't read from /dev/urandom");
+// the named policies of a repo, one "name=spec" per line
+pub static POLICY_FILE: &str = ".sparkpass_policies";
 
-    let passwd = base64::encode_config(buf, base64::URL_SAFE_NO_PAD);
+pub const DEFAULT_LENGTH: usize = 64;
 
-    return passwd[..length].to_owned();
-}
\ No newline at end of file
+static LOWER: &str = "abcdefghijklmnopqrstuvwxyz";
+static UPPER: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+static DIGITS: &str = "0123456789";
+static SYMBOLS: &str = "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+// characters that are easily confused with each other when read or typed
+static AMBIGUOUS: &str = "0O1lI|`'\"";
+
+// the stored form is "length=20;classes=lower,upper;require=lower,digits;no-ambiguous;custom=..."
+// custom has to be the last item, it takes the rest of the line so it may contain any character
+#[derive(Clone, Debug, PartialEq)]
+pub struct Policy {
+    pub length: usize,
+    pub classes: Vec<String>,
+    pub custom: String,
+    pub require: Vec<String>,
+    pub no_ambiguous: bool,
+}
+
+fn class_chars(class: &str) -> Result<&'static str, Error> {
+    match class {
+        "lower" => Ok(LOWER),
+        "upper" => Ok(UPPER),
+        "digits" => Ok(DIGITS),
+        "symbols" => Ok(SYMBOLS),
+        _ => Err(Error::InvalidArgument(format!("Unknown character class: {}. Known: lower, upper, digits, symbols, custom", class))),
+    }
+}
+
+pub fn split_list(list: &str) -> Vec<String> {
+    list.split(",").map(|s| s.trim()).filter(|s| !s.is_empty()).map(|s| s.to_owned()).collect()
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy {
+            length: DEFAULT_LENGTH,
+            classes: vec!["lower".to_owned(), "upper".to_owned(), "digits".to_owned()],
+            custom: String::new(),
+            require: Vec::new(),
+            no_ambiguous: false,
+        }
+    }
+}
+
+impl Policy {
+    pub fn parse(spec: &str) -> Result<Policy, Error> {
+        let mut policy = Policy::default();
+        let mut rest = spec;
+
+        while !rest.is_empty() {
+            if let Some(custom) = rest.strip_prefix("custom=") {
+                policy.custom = custom.to_owned();
+                break;
+            }
+
+            let (item, next) = match rest.find(";") {
+                Some(idx) => (&rest[..idx], &rest[idx + 1..]),
+                None => (rest, ""),
+            };
+            rest = next;
+
+            let (k, v) = match item.find("=") {
+                Some(idx) => (item[..idx].trim(), item[idx + 1..].trim()),
+                None => (item.trim(), ""),
+            };
+            match k {
+                "" => {},
+                "length" => {
+                    policy.length = match v.parse() {
+                        Ok(l) => l,
+                        Err(_) => return Err(Error::InvalidArgument(format!("Invalid policy length: {}", v))),
+                    }
+                },
+                "classes" => policy.classes = split_list(v),
+                "require" => policy.require = split_list(v),
+                "no-ambiguous" => policy.no_ambiguous = true,
+                _ => return Err(Error::InvalidArgument(format!("Unknown policy item: {}", k))),
+            }
+        }
+
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    pub fn to_spec(&self) -> String {
+        let mut spec = format!("length={};classes={}", self.length, self.classes.join(","));
+        if !self.require.is_empty() {
+            spec.push_str(format!(";require={}", self.require.join(",")).as_str());
+        }
+        if self.no_ambiguous {
+            spec.push_str(";no-ambiguous");
+        }
+        if !self.custom.is_empty() {
+            spec.push_str(format!(";custom={}", self.custom).as_str());
+        }
+        spec
+    }
+
+    // the characters of a class, without duplicates and ambiguous characters if those are excluded
+    fn chars_of(&self, class: &str) -> Result<Vec<char>, Error> {
+        let chars = if class == "custom" {
+            self.custom.as_str()
+        } else {
+            class_chars(class)?
+        };
+
+        let mut res = Vec::new();
+        for c in chars.chars() {
+            if self.no_ambiguous && AMBIGUOUS.contains(c) {
+                continue;
+            }
+            if !res.contains(&c) {
+                res.push(c);
+            }
+        }
+        Ok(res)
+    }
+
+    fn alphabet(&self) -> Result<Vec<char>, Error> {
+        let mut classes = self.classes.clone();
+        if !self.custom.is_empty() && !classes.iter().any(|c| c == "custom") {
+            classes.push("custom".to_owned());
+        }
+
+        let mut res = Vec::new();
+        for class in classes {
+            for c in self.chars_of(class.as_str())? {
+                if !res.contains(&c) {
+                    res.push(c);
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.length == 0 {
+            return Err(Error::InvalidArgument("The password length must be greater than 0".to_owned()));
+        }
+        if self.alphabet()?.len() < 2 {
+            return Err(Error::InvalidArgument("The policy allows less than two different characters".to_owned()));
+        }
+        for class in &self.require {
+            if self.chars_of(class.as_str())?.is_empty() {
+                return Err(Error::InvalidArgument(format!("The required class {} has no characters", class)));
+            }
+            if class != "custom" && !self.classes.contains(class) {
+                return Err(Error::InvalidArgument(format!("The required class {} is not one of the classes", class)));
+            }
+        }
+        if self.require.len() > self.length {
+            return Err(Error::InvalidArgument(format!("A password of length {} can't contain {} required classes", self.length, self.require.len())));
+        }
+        Ok(())
+    }
+}
+
+// uniform index in 0..n. Bytes above the largest multiple of n are rejected so no index is more likely than another
+fn random_index(n: usize) -> Result<usize, Error> {
+    let limit = 256 - (256 % n);
+    loop {
+        let b = transform::get_random_bytes(1)?[0] as usize;
+        if b < limit {
+            return Ok(b % n);
+        }
+    }
+}
+
+pub fn generate_with_policy(policy: &Policy) -> Result<String, Error> {
+    policy.validate()?;
+    let alphabet = policy.alphabet()?;
+    if alphabet.len() > 256 {
+        return Err(Error::InvalidArgument("The policy allows more than 256 different characters".to_owned()));
+    }
+
+    let mut required = Vec::new();
+    for class in &policy.require {
+        required.push(policy.chars_of(class.as_str())?);
+    }
+
+    //passwords missing a required class are thrown away, so every valid password is equally likely
+    for _ in 0..10000 {
+        let passwd = (0..policy.length)
+            .map(|_| random_index(alphabet.len()).map(|i| alphabet[i]))
+            .collect::<Result<Vec<char>, Error>>()?;
+        if required.iter().all(|class| passwd.iter().any(|c| class.contains(c))) {
+            return Ok(passwd.into_iter().collect());
+        }
+    }
+    Err(Error::InvalidArgument("Couldn't generate a password with all required classes, the policy is too strict".to_owned()))
+}
+
+pub fn generate_passwd(length: usize) -> Result<String, Error> {
+    generate_with_policy(&Policy { length, ..Policy::default() })
+}
+
+// the english list of BIP 39: 2048 short and distinct words, 11 bits each. Source in wordlists/README
+static DEFAULT_WORDLIST: &str = include_str!("../wordlists/bip39_english.txt");
+
+pub struct PassphraseOptions {
+    pub words: usize,
+    pub separator: String,
+    pub capitalize: bool,
+    //appends a random digit to every word
+    pub digits: bool,
+}
+
+// one word per line. Lines with more than one column (like the numbered diceware lists) use the last column
+pub fn load_wordlist(p: Option<&path::Path>) -> Result<Vec<String>, Error> {
+    let content = match p {
+        Some(p) => fs::read_to_string(p)?,
+        None => DEFAULT_WORDLIST.to_owned(),
+    };
+
+    let mut words: Vec<String> = Vec::new();
+    for line in content.lines() {
+        let word = match line.split_whitespace().last() {
+            Some(w) => w,
+            None => continue,
+        };
+        if !words.iter().any(|w| w == word) {
+            words.push(word.to_owned());
+        }
+    }
+
+    if words.len() < 2 {
+        return Err(Error::InvalidArgument("The wordlist needs at least two different words".to_owned()));
+    }
+    Ok(words)
+}
+
+fn random_index_large(n: usize) -> Result<usize, Error> {
+    if n <= 256 {
+        return random_index(n);
+    }
+    //same rejection sampling as random_index, on 32 bit numbers
+    let n = n as u64;
+    let limit = (1u64 << 32) - ((1u64 << 32) % n);
+    loop {
+        let b = transform::get_random_bytes(4)?;
+        let x = u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as u64;
+        if x < limit {
+            return Ok((x % n) as usize);
+        }
+    }
+}
+
+// returns the passphrase and its entropy in bits
+pub fn generate_passphrase(opts: &PassphraseOptions, wordlist: &[String]) -> Result<(String, f64), Error> {
+    if opts.words == 0 {
+        return Err(Error::InvalidArgument("The passphrase needs at least one word".to_owned()));
+    }
+
+    let mut words = Vec::new();
+    for _ in 0..opts.words {
+        let mut word = wordlist[random_index_large(wordlist.len())?].clone();
+        if opts.capitalize {
+            let mut chars = word.chars();
+            word = match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => word,
+            };
+        }
+        if opts.digits {
+            word.push_str(random_index(10)?.to_string().as_str());
+        }
+        words.push(word);
+    }
+
+    let mut entropy = (opts.words as f64) * (wordlist.len() as f64).log2();
+    if opts.digits {
+        entropy += (opts.words as f64) * 10f64.log2();
+    }
+    Ok((words.join(opts.separator.as_str()), entropy))
+}
+
+pub fn load_policies(repo: &path::Path) -> Result<Vec<(String, Policy)>, Error> {
+    let p = repo.join(POLICY_FILE);
+    if !p.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut res = Vec::new();
+    for line in fs::read_to_string(p)?.lines().filter(|l| !l.trim().is_empty()) {
+        let (name, spec) = match line.find("=") {
+            Some(idx) => (&line[..idx], &line[idx + 1..]),
+            None => return Err(Error::InvalidArgument(format!("Malformed line in {}: {}", POLICY_FILE, line))),
+        };
+        res.push((name.trim().to_owned(), Policy::parse(spec)?));
+    }
+    Ok(res)
+}
+
+pub fn load_policy(repo: &path::Path, name: &str) -> Result<Policy, Error> {
+    match load_policies(repo)?.into_iter().find(|(n, _)| n == name) {
+        Some((_, p)) => Ok(p),
+        None => Err(Error::NotFound(format!("policy {}", name))),
+    }
+}
+
+// adds the policy or replaces the one with the same name
+pub fn save_policy(repo: &path::Path, name: &str, policy: &Policy) -> Result<(), Error> {
+    if name.is_empty() || name.contains("=") || name.contains("\n") {
+        return Err(Error::InvalidArgument(format!("Invalid policy name: {}", name)));
+    }
+    if policy.custom.contains("\n") {
+        return Err(Error::InvalidArgument("Custom characters of a stored policy can't contain a newline".to_owned()));
+    }
+
+    let mut policies = load_policies(repo)?;
+    policies.retain(|(n, _)| n != name);
+    policies.push((name.to_owned(), policy.clone()));
+
+    let mut content = String::new();
+    for (n, p) in policies {
+        content.push_str(format!("{}={}\n", n, p.to_spec()).as_str());
+    }
+    util::write_file_atomic(repo.join(POLICY_FILE).as_path(), content.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn passphrase_opts(words: usize, separator: &str) -> PassphraseOptions {
+        PassphraseOptions { words, separator: separator.to_owned(), capitalize: false, digits: false }
+    }
+
+    fn policy(spec: &str) -> Result<Policy, Error> {
+        Policy::parse(spec)
+    }
+
+    #[test]
+    fn every_required_class_appears() {
+        let p = policy("length=4;classes=lower,upper,digits,symbols;require=lower,upper,digits,symbols").unwrap();
+        for _ in 0..200 {
+            let passwd = generate_with_policy(&p).unwrap();
+            assert_eq!(passwd.chars().count(), 4);
+            for class in &[LOWER, UPPER, DIGITS, SYMBOLS] {
+                assert!(passwd.chars().any(|c| class.contains(c)), "{}", passwd);
+            }
+        }
+
+        let custom = policy("length=3;classes=lower;require=custom;custom=äö").unwrap();
+        for _ in 0..200 {
+            let passwd = generate_with_policy(&custom).unwrap();
+            assert!(passwd.chars().all(|c| LOWER.contains(c) || "äö".contains(c)), "{}", passwd);
+            assert!(passwd.chars().any(|c| "äö".contains(c)), "{}", passwd);
+        }
+    }
+
+    #[test]
+    fn no_ambiguous_characters() {
+        let p = policy("length=200;classes=lower,upper,digits,symbols;no-ambiguous").unwrap();
+        let passwd = generate_with_policy(&p).unwrap();
+        assert!(passwd.chars().all(|c| !AMBIGUOUS.contains(c)), "{}", passwd);
+        assert_eq!(generate_passwd(20).unwrap().len(), 20);
+    }
+
+    #[test]
+    fn impossible_policies() {
+        let impossible = [
+            "length=0",
+            //more required classes than characters
+            "length=2;classes=lower,upper,digits;require=lower,upper,digits",
+            "classes=lower;require=upper",
+            "classes=emoji",
+            "classes=;custom=x",
+            "classes=lower;require=custom",
+            "classes=lower;no-ambiguous;require=custom;custom=0O",
+        ];
+        for spec in &impossible {
+            match policy(spec) {
+                Err(Error::InvalidArgument(_)) => {},
+                r => panic!("{}: unexpected result {:?}", spec, r),
+            }
+        }
+
+        let p = Policy { length: 1, require: vec!["lower".to_owned(), "upper".to_owned()], ..Policy::default() };
+        assert!(generate_with_policy(&p).is_err());
+    }
+
+    #[test]
+    fn policy_parser() {
+        assert_eq!(policy("").unwrap(), Policy::default());
+        assert_eq!(policy("length=12").unwrap().length, 12);
+
+        let p = policy(" length = 20 ;classes=lower, digits;require=digits;no-ambiguous;custom=;= x").unwrap();
+        assert_eq!(p, Policy {
+            length: 20,
+            classes: vec!["lower".to_owned(), "digits".to_owned()],
+            custom: ";= x".to_owned(),
+            require: vec!["digits".to_owned()],
+            no_ambiguous: true,
+        });
+        assert_eq!(p.to_spec(), "length=20;classes=lower,digits;require=digits;no-ambiguous;custom=;= x");
+        assert_eq!(policy(p.to_spec().as_str()).unwrap(), p);
+
+        for spec in &["length=x", "length=-1", "color=red", "classes=lower;upper"] {
+            assert!(policy(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn stored_policies() {
+        let dir = TempDir::new("policies");
+        assert!(load_policies(dir.path()).unwrap().is_empty());
+
+        let strict = policy("length=30;classes=lower,upper,digits,symbols;require=symbols").unwrap();
+        save_policy(dir.path(), "strict", &strict).unwrap();
+        save_policy(dir.path(), "pin", &policy("length=6;classes=digits").unwrap()).unwrap();
+        save_policy(dir.path(), "strict", &Policy { length: 40, ..strict.clone() }).unwrap();
+
+        assert_eq!(load_policy(dir.path(), "strict").unwrap().length, 40);
+        assert_eq!(load_policy(dir.path(), "pin").unwrap().classes, vec!["digits"]);
+        assert_eq!(load_policies(dir.path()).unwrap().len(), 2);
+        match load_policy(dir.path(), "missing") {
+            Err(Error::NotFound(_)) => {},
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(save_policy(dir.path(), "a=b", &strict).is_err());
+        assert!(save_policy(dir.path(), "", &strict).is_err());
+    }
+
+    #[test]
+    fn passphrase_word_count_and_separator() {
+        let wordlist = load_wordlist(None).unwrap();
+        for (words, separator) in &[(1, "-"), (6, "-"), (8, " "), (4, "::")] {
+            let (passphrase, entropy) = generate_passphrase(&passphrase_opts(*words, separator), &wordlist).unwrap();
+            let parts: Vec<&str> = passphrase.split(separator).collect();
+            assert_eq!(parts.len(), *words, "{}", passphrase);
+            assert!(parts.iter().all(|p| wordlist.iter().any(|w| w == p)), "{}", passphrase);
+            assert!((entropy - 11.0 * *words as f64).abs() < 1e-9);
+        }
+        assert!(generate_passphrase(&passphrase_opts(0, "-"), &wordlist).is_err());
+    }
+
+    #[test]
+    fn passphrase_capitalize_and_digits() {
+        let wordlist = vec!["alpha".to_owned(), "beta".to_owned()];
+        let opts = PassphraseOptions { words: 5, separator: ".".to_owned(), capitalize: true, digits: true };
+        let (passphrase, entropy) = generate_passphrase(&opts, &wordlist).unwrap();
+        for part in passphrase.split('.') {
+            let (word, digit) = part.split_at(part.len() - 1);
+            assert!(word == "Alpha" || word == "Beta", "{}", passphrase);
+            assert!(digit.chars().all(|c| c.is_ascii_digit()), "{}", passphrase);
+        }
+        assert!((entropy - 5.0 * (1.0 + 10f64.log2())).abs() < 1e-9);
+    }
+
+    #[test]
+    fn default_wordlist() {
+        let wordlist = load_wordlist(None).unwrap();
+        assert_eq!(wordlist.len(), 2048);
+        assert_eq!(wordlist[0], "abandon");
+        assert_eq!(wordlist[2047], "zoo");
+    }
+
+    #[test]
+    fn wordlist_file() {
+        let dir = TempDir::new("wordlist");
+        let p = dir.path().join("list.txt");
+
+        //numbered diceware lines use the last column, empty lines and duplicates are left out
+        fs::write(&p, "11111\tabacus\n11112 abdomen\n\n  \nzebra\nabacus\n").unwrap();
+        assert_eq!(load_wordlist(Some(&p)).unwrap(), vec!["abacus", "abdomen", "zebra"]);
+
+        fs::write(&p, "only\nonly\n").unwrap();
+        assert!(load_wordlist(Some(&p)).is_err());
+        assert!(load_wordlist(Some(&dir.path().join("missing"))).is_err());
+    }
+}
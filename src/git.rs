@@ -0,0 +1,56 @@
+// The repo can be kept in git. Names and contents are encrypted on disk, so the history only shows when entries changed.
+// Commit messages therefore never contain entry names.
+use crate::error::Error;
+
+use std::path;
+use std::process::{Command, ExitStatus};
+
+pub fn is_git_repo(prefix: &path::Path) -> bool {
+    prefix.join(".git").exists()
+}
+
+// runs git in the repo with the given arguments, stdin/stdout/stderr are passed through
+pub fn run_git(prefix: &path::Path, args: &[String]) -> Result<ExitStatus, Error> {
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(prefix)
+        .args(args)
+        .status()?;
+    Ok(status)
+}
+
+fn run_git_checked(prefix: &path::Path, args: &[&str]) -> Result<(), Error> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(prefix)
+        .args(args)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(Error::Io(std::io::Error::other(
+            format!("git {} failed: {}", args[0], String::from_utf8_lossy(&output.stderr).trim()),
+        )));
+    }
+    Ok(())
+}
+
+// commits all changes in the repo. Does nothing if the repo is not a git repo or nothing changed
+pub fn commit_all(prefix: &path::Path, message: &str) -> Result<(), Error> {
+    if !is_git_repo(prefix) {
+        return Ok(());
+    }
+
+    run_git_checked(prefix, &["add", "-A"])?;
+
+    //diff --quiet exits with 1 if something is staged
+    let staged = Command::new("git")
+        .arg("-C")
+        .arg(prefix)
+        .args(["diff", "--cached", "--quiet"])
+        .status()?;
+    if staged.success() {
+        return Ok(());
+    }
+
+    run_git_checked(prefix, &["commit", "-q", "-m", message])
+}
@@ -0,0 +1,768 @@
+// This file reads KeePass 2 databases (KDBX 3.1 and 4) so they can be imported without exporting them to csv first.
+// Only password protected databases are supported, key files and the Twofish cipher are not
+extern crate argon2;
+extern crate base64;
+extern crate flate2;
+extern crate quick_xml;
+extern crate salsa20;
+
+use crate::error::Error;
+use openssl::hash::{hash, MessageDigest};
+use openssl::memcmp;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use openssl::symm::{decrypt, Cipher, Crypter, Mode};
+
+use quick_xml::events::Event;
+use quick_xml::XmlVersion;
+use salsa20::cipher::{KeyIvInit, StreamCipher};
+
+use std::collections::HashMap;
+use std::io::Read;
+
+const SIGNATURE_1: u32 = 0x9AA2_D903;
+const SIGNATURE_2: u32 = 0xB54B_FB67;
+
+const CIPHER_AES256: &str = "31c1f2e6bf714350be5805216afc5aff";
+const CIPHER_CHACHA20: &str = "d6038a2b8b6f4cb5a524339a31dbb59a";
+const CIPHER_TWOFISH: &str = "ad68f29f576f4bb9a36ad47af965346c";
+
+const KDF_AES: &str = "c9d9f39a628a4460bf740d08c18a4fea";
+const KDF_AES_KDBX4: &str = "7c02bb8279a74ac0927d114a00648238";
+const KDF_ARGON2D: &str = "ef636ddf8c29444b91f7a9a403e30a0c";
+const KDF_ARGON2ID: &str = "9e298b1956db4773b23dfc3ec6f0a1e6";
+
+const INNER_STREAM_SALSA20: u32 = 2;
+const INNER_STREAM_CHACHA20: u32 = 3;
+const SALSA20_IV: [u8; 8] = [0xE8, 0x30, 0x09, 0x4B, 0x97, 0x20, 0x5D, 0x2A];
+
+// an entry with the names of the groups above it, without the root group
+pub struct KdbxEntry {
+    pub groups: Vec<String>,
+    // Title, UserName, Password, URL, Notes and the custom strings, in the order of the database
+    pub strings: Vec<(String, String)>,
+    // file name and content
+    pub attachments: Vec<(String, Vec<u8>)>,
+}
+
+impl KdbxEntry {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.strings.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+}
+
+// not Error::WrongKey, that one is about the key of the repo
+fn wrong_password() -> Error {
+    Error::Import("wrong database password".to_owned())
+}
+
+fn malformed(msg: &str) -> Error {
+    Error::Import(format!("Malformed KDBX database: {}", msg))
+}
+
+struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() - self.pos < n {
+            return Err(malformed("unexpected end of data"));
+        }
+        let res = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(res)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+}
+
+fn le_u32(b: &[u8]) -> Result<u32, Error> {
+    if b.len() != 4 {
+        return Err(malformed("integer field of wrong size"));
+    }
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn le_u64(b: &[u8]) -> Result<u64, Error> {
+    if b.len() != 8 {
+        return Err(malformed("integer field of wrong size"));
+    }
+    let mut a = [0u8; 8];
+    a.copy_from_slice(b);
+    Ok(u64::from_le_bytes(a))
+}
+
+fn hex(b: &[u8]) -> String {
+    b.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn sha256(data: &[u8]) -> Vec<u8> {
+    hash(MessageDigest::sha256(), data).unwrap().to_vec()
+}
+
+fn sha512(data: &[u8]) -> Vec<u8> {
+    hash(MessageDigest::sha512(), data).unwrap().to_vec()
+}
+
+fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+    let pkey = PKey::hmac(key).unwrap();
+    let mut signer = Signer::new(MessageDigest::sha256(), &pkey).unwrap();
+    for p in parts {
+        signer.update(p).unwrap();
+    }
+    signer.sign_to_vec().unwrap()
+}
+
+// the outer header, the fields that are needed to decrypt the payload
+struct Header {
+    major: u16,
+    cipher: Vec<u8>,
+    compressed: bool,
+    master_seed: Vec<u8>,
+    iv: Vec<u8>,
+    kdf: Kdf,
+    // only in KDBX 3.1, version 4 has them in the inner header
+    stream_start: Vec<u8>,
+    inner_stream: Option<(u32, Vec<u8>)>,
+}
+
+enum Kdf {
+    Aes { seed: Vec<u8>, rounds: u64 },
+    Argon2 { variant: argon2::Variant, salt: Vec<u8>, lanes: u32, mem_kib: u32, iterations: u32, version: argon2::Version },
+}
+
+// the typed key/value map KDBX 4 stores the kdf parameters in. Values are kept as raw bytes
+fn parse_variant_dictionary(data: &[u8]) -> Result<HashMap<String, Vec<u8>>, Error> {
+    let mut r = ByteReader { data, pos: 0 };
+    let version = r.u16()?;
+    if version >> 8 != 1 {
+        return Err(malformed(format!("unknown kdf parameter version {:#x}", version).as_str()));
+    }
+
+    let mut res = HashMap::new();
+    loop {
+        let kind = r.u8()?;
+        if kind == 0 {
+            return Ok(res);
+        }
+        let name_len = r.u32()? as usize;
+        let name = String::from_utf8_lossy(r.take(name_len)?).into_owned();
+        let value_len = r.u32()? as usize;
+        res.insert(name, r.take(value_len)?.to_vec());
+    }
+}
+
+fn parse_kdf(params: &HashMap<String, Vec<u8>>) -> Result<Kdf, Error> {
+    let get = |k: &str| match params.get(k) {
+        Some(v) => Ok(v.as_slice()),
+        None => Err(malformed(format!("kdf parameter {} missing", k).as_str())),
+    };
+
+    let uuid = hex(get("$UUID")?);
+    match uuid.as_str() {
+        KDF_AES | KDF_AES_KDBX4 => Ok(Kdf::Aes { seed: get("S")?.to_vec(), rounds: le_u64(get("R")?)? }),
+        KDF_ARGON2D | KDF_ARGON2ID => {
+            let variant = if uuid == KDF_ARGON2D { argon2::Variant::Argon2d } else { argon2::Variant::Argon2id };
+            let version = match le_u32(get("V")?)? {
+                0x10 => argon2::Version::Version10,
+                0x13 => argon2::Version::Version13,
+                v => return Err(Error::Import(format!("Unsupported argon2 version in KDBX database: {:#x}", v))),
+            };
+            Ok(Kdf::Argon2 {
+                variant,
+                salt: get("S")?.to_vec(),
+                lanes: le_u32(get("P")?)?,
+                mem_kib: (le_u64(get("M")?)? / 1024) as u32,
+                iterations: le_u64(get("I")?)? as u32,
+                version,
+            })
+        },
+        _ => Err(Error::Import(format!("Unsupported kdf in KDBX database: {}", uuid))),
+    }
+}
+
+fn parse_header(r: &mut ByteReader) -> Result<Header, Error> {
+    if r.u32()? != SIGNATURE_1 || r.u32()? != SIGNATURE_2 {
+        return Err(Error::Import("Not a KeePass 2 database".to_owned()));
+    }
+    let _minor = r.u16()?;
+    let major = r.u16()?;
+    if major != 3 && major != 4 {
+        return Err(Error::Import(format!("Unsupported KDBX version {}, only 3.1 and 4 are supported", major)));
+    }
+
+    let mut header = Header {
+        major,
+        cipher: Vec::new(),
+        compressed: false,
+        master_seed: Vec::new(),
+        iv: Vec::new(),
+        kdf: Kdf::Aes { seed: Vec::new(), rounds: 0 },
+        stream_start: Vec::new(),
+        inner_stream: None,
+    };
+    let mut stream_key = Vec::new();
+    let mut stream_id = 0;
+
+    loop {
+        let id = r.u8()?;
+        let size = if major == 3 { r.u16()? as usize } else { r.u32()? as usize };
+        let data = r.take(size)?;
+        match id {
+            0 => break,
+            2 => header.cipher = data.to_vec(),
+            3 => header.compressed = le_u32(data)? == 1,
+            4 => header.master_seed = data.to_vec(),
+            5 => if let Kdf::Aes { seed, .. } = &mut header.kdf { *seed = data.to_vec() },
+            6 => if let Kdf::Aes { rounds, .. } = &mut header.kdf { *rounds = le_u64(data)? },
+            7 => header.iv = data.to_vec(),
+            8 => stream_key = data.to_vec(),
+            9 => header.stream_start = data.to_vec(),
+            10 => stream_id = le_u32(data)?,
+            11 => header.kdf = parse_kdf(&parse_variant_dictionary(data)?)?,
+            _ => {},
+        }
+    }
+
+    if major == 3 {
+        header.inner_stream = Some((stream_id, stream_key));
+        //compared with the start of the decrypted payload
+        if header.stream_start.len() != 32 {
+            return Err(malformed("stream start bytes missing"));
+        }
+    }
+    if header.master_seed.len() != 32 {
+        return Err(malformed("master seed missing"));
+    }
+    Ok(header)
+}
+
+fn transform_key(kdf: &Kdf, composite: &[u8]) -> Result<Vec<u8>, Error> {
+    match kdf {
+        Kdf::Aes { seed, rounds } => {
+            if seed.len() != 32 {
+                return Err(malformed("transform seed missing"));
+            }
+            let mut crypter = match Crypter::new(Cipher::aes_256_ecb(), Mode::Encrypt, seed, None) {
+                Ok(c) => c,
+                Err(e) => return Err(Error::Import(format!("Key transformation failed: {}", e))),
+            };
+            crypter.pad(false);
+
+            let mut key = composite.to_vec();
+            let mut out = vec![0u8; key.len() + 16];
+            for _ in 0..*rounds {
+                crypter.update(&key, &mut out).unwrap();
+                let len = key.len();
+                key.copy_from_slice(&out[..len]);
+            }
+            Ok(sha256(&key))
+        },
+        Kdf::Argon2 { variant, salt, lanes, mem_kib, iterations, version } => {
+            let config = argon2::Config {
+                variant: *variant,
+                version: *version,
+                mem_cost: *mem_kib,
+                time_cost: *iterations,
+                lanes: *lanes,
+                secret: &[],
+                ad: &[],
+                hash_length: 32,
+            };
+            match argon2::hash_raw(composite, salt, &config) {
+                Ok(k) => Ok(k),
+                Err(e) => Err(Error::Import(format!("Key transformation failed: {}", e))),
+            }
+        },
+    }
+}
+
+// decrypts with the outer cipher. A padding error means the password is wrong
+fn decrypt_payload(header: &Header, key: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+    match hex(&header.cipher).as_str() {
+        CIPHER_AES256 => decrypt(Cipher::aes_256_cbc(), key, Some(&header.iv), data).map_err(|_| wrong_password()),
+        CIPHER_CHACHA20 => {
+            //openssl takes a 32 bit block counter in front of the 96 bit nonce
+            if header.iv.len() != 12 {
+                return Err(malformed("invalid ChaCha20 nonce"));
+            }
+            let mut iv = vec![0u8; 4];
+            iv.extend_from_slice(&header.iv);
+            decrypt(Cipher::chacha20(), key, Some(&iv), data).map_err(|_| wrong_password())
+        },
+        CIPHER_TWOFISH => Err(Error::Import("The Twofish cipher is not supported, change the database to AES or ChaCha20 in KeePass".to_owned())),
+        c => Err(Error::Import(format!("Unknown cipher in KDBX database: {}", c))),
+    }
+}
+
+fn gunzip(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut res = Vec::new();
+    if let Err(e) = flate2::read::GzDecoder::new(data).read_to_end(&mut res) {
+        return Err(malformed(format!("decompression failed: {}", e).as_str()));
+    }
+    Ok(res)
+}
+
+// KDBX 3.1: blocks of index, sha256 and data after the start bytes
+fn read_hashed_blocks(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut r = ByteReader { data, pos: 0 };
+    let mut res = Vec::new();
+    loop {
+        let _index = r.u32()?;
+        let block_hash = r.take(32)?;
+        let size = r.u32()? as usize;
+        if size == 0 {
+            return Ok(res);
+        }
+        let block = r.take(size)?;
+        if !memcmp::eq(&sha256(block), block_hash) {
+            return Err(malformed("block hash mismatch"));
+        }
+        res.extend_from_slice(block);
+    }
+}
+
+fn block_hmac_key(hmac_key: &[u8], index: u64) -> Vec<u8> {
+    let mut data = index.to_le_bytes().to_vec();
+    data.extend_from_slice(hmac_key);
+    sha512(&data)
+}
+
+// KDBX 4: blocks of hmac and size. The hmac covers the block index, so blocks can't be reordered
+fn read_hmac_blocks(data: &[u8], hmac_key: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut r = ByteReader { data, pos: 0 };
+    let mut res = Vec::new();
+    let mut index: u64 = 0;
+    loop {
+        let mac = r.take(32)?;
+        let size_bytes = r.take(4)?;
+        let size = le_u32(size_bytes)? as usize;
+        let block = r.take(size)?;
+
+        let expected = hmac_sha256(&block_hmac_key(hmac_key, index), &[&index.to_le_bytes(), size_bytes, block]);
+        if !memcmp::eq(&expected, mac) {
+            return Err(malformed("block hmac mismatch"));
+        }
+        if size == 0 {
+            return Ok(res);
+        }
+        res.extend_from_slice(block);
+        index += 1;
+    }
+}
+
+// the stream cipher that hides protected values (mostly passwords) in the xml
+enum InnerStream {
+    Salsa20(salsa20::Salsa20),
+    ChaCha20(Crypter),
+    None,
+}
+
+impl InnerStream {
+    fn new(id: u32, key: &[u8]) -> Result<InnerStream, Error> {
+        match id {
+            0 => Ok(InnerStream::None),
+            INNER_STREAM_SALSA20 => {
+                let key = sha256(key);
+                Ok(InnerStream::Salsa20(salsa20::Salsa20::new(key.as_slice().into(), (&SALSA20_IV).into())))
+            },
+            INNER_STREAM_CHACHA20 => {
+                let h = sha512(key);
+                let mut iv = vec![0u8; 4];
+                iv.extend_from_slice(&h[32..44]);
+                match Crypter::new(Cipher::chacha20(), Mode::Decrypt, &h[..32], Some(&iv)) {
+                    Ok(c) => Ok(InnerStream::ChaCha20(c)),
+                    Err(e) => Err(Error::Import(format!("Inner stream setup failed: {}", e))),
+                }
+            },
+            _ => Err(Error::Import(format!("Unsupported inner stream cipher in KDBX database: {}", id))),
+        }
+    }
+
+    // the key stream continues over all protected values in document order
+    fn apply(&mut self, data: &mut Vec<u8>) {
+        match self {
+            InnerStream::Salsa20(c) => c.apply_keystream(data),
+            InnerStream::ChaCha20(c) => {
+                let mut out = vec![0u8; data.len() + 16];
+                let n = c.update(data, &mut out).unwrap();
+                out.truncate(n);
+                *data = out;
+            },
+            InnerStream::None => {},
+        }
+    }
+}
+
+// a minimal dom of the xml part. Protected values are decrypted while parsing, because the stream depends on the order
+struct Node {
+    name: String,
+    attrs: Vec<(String, String)>,
+    text: String,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+    }
+
+    fn child(&self, name: &str) -> Option<&Node> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Node> {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+}
+
+fn xml_error(e: quick_xml::Error) -> Error {
+    malformed(format!("invalid xml: {}", e).as_str())
+}
+
+fn start_node(e: &quick_xml::events::BytesStart) -> Result<Node, Error> {
+    let mut attrs = Vec::new();
+    for a in e.attributes() {
+        let a = a.map_err(|e| xml_error(e.into()))?;
+        let value = a.normalized_value(XmlVersion::Implicit1_0).map_err(xml_error)?.into_owned();
+        attrs.push((a.key.into_inner().to_owned(), value));
+    }
+    Ok(Node {
+        name: e.name().into_inner().to_owned(),
+        attrs,
+        text: String::new(),
+        children: Vec::new(),
+    })
+}
+
+fn finish_node(mut node: Node, stream: &mut InnerStream) -> Result<Node, Error> {
+    if node.name == "Value" && node.attr("Protected") == Some("True") {
+        let mut data = match base64::decode(node.text.trim()) {
+            Ok(d) => d,
+            Err(_) => return Err(malformed("protected value is not base64")),
+        };
+        stream.apply(&mut data);
+        node.text = match String::from_utf8(data) {
+            Ok(t) => t,
+            Err(_) => return Err(malformed("protected value is not UTF-8")),
+        };
+    }
+    Ok(node)
+}
+
+fn parse_xml(xml: &str, stream: &mut InnerStream) -> Result<Node, Error> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let mut stack: Vec<Node> = vec![Node { name: String::new(), attrs: Vec::new(), text: String::new(), children: Vec::new() }];
+
+    loop {
+        match reader.read_event().map_err(xml_error)? {
+            Event::Start(e) => stack.push(start_node(&e)?),
+            Event::Empty(e) => {
+                let node = finish_node(start_node(&e)?, stream)?;
+                stack.last_mut().unwrap().children.push(node);
+            },
+            Event::End(_) => {
+                if stack.len() < 2 {
+                    return Err(malformed("unbalanced xml"));
+                }
+                let node = finish_node(stack.pop().unwrap(), stream)?;
+                stack.last_mut().unwrap().children.push(node);
+            },
+            Event::Text(t) => stack.last_mut().unwrap().text.push_str(&t.xml_content(XmlVersion::Implicit1_0)),
+            Event::CData(t) => stack.last_mut().unwrap().text.push_str(&t.xml_content(XmlVersion::Implicit1_0)),
+            Event::GeneralRef(r) => {
+                let c = match r.resolve_char_ref().map_err(xml_error)? {
+                    Some(c) => c,
+                    None => match r.as_ref() {
+                        "amp" => '&',
+                        "lt" => '<',
+                        "gt" => '>',
+                        "quot" => '"',
+                        "apos" => '\'',
+                        other => return Err(malformed(format!("unknown entity &{};", other).as_str())),
+                    },
+                };
+                stack.last_mut().unwrap().text.push(c);
+            },
+            Event::Eof => break,
+            _ => {},
+        }
+    }
+
+    match stack.pop().and_then(|doc| doc.children.into_iter().find(|c| c.name == "KeePassFile")) {
+        Some(root) => Ok(root),
+        None => Err(malformed("no KeePassFile element")),
+    }
+}
+
+// KDBX 3.1 keeps the attachments base64 encoded in Meta/Binaries, optionally gzipped
+fn meta_binaries(file: &Node) -> Result<HashMap<String, Vec<u8>>, Error> {
+    let mut res = HashMap::new();
+    let binaries = match file.child("Meta").and_then(|m| m.child("Binaries")) {
+        Some(b) => b,
+        None => return Ok(res),
+    };
+    for b in binaries.children_named("Binary") {
+        let mut data = match base64::decode(b.text.trim()) {
+            Ok(d) => d,
+            Err(_) => return Err(malformed("attachment is not base64")),
+        };
+        if b.attr("Compressed") == Some("True") {
+            data = gunzip(&data)?;
+        }
+        res.insert(b.attr("ID").unwrap_or("").to_owned(), data);
+    }
+    Ok(res)
+}
+
+fn collect_entries(group: &Node, groups: &[String], recycle_bin: Option<&str>, binaries: &HashMap<String, Vec<u8>>, res: &mut Vec<KdbxEntry>) -> Result<(), Error> {
+    for e in group.children_named("Entry") {
+        let mut entry = KdbxEntry { groups: groups.to_vec(), strings: Vec::new(), attachments: Vec::new() };
+        for s in e.children_named("String") {
+            let key = s.child("Key").map(|k| k.text.clone()).unwrap_or_default();
+            let value = s.child("Value").map(|v| v.text.clone()).unwrap_or_default();
+            entry.strings.push((key, value));
+        }
+        for b in e.children_named("Binary") {
+            let name = b.child("Key").map(|k| k.text.clone()).unwrap_or_default();
+            let id = b.child("Value").and_then(|v| v.attr("Ref")).unwrap_or("");
+            match binaries.get(id) {
+                Some(data) => entry.attachments.push((name, data.clone())),
+                None => return Err(malformed(format!("attachment {} not found", name).as_str())),
+            }
+        }
+        res.push(entry);
+    }
+
+    for g in group.children_named("Group") {
+        let uuid = g.child("UUID").map(|u| u.text.trim());
+        if recycle_bin.is_some() && uuid == recycle_bin {
+            continue;
+        }
+        let mut sub = groups.to_vec();
+        sub.push(g.child("Name").map(|n| n.text.clone()).unwrap_or_default());
+        collect_entries(g, &sub, recycle_bin, binaries, res)?;
+    }
+    Ok(())
+}
+
+// decrypts the database and returns its entries. Deleted entries in the recycle bin and the history are left out
+pub fn read_database(data: &[u8], password: &str) -> Result<Vec<KdbxEntry>, Error> {
+    let mut r = ByteReader { data, pos: 0 };
+    let header = parse_header(&mut r)?;
+    let header_bytes = &data[..r.pos];
+
+    let composite = sha256(&sha256(password.as_bytes()));
+    let transformed = transform_key(&header.kdf, &composite)?;
+    let mut seed_and_key = header.master_seed.clone();
+    seed_and_key.extend_from_slice(&transformed);
+    let key = sha256(&seed_and_key);
+
+    let (mut binaries, inner_stream, xml) = if header.major == 3 {
+        let plain = decrypt_payload(&header, &key, r.rest())?;
+        if plain.len() < 32 || !memcmp::eq(&plain[..32], &header.stream_start) {
+            return Err(wrong_password());
+        }
+        let mut payload = read_hashed_blocks(&plain[32..])?;
+        if header.compressed {
+            payload = gunzip(&payload)?;
+        }
+        (HashMap::new(), header.inner_stream.clone().unwrap_or((0, Vec::new())), payload)
+    } else {
+        let header_hash = r.take(32)?;
+        if !memcmp::eq(&sha256(header_bytes), header_hash) {
+            return Err(malformed("header hash mismatch"));
+        }
+        seed_and_key.push(1);
+        let hmac_key = sha512(&seed_and_key);
+        let header_mac = r.take(32)?;
+        if !memcmp::eq(&hmac_sha256(&block_hmac_key(&hmac_key, u64::MAX), &[header_bytes]), header_mac) {
+            return Err(wrong_password());
+        }
+
+        let mut payload = decrypt_payload(&header, &key, &read_hmac_blocks(r.rest(), &hmac_key)?)?;
+        if header.compressed {
+            payload = gunzip(&payload)?;
+        }
+
+        //the inner header holds the stream cipher for protected values and the attachments
+        let mut inner = ByteReader { data: &payload, pos: 0 };
+        let mut binaries = HashMap::new();
+        let mut stream = (0, Vec::new());
+        loop {
+            let id = inner.u8()?;
+            let size = inner.u32()? as usize;
+            let field = inner.take(size)?;
+            match id {
+                0 => break,
+                1 => stream.0 = le_u32(field)?,
+                2 => stream.1 = field.to_vec(),
+                3 => {
+                    if field.is_empty() {
+                        return Err(malformed("empty attachment field"));
+                    }
+                    binaries.insert(binaries.len().to_string(), field[1..].to_vec());
+                },
+                _ => {},
+            }
+        }
+        (binaries, stream, inner.rest().to_vec())
+    };
+
+    let xml = match String::from_utf8(xml) {
+        Ok(x) => x,
+        Err(_) => return Err(malformed("xml is not UTF-8")),
+    };
+    let mut stream = InnerStream::new(inner_stream.0, &inner_stream.1)?;
+    let file = parse_xml(xml.as_str(), &mut stream)?;
+
+    if header.major == 3 {
+        binaries = meta_binaries(&file)?;
+    }
+
+    let recycle_bin = file.child("Meta")
+        .and_then(|m| m.child("RecycleBinUUID"))
+        .map(|u| u.text.trim())
+        .filter(|u| !u.is_empty() && *u != "AAAAAAAAAAAAAAAAAAAAAA==");
+
+    let root_group = match file.child("Root").and_then(|r| r.child("Group")) {
+        Some(g) => g,
+        None => return Err(malformed("no root group")),
+    };
+
+    let mut res = Vec::new();
+    collect_entries(root_group, &[], recycle_bin, &binaries, &mut res)?;
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the fixtures hold the same entries, see fixtures/kdbx/README
+    const PASSWORD: &str = "secret pw";
+
+    fn read_fixture(name: &str, password: &str) -> Result<Vec<KdbxEntry>, Error> {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/kdbx").join(name);
+        read_database(&std::fs::read(path).unwrap(), password)
+    }
+
+    fn check_entries(entries: &[KdbxEntry]) {
+        assert_eq!(entries.len(), 4);
+
+        let mail = &entries[0];
+        assert!(mail.groups.is_empty());
+        assert_eq!(mail.get("Title"), Some("Mail/Work"));
+        assert_eq!(mail.get("UserName"), Some("bob & co"));
+        assert_eq!(mail.get("Password"), Some("pässwörd&<1>"));
+        assert_eq!(mail.get("URL"), Some("https://mail.example.com"));
+        assert_eq!(mail.get("Notes"), Some("line one\nline two"));
+        assert_eq!(mail.get("Recovery Code"), Some("ABC-123"));
+        assert_eq!(mail.get("Multi"), Some("a\nb"));
+        assert_eq!(mail.attachments, vec![
+            ("note.txt".to_owned(), b"attached text\n".to_vec()),
+            ("blob.bin".to_owned(), vec![0, 255, 1, 254]),
+        ]);
+
+        //the protected values after the history and in sub groups have to be decrypted with the right keystream offset
+        let social: Vec<_> = entries[1..].iter().map(|e| (e.groups.clone(), e.get("Password").unwrap())).collect();
+        let group = vec!["Social".to_owned()];
+        assert_eq!(social, vec![(group.clone(), "pw2"), (group.clone(), "pw3"), (group, "xü")]);
+
+        //the recycle bin is left out
+        assert!(entries.iter().all(|e| e.get("Title") != Some("gone")));
+    }
+
+    #[test]
+    fn kdbx3_aes_kdf_salsa20_gzip_meta_binaries() {
+        check_entries(&read_fixture("v3_aeskdf_salsa20.kdbx", PASSWORD).unwrap());
+    }
+
+    #[test]
+    fn kdbx4_aes_kdf() {
+        check_entries(&read_fixture("v4_aeskdf_aes.kdbx", PASSWORD).unwrap());
+    }
+
+    #[test]
+    fn kdbx4_argon2id_chacha20() {
+        check_entries(&read_fixture("v4_argon2id_chacha20.kdbx", PASSWORD).unwrap());
+    }
+
+    #[test]
+    fn kdbx4_argon2d() {
+        check_entries(&read_fixture("v4_argon2d_aes.kdbx", PASSWORD).unwrap());
+    }
+
+    // the fields of a KDBX 3 header with the given one replaced, a missing value removes the field
+    fn replace_v3_header_field(data: &[u8], field: u8, value: Option<&[u8]>) -> Vec<u8> {
+        let mut res = data[..12].to_vec();
+        let mut pos = 12;
+        loop {
+            let id = data[pos];
+            let size = u16::from_le_bytes([data[pos + 1], data[pos + 2]]) as usize;
+            let end = pos + 3 + size;
+            if id != field {
+                res.extend_from_slice(&data[pos..end]);
+            } else if let Some(v) = value {
+                res.push(id);
+                res.extend_from_slice(&(v.len() as u16).to_le_bytes());
+                res.extend_from_slice(v);
+            }
+            pos = end;
+            if id == 0 {
+                break;
+            }
+        }
+        res.extend_from_slice(&data[pos..]);
+        res
+    }
+
+    #[test]
+    fn kdbx3_without_stream_start_bytes_is_malformed() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/kdbx/v3_aeskdf_salsa20.kdbx");
+        let data = std::fs::read(path).unwrap();
+        assert_eq!(replace_v3_header_field(&data, 200, None), data);
+
+        for value in &[None, Some(&[0u8; 16][..]), Some(&[0u8; 33][..])] {
+            match read_database(&replace_v3_header_field(&data, 9, *value), PASSWORD) {
+                Err(Error::Import(msg)) => assert!(msg.contains("stream start"), "{}", msg),
+                Err(e) => panic!("unexpected error {}", e),
+                Ok(_) => panic!("opened without the stream start bytes"),
+            }
+        }
+    }
+
+    #[test]
+    fn wrong_password_is_an_import_error() {
+        for name in &["v3_aeskdf_salsa20.kdbx", "v4_argon2id_chacha20.kdbx"] {
+            match read_fixture(name, "not the password") {
+                Err(Error::Import(msg)) => assert_eq!(msg, "wrong database password"),
+                Err(e) => panic!("{}: unexpected error {}", name, e),
+                Ok(_) => panic!("{}: opened with the wrong password", name),
+            }
+        }
+    }
+
+    #[test]
+    fn not_a_database() {
+        assert!(read_database(b"not a kdbx file", PASSWORD).is_err());
+    }
+}
@@ -1,5 +1,17 @@
 pub mod transform;
+pub mod entry;
 pub mod generate;
 pub mod export_import;
 pub mod util;
-pub mod cmd;
\ No newline at end of file
+pub mod cmd;
+pub mod repo;
+pub mod error;
+pub mod git;
+pub mod otp;
+pub mod clipboard;
+pub mod qr;
+pub mod kdbx;
+#[cfg(test)]
+mod test_util;
+
+pub use error::Error;
\ No newline at end of file
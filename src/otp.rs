@@ -0,0 +1,350 @@
+// This file deals with one-time passwords (RFC 4226 HOTP and RFC 6238 TOTP) stored in entries as otpauth:// URIs,
+// the format used by pass-otp and most authenticator apps
+extern crate url;
+
+use crate::entry::{Entry, EntryLine, replace_line, append_line};
+use crate::error::Error;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+
+use url::Url;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    fn from_name(name: &str) -> Option<Algorithm> {
+        match name.to_uppercase().as_str() {
+            "SHA1" => Some(Algorithm::Sha1),
+            "SHA256" => Some(Algorithm::Sha256),
+            "SHA512" => Some(Algorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    fn digest(&self) -> MessageDigest {
+        match self {
+            Algorithm::Sha1 => MessageDigest::sha1(),
+            Algorithm::Sha256 => MessageDigest::sha256(),
+            Algorithm::Sha512 => MessageDigest::sha512(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OtpKind {
+    Totp { period: u64 },
+    Hotp { counter: u64 },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OtpParams {
+    pub secret: Vec<u8>,
+    pub algorithm: Algorithm,
+    pub digits: u32,
+    pub kind: OtpKind,
+}
+
+// RFC 4648 base32 without padding. Authenticator secrets are often lower case or grouped with spaces
+pub fn base32_decode(s: &str) -> Result<Vec<u8>, Error> {
+    let mut res = Vec::new();
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+
+    for c in s.chars() {
+        if c == '=' || c == ' ' || c == '-' {
+            continue;
+        }
+        let value = match c.to_ascii_uppercase() {
+            c @ 'A'..='Z' => c as u64 - 'A' as u64,
+            c @ '2'..='7' => c as u64 - '2' as u64 + 26,
+            _ => return Err(Error::InvalidArgument(format!("Invalid character in base32 secret: {}", c))),
+        };
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            res.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    if res.is_empty() {
+        return Err(Error::InvalidArgument("Empty otp secret".to_owned()));
+    }
+    Ok(res)
+}
+
+pub fn parse_uri(uri: &str) -> Result<OtpParams, Error> {
+    let url = match Url::parse(uri.trim()) {
+        Ok(u) => u,
+        Err(e) => return Err(Error::InvalidArgument(format!("Invalid otpauth URI: {}", e))),
+    };
+    if url.scheme() != "otpauth" {
+        return Err(Error::InvalidArgument(format!("Not an otpauth URI: {}", uri)));
+    }
+
+    let mut secret = None;
+    let mut algorithm = Algorithm::Sha1;
+    let mut digits = 6;
+    let mut period = 30;
+    let mut counter = None;
+
+    for (k, v) in url.query_pairs() {
+        match k.to_lowercase().as_str() {
+            "secret" => secret = Some(base32_decode(v.as_ref())?),
+            "algorithm" => {
+                algorithm = match Algorithm::from_name(v.as_ref()) {
+                    Some(a) => a,
+                    None => return Err(Error::InvalidArgument(format!("Unknown otp algorithm: {}", v))),
+                }
+            },
+            "digits" => {
+                digits = match v.parse() {
+                    Ok(d) if (6..=10).contains(&d) => d,
+                    _ => return Err(Error::InvalidArgument(format!("Invalid otp digits: {}", v))),
+                }
+            },
+            "period" => {
+                period = match v.parse() {
+                    Ok(p) if p > 0 => p,
+                    _ => return Err(Error::InvalidArgument(format!("Invalid otp period: {}", v))),
+                }
+            },
+            "counter" => {
+                counter = match v.parse() {
+                    Ok(c) => Some(c),
+                    Err(_) => return Err(Error::InvalidArgument(format!("Invalid otp counter: {}", v))),
+                }
+            },
+            _ => {},
+        }
+    }
+
+    let secret = match secret {
+        Some(s) => s,
+        None => return Err(Error::InvalidArgument("The otpauth URI has no secret".to_owned())),
+    };
+
+    let kind = match url.host_str() {
+        Some("totp") => OtpKind::Totp { period },
+        Some("hotp") => match counter {
+            Some(counter) => OtpKind::Hotp { counter },
+            None => return Err(Error::InvalidArgument("The hotp URI has no counter".to_owned())),
+        },
+        _ => return Err(Error::InvalidArgument(format!("Unknown otp type in: {}", uri))),
+    };
+
+    Ok(OtpParams { secret, algorithm, digits, kind })
+}
+
+// RFC 4226: dynamic truncation of the hmac over the big endian counter
+pub fn hotp(secret: &[u8], counter: u64, digits: u32, algorithm: Algorithm) -> Result<String, Error> {
+    let pkey = PKey::hmac(secret)?;
+    let mut signer = Signer::new(algorithm.digest(), &pkey)?;
+    signer.update(&counter.to_be_bytes())?;
+    let mac = signer.sign_to_vec()?;
+
+    let offset = (mac[mac.len() - 1] & 0x0f) as usize;
+    let code = ((mac[offset] as u64 & 0x7f) << 24)
+        | ((mac[offset + 1] as u64) << 16)
+        | ((mac[offset + 2] as u64) << 8)
+        | (mac[offset + 3] as u64);
+
+    let code = code % 10u64.pow(digits);
+    Ok(format!("{:0width$}", code, width = digits as usize))
+}
+
+// RFC 6238: hotp with the number of periods since the epoch as counter
+pub fn totp_at(params: &OtpParams, unix_time: u64) -> Result<String, Error> {
+    match params.kind {
+        OtpKind::Totp { period } => hotp(&params.secret, unix_time / period, params.digits, params.algorithm),
+        OtpKind::Hotp { .. } => Err(Error::InvalidArgument("Not a totp secret".to_owned())),
+    }
+}
+
+pub fn now() -> u64 {
+    match std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        Ok(d) => d.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+fn is_uri(s: &str) -> bool {
+    s.trim().starts_with("otpauth://")
+}
+
+// the index of the line with the otpauth:// URI (the password is line 0) and the URI. Entries created by pass-otp
+// have it as their first line
+fn find_uri_line(entry: &Entry) -> Option<(usize, String)> {
+    if is_uri(entry.password.as_str()) {
+        return Some((0, entry.password.trim().to_owned()));
+    }
+    for (i, line) in entry.lines.iter().enumerate() {
+        match line {
+            EntryLine::Note(n) if is_uri(n.as_str()) => return Some((i + 1, n.trim().to_owned())),
+            EntryLine::Field(_, v) if is_uri(v.as_str()) => return Some((i + 1, v.trim().to_owned())),
+            _ => {},
+        }
+    }
+    None
+}
+
+// the otpauth:// URI in the entry, on any line
+pub fn find_uri(entry: &Entry) -> Option<String> {
+    find_uri_line(entry).map(|(_, uri)| uri)
+}
+
+// an otpauth:// URI, or a plain base32 secret in a "totp:" or "otp:" field which is used as sha1, 6 digits, 30 seconds totp
+pub fn find_params(entry: &Entry) -> Result<OtpParams, Error> {
+    if let Some(uri) = find_uri(entry) {
+        return parse_uri(uri.as_str());
+    }
+    for name in &["totp", "otp"] {
+        if let Some(secret) = entry.get(name) {
+            return Ok(OtpParams {
+                secret: base32_decode(secret.as_str())?,
+                algorithm: Algorithm::Sha1,
+                digits: 6,
+                kind: OtpKind::Totp { period: 30 },
+            });
+        }
+    }
+    Err(Error::NotFound("no otpauth URI or totp secret in the entry".to_owned()))
+}
+
+// replaces the counter in the hotp URI of the entry content, it has to be stored after every use
+pub fn set_hotp_counter(content: &str, counter: u64) -> Result<String, Error> {
+    let uri = match find_uri(&Entry::parse(content)) {
+        Some(u) => u,
+        None => return Err(Error::NotFound("no otpauth URI in the entry".to_owned())),
+    };
+    let mut url = match Url::parse(uri.as_str()) {
+        Ok(u) => u,
+        Err(e) => return Err(Error::InvalidArgument(format!("Invalid otpauth URI: {}", e))),
+    };
+
+    let pairs: Vec<(String, String)> = url.query_pairs()
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .map(|(k, v)| if k == "counter" { (k, counter.to_string()) } else { (k, v) })
+        .collect();
+    url.query_pairs_mut().clear().extend_pairs(pairs);
+    let new_uri = url.into_string();
+
+    Ok(replace_uri(content, new_uri.as_str()))
+}
+
+// replaces the otpauth:// URI in the entry content or adds it as a new line. Only the URI changes, the rest of the
+// content stays byte for byte
+pub fn replace_uri(content: &str, uri: &str) -> String {
+    match find_uri_line(&Entry::parse(content)) {
+        Some((index, old)) => {
+            let line = content.lines().nth(index).unwrap_or("");
+            replace_line(content, index, line.replacen(old.as_str(), uri, 1).as_str())
+        },
+        None => append_line(content, uri),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the seeds of RFC 6238 appendix B, the key length matches the hash
+    const SEED_SHA1: &[u8] = b"12345678901234567890";
+    const SEED_SHA256: &[u8] = b"12345678901234567890123456789012";
+    const SEED_SHA512: &[u8] = b"1234567890123456789012345678901234567890123456789012345678901234";
+
+    fn totp(secret: &[u8], algorithm: Algorithm) -> OtpParams {
+        OtpParams { secret: secret.to_vec(), algorithm, digits: 8, kind: OtpKind::Totp { period: 30 } }
+    }
+
+    #[test]
+    fn rfc6238_test_vectors() {
+        let vectors: &[(u64, &str, &str, &str)] = &[
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ];
+        for (time, sha1, sha256, sha512) in vectors {
+            assert_eq!(totp_at(&totp(SEED_SHA1, Algorithm::Sha1), *time).unwrap(), *sha1, "sha1 at {}", time);
+            assert_eq!(totp_at(&totp(SEED_SHA256, Algorithm::Sha256), *time).unwrap(), *sha256, "sha256 at {}", time);
+            assert_eq!(totp_at(&totp(SEED_SHA512, Algorithm::Sha512), *time).unwrap(), *sha512, "sha512 at {}", time);
+        }
+    }
+
+    #[test]
+    fn rfc4226_test_vectors() {
+        let codes = ["755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583", "399871", "520489"];
+        for (counter, code) in codes.iter().enumerate() {
+            assert_eq!(hotp(SEED_SHA1, counter as u64, 6, Algorithm::Sha1).unwrap(), *code);
+        }
+    }
+
+    #[test]
+    fn base32() {
+        assert_eq!(base32_decode("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap(), SEED_SHA1);
+        assert_eq!(base32_decode("gezd gnbv-gy3t qojq gezd gnbv gy3t qojq").unwrap(), SEED_SHA1);
+        assert_eq!(base32_decode("MZXW6===").unwrap(), b"foo");
+        assert_eq!(base32_decode("MZXW6YQ").unwrap(), b"foob");
+        assert!(base32_decode("MZXW1").is_err());
+        assert!(base32_decode("").is_err());
+        assert!(base32_decode("==").is_err());
+    }
+
+    #[test]
+    fn parse_totp_uri() {
+        let params = parse_uri("otpauth://totp/Example:alice@example.com?secret=JBSWY3DPEHPK3PXP&issuer=Example").unwrap();
+        assert_eq!(params.secret, b"Hello!\xde\xad\xbe\xef");
+        assert_eq!(params.algorithm, Algorithm::Sha1);
+        assert_eq!(params.digits, 6);
+        assert_eq!(params.kind, OtpKind::Totp { period: 30 });
+
+        let params = parse_uri(" otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&algorithm=sha512&digits=8&period=60 ").unwrap();
+        assert_eq!(params.algorithm, Algorithm::Sha512);
+        assert_eq!(params.digits, 8);
+        assert_eq!(params.kind, OtpKind::Totp { period: 60 });
+    }
+
+    #[test]
+    fn parse_hotp_uri() {
+        let params = parse_uri("otpauth://hotp/x?secret=JBSWY3DPEHPK3PXP&counter=7").unwrap();
+        assert_eq!(params.kind, OtpKind::Hotp { counter: 7 });
+        assert!(parse_uri("otpauth://hotp/x?secret=JBSWY3DPEHPK3PXP").is_err());
+    }
+
+    #[test]
+    fn parse_invalid_uris() {
+        assert!(parse_uri("https://example.com/?secret=JBSWY3DPEHPK3PXP").is_err());
+        assert!(parse_uri("otpauth://totp/x").is_err());
+        assert!(parse_uri("otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&algorithm=md5").is_err());
+        assert!(parse_uri("otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&digits=5").is_err());
+        assert!(parse_uri("otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&period=0").is_err());
+        assert!(parse_uri("otpauth://motp/x?secret=JBSWY3DPEHPK3PXP").is_err());
+        assert!(parse_uri("not a uri").is_err());
+    }
+
+    #[test]
+    fn replace_uri_keeps_the_rest() {
+        let content = "pw\nkey:   spaced  \notp:  otpauth://totp/x?secret=AA  \nnotes\n";
+        assert_eq!(replace_uri(content, "otpauth://totp/y?secret=BB"), "pw\nkey:   spaced  \notp:  otpauth://totp/y?secret=BB  \nnotes\n");
+        assert_eq!(replace_uri("otpauth://totp/x?secret=AA\nnote", "otpauth://totp/y"), "otpauth://totp/y\nnote");
+        assert_eq!(replace_uri("pw\nkey:   spaced  ", "otpauth://totp/y"), "pw\nkey:   spaced  \notpauth://totp/y");
+    }
+
+    #[test]
+    fn hotp_counter_is_updated_in_place() {
+        let content = "pw\nuser:  bob \notpauth://hotp/x?secret=JBSWY3DPEHPK3PXP&counter=7\n";
+        let updated = set_hotp_counter(content, 8).unwrap();
+        assert_eq!(updated, "pw\nuser:  bob \notpauth://hotp/x?secret=JBSWY3DPEHPK3PXP&counter=8\n");
+        assert_eq!(parse_uri(find_uri(&Entry::parse(updated.as_str())).unwrap().as_str()).unwrap().kind, OtpKind::Hotp { counter: 8 });
+    }
+}
@@ -0,0 +1,145 @@
+// This file renders content as QR code, for the terminal or as svg/png file, e.g. to move a secret to a phone
+extern crate png;
+extern crate qrcode;
+
+use crate::error::Error;
+use crate::util;
+
+use qrcode::{Color, QrCode};
+
+use std::path;
+
+// modules of light border around the code, scanners need it to find the code
+const QUIET_ZONE: usize = 2;
+const FILE_QUIET_ZONE: usize = 4;
+const PNG_MODULE_PIXELS: usize = 8;
+
+fn encode(content: &str) -> Result<QrCode, Error> {
+    match QrCode::new(content.as_bytes()) {
+        Ok(c) => Ok(c),
+        Err(e) => Err(Error::InvalidArgument(format!("Can't encode as QR code: {}", e))),
+    }
+}
+
+// dark modules with a border of light ones, row by row
+fn modules(code: &QrCode, quiet_zone: usize) -> Vec<Vec<bool>> {
+    let width = code.width();
+    let colors = code.to_colors();
+    let size = width + 2 * quiet_zone;
+
+    let mut rows = vec![vec![false; size]; size];
+    for y in 0..width {
+        for x in 0..width {
+            rows[y + quiet_zone][x + quiet_zone] = colors[y * width + x] == Color::Dark;
+        }
+    }
+    rows
+}
+
+// two rows of modules per line of text using half blocks. The blocks are drawn for the light modules,
+// so the code reads right on terminals with light text on a dark background
+pub fn render_terminal(content: &str) -> Result<String, Error> {
+    let rows = modules(&encode(content)?, QUIET_ZONE);
+
+    let mut res = String::new();
+    for pair in rows.chunks(2) {
+        for x in 0..pair[0].len() {
+            let top_light = !pair[0][x];
+            let bottom_light = pair.len() > 1 && !pair[1][x];
+            res.push(match (top_light, bottom_light) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            });
+        }
+        res.push('\n');
+    }
+    Ok(res)
+}
+
+// the format is chosen by the extension, .svg or .png
+pub fn write_file(content: &str, p: &path::Path) -> Result<(), Error> {
+    let extension = p.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase());
+    match extension.as_deref() {
+        Some("svg") => write_svg(content, p),
+        Some("png") => write_png(content, p),
+        _ => Err(Error::InvalidArgument(format!("Unknown QR code file type, use .svg or .png: {}", p.display()))),
+    }
+}
+
+fn write_svg(content: &str, p: &path::Path) -> Result<(), Error> {
+    let code = encode(content)?;
+    let svg = code.render::<qrcode::render::svg::Color>()
+        .quiet_zone(true)
+        .min_dimensions(256, 256)
+        .build();
+    write_private(p, svg.as_bytes())
+}
+
+fn write_png(content: &str, p: &path::Path) -> Result<(), Error> {
+    let rows = modules(&encode(content)?, FILE_QUIET_ZONE);
+    let size = rows.len() * PNG_MODULE_PIXELS;
+
+    let mut pixels = Vec::with_capacity(size * size);
+    for row in &rows {
+        let mut line = Vec::with_capacity(size);
+        for dark in row {
+            let value = if *dark { 0u8 } else { 255u8 };
+            line.extend(std::iter::repeat_n(value, PNG_MODULE_PIXELS));
+        }
+        for _ in 0..PNG_MODULE_PIXELS {
+            pixels.extend_from_slice(&line);
+        }
+    }
+
+    let mut data = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut data, size as u32, size as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let result = encoder.write_header().and_then(|mut w| w.write_image_data(&pixels));
+        if let Err(e) = result {
+            return Err(Error::Io(std::io::Error::other(format!("Writing the png failed: {}", e))));
+        }
+    }
+    write_private(p, &data)
+}
+
+// the file holds a secret, so only the owner may read it. The mode only applies to new files, so the code is written to
+// a new file that replaces an existing one, which might be readable by others
+fn write_private(p: &path::Path, data: &[u8]) -> Result<(), Error> {
+    util::write_file_atomic_with_mode(p, data, 0o600)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn mode(p: &path::Path) -> u32 {
+        std::fs::metadata(p).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn files_are_private() {
+        let dir = TempDir::new("qr");
+        for name in &["code.svg", "code.PNG"] {
+            let p = dir.path().join(name);
+            //an existing file readable by others is replaced, not written to
+            std::fs::write(&p, "old").unwrap();
+            std::fs::set_permissions(&p, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+            write_file("WIFI:S:home;T:WPA;P:secret;;", &p).unwrap();
+            assert_eq!(mode(&p), 0o600);
+            assert_ne!(std::fs::read(&p).unwrap(), b"old");
+        }
+        assert!(std::fs::read_to_string(dir.path().join("code.svg")).unwrap().contains("<svg"));
+        assert_eq!(&std::fs::read(dir.path().join("code.PNG")).unwrap()[1..4], b"PNG");
+
+        assert!(write_file("x", &dir.path().join("code.txt")).is_err());
+        //no temp files are left behind
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+}
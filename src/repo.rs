@@ -0,0 +1,526 @@
+// This file deals with the repo header. It holds the format version, the cipher, the salt and the parameters used to derive
+// the key from the master password and a value to check the key against
+extern crate argon2;
+extern crate base64;
+
+use crate::transform;
+use crate::util;
+use crate::error::Error;
+use openssl::hash::MessageDigest;
+use openssl::pkcs5::{pbkdf2_hmac, scrypt};
+use openssl::sha::sha256;
+
+use std::convert::TryFrom;
+use std::fs;
+use std::path;
+
+// the header lives in the repo root. Encrypted names are url safe base64 and never start with a '.'
+pub static HEADER_FILE: &str = ".sparkpass_repo";
+
+// repos without a header are version 1, version 2 headers have no name scheme and use cbc names
+pub const REPO_VERSION: u32 = 3;
+const LEGACY_REPO_VERSION: u32 = 1;
+
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+
+// encrypted into the header on init. If it decrypts the key is right
+static KEY_CHECK_CONTENT: &str = "sparkpass key check";
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Kdf {
+    //only used for repos created before the header existed
+    Sha256,
+    Pbkdf2 { iterations: u32 },
+    Scrypt { n: u64, r: u64, p: u64 },
+    Argon2id { mem_kib: u32, iterations: u32, lanes: u32 },
+}
+
+impl Kdf {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kdf::Sha256 => "sha256",
+            Kdf::Pbkdf2 { .. } => "pbkdf2",
+            Kdf::Scrypt { .. } => "scrypt",
+            Kdf::Argon2id { .. } => "argon2id",
+        }
+    }
+
+    pub fn default_for(name: &str) -> Result<Kdf, Error> {
+        match name {
+            "pbkdf2" => Ok(Kdf::Pbkdf2 { iterations: 600_000 }),
+            "scrypt" => Ok(Kdf::Scrypt { n: 1 << 15, r: 8, p: 1 }),
+            "argon2id" => Ok(Kdf::Argon2id { mem_kib: 64 * 1024, iterations: 3, lanes: 1 }),
+            _ => Err(Error::InvalidArgument(format!("Unknown kdf: {}. Known are: argon2id, scrypt, pbkdf2", name))),
+        }
+    }
+
+    // the kdf asked for with --kdf and --kdf-params. Without a name the default, argon2id, is used
+    pub fn from_args(name: &str, params: &str) -> Result<Kdf, Error> {
+        let name = if name.is_empty() { "argon2id" } else { name };
+        Kdf::default_for(name)?.with_params(params)
+    }
+
+    // params are given as "key=value,key=value", e.g. "m=65536,t=3,p=1" for argon2id
+    pub fn with_params(&self, params: &str) -> Result<Kdf, Error> {
+        let mut kdf = self.clone();
+        for param in params.split(",").filter(|p| !p.trim().is_empty()) {
+            let (k, v) = match split_key_value(param) {
+                Some(kv) => kv,
+                None => return Err(Error::InvalidArgument(format!("Malformed kdf parameter: {}", param))),
+            };
+            kdf.set_param(k, v)?;
+        }
+        Ok(kdf)
+    }
+
+    fn set_param(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        let num: u64 = match value.parse() {
+            Ok(n) => n,
+            Err(e) => return Err(Error::InvalidArgument(format!("Kdf parameter {} is not a number: {}", key, e))),
+        };
+
+        //a value that doesn't fit must not wrap around to a weaker parameter
+        let num32 = || match u32::try_from(num) {
+            Ok(n) => Ok(n),
+            Err(_) => Err(Error::InvalidArgument(format!("Kdf parameter {} is too large: {}", key, num))),
+        };
+
+        match (self, key) {
+            (Kdf::Pbkdf2 { iterations }, "i") => *iterations = num32()?,
+            (Kdf::Scrypt { n, .. }, "n") => *n = num,
+            (Kdf::Scrypt { r, .. }, "r") => *r = num,
+            (Kdf::Scrypt { p, .. }, "p") => *p = num,
+            (Kdf::Argon2id { mem_kib, .. }, "m") => *mem_kib = num32()?,
+            (Kdf::Argon2id { iterations, .. }, "t") => *iterations = num32()?,
+            (Kdf::Argon2id { lanes, .. }, "p") => *lanes = num32()?,
+            (kdf, _) => return Err(Error::InvalidArgument(format!("Unknown parameter for {}: {}", kdf.name(), key))),
+        }
+        Ok(())
+    }
+
+    fn params(&self) -> Vec<(&'static str, u64)> {
+        match self {
+            Kdf::Sha256 => vec![],
+            Kdf::Pbkdf2 { iterations } => vec![("i", *iterations as u64)],
+            Kdf::Scrypt { n, r, p } => vec![("n", *n), ("r", *r), ("p", *p)],
+            Kdf::Argon2id { mem_kib, iterations, lanes } => vec![
+                ("m", *mem_kib as u64),
+                ("t", *iterations as u64),
+                ("p", *lanes as u64),
+            ],
+        }
+    }
+}
+
+pub struct RepoHeader {
+    pub version: u32,
+    pub cipher: transform::EntryCipher,
+    pub names: transform::NameScheme,
+    pub kdf: Kdf,
+    pub salt: Vec<u8>,
+    //legacy repos have nothing to check the key against
+    pub check: Option<String>,
+}
+
+fn split_key_value(line: &str) -> Option<(&str, &str)> {
+    let mut split = line.splitn(2, "=");
+    match (split.next(), split.next()) {
+        (Some(k), Some(v)) => Some((k.trim(), v.trim())),
+        _ => None,
+    }
+}
+
+impl RepoHeader {
+    // creates a header with a random salt and the check value for the key derived from the password
+    pub fn new(kdf: Kdf, cipher: transform::EntryCipher, password: &[u8]) -> Result<RepoHeader, Error> {
+        let mut header = RepoHeader {
+            version: REPO_VERSION,
+            cipher,
+            names: transform::NameScheme::Siv,
+            kdf,
+            salt: transform::get_random_bytes(SALT_LEN)?,
+            check: None,
+        };
+
+        let key = header.derive_key(password)?;
+        let enc_params = header.encryption_params(&key);
+        header.check = Some(transform::transform_entry_with_random_iv(&enc_params, KEY_CHECK_CONTENT)?);
+
+        Ok(header)
+    }
+
+    //repos without a header were created before it existed and use a plain sha256 of the password
+    pub fn legacy() -> RepoHeader {
+        RepoHeader {
+            version: LEGACY_REPO_VERSION,
+            cipher: transform::EntryCipher::Aes256Gcm,
+            names: transform::NameScheme::Cbc,
+            kdf: Kdf::Sha256,
+            salt: Vec::new(),
+            check: None,
+        }
+    }
+
+    pub fn encryption_params<'a>(&self, key: &'a [u8]) -> transform::EncryptionParams<'a> {
+        transform::EncryptionParams {
+            key,
+            //this iv is only used for encrypting the path. This must unfortunately be deterministic.
+            iv: transform::DEFAULT_IV,
+            cipher: self.cipher,
+            names: self.names,
+        }
+    }
+
+    pub fn read(repo: &path::Path) -> Result<Option<RepoHeader>, Error> {
+        let header_path = repo.join(HEADER_FILE);
+        if !header_path.exists() {
+            return Ok(None);
+        }
+
+        let content = match fs::read_to_string(header_path) {
+            Ok(c) => c,
+            Err(e) => return Err(Error::Io(e)),
+        };
+
+        let mut fields = Vec::new();
+        for line in content.lines().filter(|l| !l.trim().is_empty()) {
+            match split_key_value(line) {
+                Some(kv) => fields.push(kv),
+                None => return Err(Error::Header(format!("malformed line: {}", line))),
+            }
+        }
+
+        //check the version first, newer versions might have fields this version doesn't know
+        let version = match fields.iter().find(|(k, _)| *k == "version") {
+            Some((_, v)) => match v.parse() {
+                Ok(n) => n,
+                Err(_) => return Err(Error::Header(format!("malformed version: {}", v))),
+            },
+            None => return Err(Error::Header("no version".to_owned())),
+        };
+        if version > REPO_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        //version 1 repos have no header, so a header is at least version 2
+        if version <= LEGACY_REPO_VERSION {
+            return Err(Error::Header(format!("invalid version: {}", version)));
+        }
+
+        let mut cipher = None;
+        let mut names = transform::NameScheme::Cbc;
+        let mut kdf = None;
+        let mut salt = None;
+        let mut check = None;
+        let mut params = Vec::new();
+
+        for (k, v) in fields {
+            match k {
+                "version" => {}
+                "cipher" => {
+                    cipher = match transform::EntryCipher::from_name(v) {
+                        Some(c) => Some(c),
+                        None => return Err(Error::Header(format!("unknown cipher: {}", v))),
+                    }
+                }
+                "names" => {
+                    names = match transform::NameScheme::from_name(v) {
+                        Some(n) => n,
+                        None => return Err(Error::Header(format!("unknown name scheme: {}", v))),
+                    }
+                }
+                "check" => check = Some(v.to_owned()),
+                "kdf" => {
+                    kdf = match Kdf::default_for(v) {
+                        Ok(k) => Some(k),
+                        Err(_) => return Err(Error::Header(format!("unknown kdf: {}", v))),
+                    }
+                }
+                "salt" => {
+                    salt = match base64::decode_config(v, base64::URL_SAFE) {
+                        Ok(s) => Some(s),
+                        Err(_) => return Err(Error::Header("malformed salt".to_owned())),
+                    }
+                }
+                _ => params.push((k, v)),
+            }
+        }
+
+        let cipher = match cipher {
+            Some(c) => c,
+            None => return Err(Error::Header("no cipher".to_owned())),
+        };
+
+        let mut kdf = match kdf {
+            Some(k) => k,
+            None => return Err(Error::Header("no kdf".to_owned())),
+        };
+        for (k, v) in params {
+            if let Err(e) = kdf.set_param(k, v) {
+                return Err(Error::Header(e.to_string()));
+            }
+        }
+
+        let salt = match salt {
+            Some(s) => s,
+            None => return Err(Error::Header("no salt".to_owned())),
+        };
+
+        //without it any password would be accepted and entries written with a wrong key
+        if check.is_none() {
+            return Err(Error::Header("no check".to_owned()));
+        }
+
+        Ok(Some(RepoHeader { version, cipher, names, kdf, salt, check }))
+    }
+
+    pub fn write(&self, repo: &path::Path) -> Result<(), Error> {
+        let mut content = String::new();
+        content.push_str(format!("version={}\n", self.version).as_str());
+        content.push_str(format!("cipher={}\n", self.cipher.name()).as_str());
+        content.push_str(format!("names={}\n", self.names.name()).as_str());
+        content.push_str(format!("kdf={}\n", self.kdf.name()).as_str());
+        content.push_str(format!("salt={}\n", base64::encode_config(&self.salt, base64::URL_SAFE)).as_str());
+        for (k, v) in self.kdf.params() {
+            content.push_str(format!("{}={}\n", k, v).as_str());
+        }
+        if let Some(check) = &self.check {
+            content.push_str(format!("check={}\n", check).as_str());
+        }
+
+        util::write_file_atomic(repo.join(HEADER_FILE).as_path(), content.as_bytes())
+    }
+
+    pub fn derive_key(&self, password: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut key = vec![0u8; KEY_LEN];
+        match self.kdf {
+            Kdf::Sha256 => {
+                key = sha256(password).to_vec();
+            }
+            Kdf::Pbkdf2 { iterations } => {
+                if let Err(e) = pbkdf2_hmac(password, &self.salt, iterations as usize, MessageDigest::sha256(), &mut key) {
+                    return Err(Error::InvalidArgument(format!("Key derivation failed: {}", e)));
+                }
+            }
+            Kdf::Scrypt { n, r, p } => {
+                //openssl refuses to use more than maxmem, so allow what the parameters need
+                let maxmem = match 128u64.checked_mul(n)
+                    .and_then(|m| m.checked_mul(r))
+                    .and_then(|m| m.checked_mul(p))
+                    .and_then(|m| m.checked_add(1024 * 1024)) {
+                    Some(m) => m,
+                    None => return Err(Error::InvalidArgument(format!("Scrypt parameters are too large: n={}, r={}, p={}", n, r, p))),
+                };
+                if let Err(e) = scrypt(password, &self.salt, n, r, p, maxmem, &mut key) {
+                    return Err(Error::InvalidArgument(format!("Key derivation failed: {}", e)));
+                }
+            }
+            Kdf::Argon2id { mem_kib, iterations, lanes } => {
+                let config = argon2::Config {
+                    variant: argon2::Variant::Argon2id,
+                    version: argon2::Version::Version13,
+                    mem_cost: mem_kib,
+                    time_cost: iterations,
+                    lanes,
+                    secret: &[],
+                    ad: &[],
+                    hash_length: KEY_LEN as u32,
+                };
+                key = match argon2::hash_raw(password, &self.salt, &config) {
+                    Ok(k) => k,
+                    Err(e) => return Err(Error::InvalidArgument(format!("Key derivation failed: {}", e))),
+                };
+            }
+        }
+        Ok(key)
+    }
+
+    pub fn check_key(&self, key: &[u8]) -> Result<(), Error> {
+        let check = match &self.check {
+            Some(c) => c,
+            None if self.version == LEGACY_REPO_VERSION => return Ok(()),
+            None => return Err(Error::Header("no check".to_owned())),
+        };
+
+        let enc_params = self.encryption_params(key);
+        match transform::retransform_entry_with_random_iv(&enc_params, check.as_str()) {
+            Ok(ref content) if content == KEY_CHECK_CONTENT => Ok(()),
+            _ => Err(Error::WrongKey),
+        }
+    }
+}
+
+pub fn is_initialized(repo: &path::Path) -> bool {
+    repo.join(HEADER_FILE).exists()
+}
+
+// creates the repo dir and writes a fresh header with a random salt
+pub fn init_repo(repo: &path::Path, kdf: Kdf, cipher: transform::EntryCipher, password: &[u8]) -> Result<RepoHeader, Error> {
+    if is_initialized(repo) {
+        return Err(Error::Exists(format!("repo {}", repo.display())));
+    }
+
+    //a repo with entries but no header uses the legacy key derivation. A new header would lock those entries out
+    if let Ok(mut it) = fs::read_dir(repo) {
+        if it.next().is_some() {
+            return Err(Error::InvalidArgument(format!("Repo already contains entries: {}", repo.display())));
+        }
+    }
+
+    fs::create_dir_all(repo)?;
+
+    let header = RepoHeader::new(kdf, cipher, password)?;
+    header.write(repo)?;
+    Ok(header)
+}
+
+// reads the header of the repo (if any), derives the key for the password and checks it
+pub fn unlock(repo: &path::Path, password: &[u8]) -> Result<(RepoHeader, Vec<u8>), Error> {
+    let header = match RepoHeader::read(repo)? {
+        Some(h) => h,
+        None => RepoHeader::legacy(),
+    };
+    let key = header.derive_key(password)?;
+    header.check_key(&key)?;
+    Ok((header, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    // cheap parameters, the tests only need the key derivation to run
+    fn cheap_kdfs() -> Vec<Kdf> {
+        vec![
+            Kdf::Pbkdf2 { iterations: 1000 },
+            Kdf::Scrypt { n: 1024, r: 8, p: 1 },
+            Kdf::Argon2id { mem_kib: 1024, iterations: 1, lanes: 1 },
+        ]
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn write_header(dir: &TempDir, content: &str) {
+        fs::write(dir.path().join(HEADER_FILE), content).unwrap();
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let dir = TempDir::new("header-round-trip");
+        for kdf in cheap_kdfs() {
+            for cipher in &[transform::EntryCipher::Aes256Gcm, transform::EntryCipher::ChaCha20Poly1305] {
+                let header = RepoHeader::new(kdf.clone(), *cipher, b"pw").unwrap();
+                header.write(dir.path()).unwrap();
+
+                let read = RepoHeader::read(dir.path()).unwrap().unwrap();
+                assert_eq!(read.version, REPO_VERSION);
+                assert_eq!(read.cipher, *cipher);
+                assert_eq!(read.names, transform::NameScheme::Siv);
+                assert_eq!(read.kdf, kdf);
+                assert_eq!(read.salt, header.salt);
+                assert_eq!(read.check, header.check);
+            }
+        }
+    }
+
+    #[test]
+    fn no_header() {
+        let dir = TempDir::new("no-header");
+        assert!(RepoHeader::read(dir.path()).unwrap().is_none());
+    }
+
+    #[test]
+    fn kdfs_derive_known_keys() {
+        //the first 32 bytes of the test vectors of RFC 7914
+        let header = |kdf, salt: &[u8]| RepoHeader { salt: salt.to_vec(), kdf, ..RepoHeader::legacy() };
+        let pbkdf2 = header(Kdf::Pbkdf2 { iterations: 1 }, b"salt").derive_key(b"passwd").unwrap();
+        assert_eq!(hex(&pbkdf2), "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc");
+        let scrypt = header(Kdf::Scrypt { n: 1024, r: 8, p: 16 }, b"NaCl").derive_key(b"password").unwrap();
+        assert_eq!(hex(&scrypt), "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162");
+
+        let sha = header(Kdf::Sha256, b"").derive_key(b"abc").unwrap();
+        assert_eq!(hex(&sha), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    #[test]
+    fn kdfs_depend_on_password_and_salt() {
+        for kdf in cheap_kdfs() {
+            let header = RepoHeader { salt: b"0123456789abcdef".to_vec(), kdf: kdf.clone(), ..RepoHeader::legacy() };
+            let key = header.derive_key(b"pw").unwrap();
+            assert_eq!(key.len(), KEY_LEN);
+            assert_eq!(header.derive_key(b"pw").unwrap(), key, "{}", kdf.name());
+            assert_ne!(header.derive_key(b"pw2").unwrap(), key, "{}", kdf.name());
+
+            let salted = RepoHeader { salt: b"fedcba9876543210".to_vec(), ..header };
+            assert_ne!(salted.derive_key(b"pw").unwrap(), key, "{}", kdf.name());
+        }
+    }
+
+    #[test]
+    fn wrong_password_is_detected() {
+        let dir = TempDir::new("wrong-password");
+        let repo = dir.path().join("repo");
+        init_repo(&repo, cheap_kdfs().remove(2), transform::EntryCipher::Aes256Gcm, b"right").unwrap();
+
+        let (_, key) = unlock(&repo, b"right").unwrap();
+        assert_eq!(key.len(), KEY_LEN);
+        match unlock(&repo, b"wrong") {
+            Err(Error::WrongKey) => {},
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        assert!(init_repo(&repo, Kdf::Sha256, transform::EntryCipher::Aes256Gcm, b"right").is_err());
+    }
+
+    #[test]
+    fn malformed_headers() {
+        let dir = TempDir::new("malformed-header");
+        let valid = "version=3\ncipher=aes-256-gcm\nnames=siv\nkdf=pbkdf2\nsalt=AAAA\ni=1000\ncheck=x\n";
+        write_header(&dir, valid);
+        assert!(RepoHeader::read(dir.path()).is_ok());
+
+        let cases = [
+            valid.replace("check=x\n", ""),
+            valid.replace("version=3", "version=0"),
+            valid.replace("version=3", "version=1"),
+            valid.replace("version=3", "version=-1"),
+            valid.replace("cipher=aes-256-gcm\n", ""),
+            valid.replace("kdf=pbkdf2", "kdf=md5"),
+            valid.replace("i=1000", "i=4294967296"),
+            valid.replace("salt=AAAA", "salt=!"),
+            valid.replace("names=siv", "names=rot13"),
+        ];
+        for content in &cases {
+            write_header(&dir, content);
+            match RepoHeader::read(dir.path()) {
+                Err(Error::Header(_)) => {},
+                r => panic!("{:?}: unexpected result {:?}", content, r.map(|_| ())),
+            }
+        }
+
+        write_header(&dir, &valid.replace("version=3", "version=4"));
+        match RepoHeader::read(dir.path()) {
+            Err(Error::UnsupportedVersion(4)) => {},
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn kdf_params() {
+        let argon = Kdf::default_for("argon2id").unwrap().with_params("m=1024, t=2,p=4").unwrap();
+        assert_eq!(argon, Kdf::Argon2id { mem_kib: 1024, iterations: 2, lanes: 4 });
+        assert!(argon.with_params("m=4294967296").is_err());
+        assert!(argon.with_params("n=1024").is_err());
+        assert!(argon.with_params("m").is_err());
+        assert!(Kdf::default_for("pbkdf2").unwrap().with_params("i=4294967296").is_err());
+
+        //parameters whose memory doesn't fit in 64 bits fail instead of wrapping around
+        let huge = RepoHeader { salt: b"salt".to_vec(), kdf: Kdf::Scrypt { n: 1 << 40, r: 1 << 20, p: 1 << 10 }, ..RepoHeader::legacy() };
+        match huge.derive_key(b"pw") {
+            Err(Error::InvalidArgument(_)) => {},
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+}
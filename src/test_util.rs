@@ -0,0 +1,63 @@
+// Helpers for the unit tests that need a repo on disk
+use crate::transform::{EncryptionParams, EntryCipher, NameScheme, DEFAULT_IV};
+use crate::util::{add_entry, flatten_tree, get_tree_from_path, prepare_entry_path, show_entry, TreeNode};
+use std::path::{Path, PathBuf};
+
+const KEY: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+pub fn enc_params() -> EncryptionParams<'static> {
+    EncryptionParams { key: KEY, iv: DEFAULT_IV, cipher: EntryCipher::Aes256Gcm, names: NameScheme::Siv }
+}
+
+// an empty dir in the temp dir, removed with everything in it when dropped
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("sparkpass-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+pub fn add(prefix: &Path, path: &str, content: &str) {
+    add_entry(prefix, Path::new(path), content, false, &enc_params()).unwrap();
+}
+
+pub fn show(prefix: &Path, path: &str) -> String {
+    show_entry(prefix, Path::new(path), &enc_params()).unwrap()
+}
+
+// the clear paths and contents of all entries in the repo, sorted by path
+pub fn entries(prefix: &Path) -> Vec<(String, String)> {
+    let mut res = Vec::new();
+    if let TreeNode::Node(_, children) = get_tree_from_path(prefix, None, &enc_params()).unwrap() {
+        for c in &children {
+            for e in flatten_tree(c, "".to_owned()) {
+                let path = prepare_entry_path(e.as_str()).to_owned();
+                let content = show(prefix, path.as_str());
+                res.push((path, content));
+            }
+        }
+    }
+    res.sort();
+    res
+}
+
+// entries() with borrowed strings, for comparing against literals
+pub fn expect(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+    entries.iter().map(|(p, c)| (p.to_string(), c.to_string())).collect()
+}
@@ -1,18 +1,92 @@
 
 // This file provides methods to transform the entries to the format they use on disk
-use openssl::symm::{decrypt, encrypt, Cipher};
+use openssl::symm::{decrypt, decrypt_aead, encrypt, encrypt_aead, Cipher};
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use crate::error::Error;
 extern crate base64;
 use std::io::Read;
 
 pub static DEFAULT_IV: &[u8] = b"\x00\x01\x02\x03\x04\x05\x06\x07\x00\x01\x02\x03\x04\x05\x06\x07";
 
+// version marker at the start of entry contents written with an AEAD cipher.
+// Contents without it are in the old cipher~mac~iv layout
+pub static ENTRY_FORMAT_VERSION: &str = "2";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EntryCipher {
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+impl EntryCipher {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EntryCipher::Aes256Gcm => "aes-256-gcm",
+            EntryCipher::ChaCha20Poly1305 => "chacha20-poly1305",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<EntryCipher> {
+        match name {
+            "aes-256-gcm" => Some(EntryCipher::Aes256Gcm),
+            "chacha20-poly1305" => Some(EntryCipher::ChaCha20Poly1305),
+            _ => None,
+        }
+    }
+
+    fn openssl_cipher(&self) -> Cipher {
+        match self {
+            EntryCipher::Aes256Gcm => Cipher::aes_256_gcm(),
+            EntryCipher::ChaCha20Poly1305 => Cipher::chacha20_poly1305(),
+        }
+    }
+}
+
+// both ciphers use a 96 bit nonce and a 128 bit tag
+const AEAD_IV_LEN: usize = 12;
+const AEAD_TAG_LEN: usize = 16;
+
+// how the entry names are encrypted. This is fixed per repo
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NameScheme {
+    //aes_256_cbc under the fixed DEFAULT_IV. Equal names encrypt to equal file names everywhere in the repo
+    Cbc,
+    //SIV style: the iv is a hmac over the parent path and the name, so equal names under different parents differ
+    Siv,
+}
+
+impl NameScheme {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NameScheme::Cbc => "cbc",
+            NameScheme::Siv => "siv",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<NameScheme> {
+        match name {
+            "cbc" => Some(NameScheme::Cbc),
+            "siv" => Some(NameScheme::Siv),
+            _ => None,
+        }
+    }
+}
+
+const SIV_LEN: usize = 16;
+
 pub struct EncryptionParams<'a> {
     pub key: &'a [u8],
     pub iv: &'a [u8],
+    //cipher used for new entry contents. Reading detects the cipher from the entry itself
+    pub cipher: EntryCipher,
+    pub names: NameScheme,
 }
 
 //from clear to encrypted
-pub fn transform_entry(enc_params: &EncryptionParams, entry: &str)-> String {
+pub fn transform_entry(enc_params: &EncryptionParams, entry: &str)-> Result<String, Error> {
     let cipher = Cipher::aes_256_cbc();
 
     let ciphertext = encrypt(
@@ -20,7 +94,7 @@ pub fn transform_entry(enc_params: &EncryptionParams, entry: &str)-> String {
         enc_params.key,
         Some(enc_params.iv),
         entry.as_bytes(),
-    ).unwrap();
+    )?;
 
     let mac_cipher = Cipher::aes_256_cbc();
     let ciphertext_mac = encrypt(
@@ -28,75 +102,90 @@ pub fn transform_entry(enc_params: &EncryptionParams, entry: &str)-> String {
         enc_params.key,
         Some(enc_params.iv),
         ciphertext.as_slice(),
-    ).unwrap();
+    )?;
 
     let cipher_part = base64::encode_config(&ciphertext, base64::URL_SAFE);
     let mac_part = base64::encode_config(&ciphertext_mac, base64::URL_SAFE);
 
-    let mut content = String::from(cipher_part);
+    let mut content = cipher_part;
     content.push('~'); // + does not appear in url safe base64 but is till url safe
     content.push_str(mac_part.as_str());
 
-    return content;
+    Ok(content)
+}
+
+fn decode_part(part: &str, config: base64::Config) -> Result<Vec<u8>, Error> {
+    match base64::decode_config(part, config) {
+        Ok(d) => Ok(d),
+        Err(_) => Err(Error::CorruptEntry("not valid base64".to_owned())),
+    }
+}
+
+fn into_string(clear: Vec<u8>) -> Result<String, Error> {
+    match String::from_utf8(clear) {
+        Ok(s) => Ok(s),
+        Err(_) => Err(Error::CorruptEntry("content is not UTF-8".to_owned())),
+    }
 }
 
-fn get_random_iv() -> Vec<u8> {
-    let mut f = std::fs::File::open("/dev/urandom").unwrap();
-    let mut vbuf = vec![0u8;16];
+pub fn get_random_bytes(len: usize) -> Result<Vec<u8>, Error> {
+    let mut f = std::fs::File::open("/dev/urandom")?;
+    let mut vbuf = vec![0u8;len];
     let buf = vbuf.as_mut_slice();
 
-    f.read_exact(buf).expect("Couldn't read from /dev/urandom");
-    
-    vbuf
+    f.read_exact(buf)?;
+
+    Ok(vbuf)
 }
 
-pub fn transform_entry_with_random_iv(enc_params: &EncryptionParams, entry: &str)-> String {
-    let cipher = Cipher::aes_256_cbc();
+pub fn transform_entry_with_random_iv(enc_params: &EncryptionParams, entry: &str)-> Result<String, Error> {
+    let cipher = enc_params.cipher;
+    let iv = get_random_bytes(AEAD_IV_LEN)?;
 
-    let iv = get_random_iv();
+    let mut header = ENTRY_FORMAT_VERSION.to_owned();
+    header.push('~');
+    header.push_str(cipher.name());
 
-    let ciphertext = encrypt(
-        cipher,
+    //the header is authenticated too, so the version and cipher can't be swapped out
+    let mut tag = vec![0u8; AEAD_TAG_LEN];
+    let ciphertext = encrypt_aead(
+        cipher.openssl_cipher(),
         enc_params.key,
         Some(iv.as_slice()),
+        header.as_bytes(),
         entry.as_bytes(),
-    ).unwrap();
-
-    let mac_cipher = Cipher::aes_256_cbc();
-    let ciphertext_mac = encrypt(
-        mac_cipher,
-        enc_params.key,
-        Some(iv.as_slice()),
-        ciphertext.as_slice(),
-    ).unwrap();
+        tag.as_mut_slice(),
+    )?;
 
+    let iv_part = base64::encode_config(&iv, base64::URL_SAFE);
     let cipher_part = base64::encode_config(&ciphertext, base64::URL_SAFE);
-    let mac_part = base64::encode_config(&ciphertext_mac, base64::URL_SAFE);
-    let iv_part = base64::encode_config(&iv.as_slice(), base64::URL_SAFE);
+    let tag_part = base64::encode_config(&tag, base64::URL_SAFE);
 
-    let mut content = String::from(cipher_part);
-    content.push('~'); // + does not appear in url safe base64 but is till url safe
-    content.push_str(mac_part.as_str());
-    content.push('~'); // + does not appear in url safe base64 but is till url safe
+    let mut content = header;
+    content.push('~');
     content.push_str(iv_part.as_str());
+    content.push('~');
+    content.push_str(cipher_part.as_str());
+    content.push('~');
+    content.push_str(tag_part.as_str());
 
-    return content;
+    Ok(content)
 }
 
 //from encrypted to clear
-pub fn retransform_entry(enc_params: &EncryptionParams, entry: &str) -> Result<String, String> {
+pub fn retransform_entry(enc_params: &EncryptionParams, entry: &str) -> Result<String, Error> {
     let cipher = Cipher::aes_256_cbc();
 
     let parts: Vec<&str> = entry.split("~").collect();
     if parts.len() != 2 {
-        return Err("Malformed entry, needs entry and mac".to_owned());
+        return Err(Error::CorruptEntry("needs entry and mac".to_owned()));
     }
 
     let cipher_part = parts[0];
     let mac_part = parts[1];
    
-    let ciphertext = base64::decode_config(cipher_part, base64::URL_SAFE).unwrap();
-    let mactext = base64::decode_config(mac_part, base64::URL_SAFE).unwrap();
+    let ciphertext = decode_part(cipher_part, base64::URL_SAFE)?;
+    let mactext = decode_part(mac_part, base64::URL_SAFE)?;
 
     let mac_cipher = Cipher::aes_256_cbc();
     let ciphertext_mac = encrypt(
@@ -104,13 +193,13 @@ pub fn retransform_entry(enc_params: &EncryptionParams, entry: &str) -> Result<S
         enc_params.key,
         Some(enc_params.iv),
         ciphertext.as_slice(),
-    ).unwrap();
+    )?;
 
     match mactext.as_slice().cmp(ciphertext_mac.as_slice()) {
         std::cmp::Ordering::Equal => {
             //nothing
         },
-        _ => return Err("Mac did not match with calculated mac. Key is probably wrong or data was corrupted".to_owned()),
+        _ => return Err(Error::WrongKey),
     }
 
     let result = decrypt(
@@ -120,27 +209,70 @@ pub fn retransform_entry(enc_params: &EncryptionParams, entry: &str) -> Result<S
         ciphertext.as_slice(),
     );
 
-    return match result {
-        Ok(r) => Ok(std::str::from_utf8(r.as_slice()).unwrap().to_owned()),
-        Err(_) => Err("Could not decrypt. Is the key correct?".to_owned()),
+    match result {
+        Ok(r) => into_string(r),
+        Err(_) => Err(Error::WrongKey),
     }
 }
 
-pub fn retransform_entry_with_random_iv(enc_params: &EncryptionParams, entry: &str) -> Result<String, String> {
-    let cipher = Cipher::aes_256_cbc();
-
+pub fn retransform_entry_with_random_iv(enc_params: &EncryptionParams, entry: &str) -> Result<String, Error> {
     let parts: Vec<&str> = entry.split("~").collect();
-    if parts.len() != 3 {
-        return Err("Malformed entry, needs entry, mac, and iv".to_owned());
+    if parts.len() == 5 && parts[0] == ENTRY_FORMAT_VERSION {
+        return retransform_entry_aead(enc_params, parts.as_slice());
     }
+    if parts.len() == 3 {
+        return retransform_entry_cbc(enc_params, parts.as_slice());
+    }
+    Err(Error::CorruptEntry("needs version, cipher, iv, entry and tag".to_owned()))
+}
+
+fn retransform_entry_aead(enc_params: &EncryptionParams, parts: &[&str]) -> Result<String, Error> {
+    let cipher = match EntryCipher::from_name(parts[1]) {
+        Some(c) => c,
+        None => return Err(Error::CorruptEntry(format!("unknown cipher {}", parts[1]))),
+    };
+
+    let ivtext = decode_part(parts[2], base64::URL_SAFE)?;
+    let ciphertext = decode_part(parts[3], base64::URL_SAFE)?;
+    let tagtext = decode_part(parts[4], base64::URL_SAFE)?;
+    //openssl accepts shorter tags and other nonce sizes, which would weaken the authentication
+    if ivtext.len() != AEAD_IV_LEN {
+        return Err(Error::CorruptEntry("iv has the wrong length".to_owned()));
+    }
+    if tagtext.len() != AEAD_TAG_LEN {
+        return Err(Error::CorruptEntry("tag has the wrong length".to_owned()));
+    }
+
+    let mut header = parts[0].to_owned();
+    header.push('~');
+    header.push_str(parts[1]);
+
+    let result = decrypt_aead(
+        cipher.openssl_cipher(),
+        enc_params.key,
+        Some(ivtext.as_slice()),
+        header.as_bytes(),
+        ciphertext.as_slice(),
+        tagtext.as_slice(),
+    );
+
+    match result {
+        Ok(r) => into_string(r),
+        Err(_) => Err(Error::WrongKey),
+    }
+}
+
+//entries written before the AEAD format: cipher~mac~iv with aes_256_cbc
+fn retransform_entry_cbc(enc_params: &EncryptionParams, parts: &[&str]) -> Result<String, Error> {
+    let cipher = Cipher::aes_256_cbc();
 
     let cipher_part = parts[0];
     let mac_part = parts[1];
     let iv_part = parts[2];
    
-    let ciphertext = base64::decode_config(cipher_part, base64::URL_SAFE).unwrap();
-    let mactext = base64::decode_config(mac_part, base64::URL_SAFE).unwrap();
-    let ivtext = base64::decode_config(iv_part, base64::URL_SAFE).unwrap();
+    let ciphertext = decode_part(cipher_part, base64::URL_SAFE)?;
+    let mactext = decode_part(mac_part, base64::URL_SAFE)?;
+    let ivtext = decode_part(iv_part, base64::URL_SAFE)?;
 
     let mac_cipher = Cipher::aes_256_cbc();
     let ciphertext_mac = encrypt(
@@ -148,13 +280,13 @@ pub fn retransform_entry_with_random_iv(enc_params: &EncryptionParams, entry: &s
         enc_params.key,
         Some(ivtext.as_slice()),
         ciphertext.as_slice(),
-    ).unwrap();
+    )?;
 
     match mactext.as_slice().cmp(ciphertext_mac.as_slice()) {
         std::cmp::Ordering::Equal => {
             //happy path
         },
-        _ => return Err("Mac did not match with calculated mac. Key is probably wrong or data was corrupted".to_owned()),
+        _ => return Err(Error::WrongKey),
     }
 
     let result = decrypt(
@@ -164,28 +296,291 @@ pub fn retransform_entry_with_random_iv(enc_params: &EncryptionParams, entry: &s
         ciphertext.as_slice(),
     );
 
-    return match result {
-        Ok(r) => Ok(std::str::from_utf8(r.as_slice()).unwrap().to_owned()),
-        Err(_) => Err("Could not decrypt. Is the key correct?".to_owned()),
+    match result {
+        Ok(r) => into_string(r),
+        Err(_) => Err(Error::WrongKey),
     }
 }
 
-//pub fn retransform_path(enc_params: &EncryptionParams, path: &str) -> Vec<String> {
-//    let mut vec = Vec::new();
-//
-//    for part in path.split("/") {
-//        vec.push(retransform_entry(enc_params, part))
-//    }
-//
-//    return vec
-//}
+// the mac and the encryption of the SIV scheme use their own keys, derived from the repo key
+fn derive_name_key(enc_params: &EncryptionParams, purpose: &str) -> Result<Vec<u8>, Error> {
+    hmac_sha256(enc_params.key, &[purpose.as_bytes()])
+}
 
-pub fn transform_path(enc_params: &EncryptionParams, path: &str) -> Vec<String> {
+fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> Result<Vec<u8>, Error> {
+    let pkey = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &pkey)?;
+    for part in parts {
+        signer.update(part)?;
+    }
+    Ok(signer.sign_to_vec()?)
+}
+
+fn synthetic_iv(enc_params: &EncryptionParams, parent: &str, name: &str) -> Result<Vec<u8>, Error> {
+    let mac_key = derive_name_key(enc_params, "sparkpass name mac")?;
+    //the 0 byte separates parent and name, it can't appear in either of them
+    let mut siv = hmac_sha256(&mac_key, &[parent.as_bytes(), b"\0", name.as_bytes()])?;
+    siv.truncate(SIV_LEN);
+    Ok(siv)
+}
+
+fn transform_name_siv(enc_params: &EncryptionParams, parent: &str, name: &str) -> Result<String, Error> {
+    let siv = synthetic_iv(enc_params, parent, name)?;
+    let enc_key = derive_name_key(enc_params, "sparkpass name enc")?;
+
+    let ciphertext = encrypt(
+        Cipher::aes_256_ctr(),
+        &enc_key,
+        Some(siv.as_slice()),
+        name.as_bytes(),
+    )?;
+
+    let mut content = base64::encode_config(&siv, base64::URL_SAFE_NO_PAD);
+    content.push('~');
+    content.push_str(base64::encode_config(&ciphertext, base64::URL_SAFE_NO_PAD).as_str());
+
+    Ok(content)
+}
+
+fn retransform_name_siv(enc_params: &EncryptionParams, parent: &str, entry: &str) -> Result<String, Error> {
+    let parts: Vec<&str> = entry.split("~").collect();
+    if parts.len() != 2 {
+        return Err(Error::CorruptEntry("needs iv and name".to_owned()));
+    }
+
+    let siv = decode_part(parts[0], base64::URL_SAFE_NO_PAD)?;
+    let ciphertext = decode_part(parts[1], base64::URL_SAFE_NO_PAD)?;
+    if siv.len() != SIV_LEN {
+        return Err(Error::CorruptEntry("iv has the wrong length".to_owned()));
+    }
+
+    let enc_key = derive_name_key(enc_params, "sparkpass name enc")?;
+    let clear = match decrypt(
+        Cipher::aes_256_ctr(),
+        &enc_key,
+        Some(siv.as_slice()),
+        ciphertext.as_slice(),
+    ) {
+        Ok(c) => c,
+        Err(_) => return Err(Error::WrongKey),
+    };
+    //a wrong key produces garbage here, which is most likely not valid UTF-8
+    let name = match String::from_utf8(clear) {
+        Ok(n) => n,
+        Err(_) => return Err(Error::WrongKey),
+    };
+
+    //the iv doubles as mac. It only matches if key, parent and name are right
+    if !memcmp::eq(&siv, &synthetic_iv(enc_params, parent, name.as_str())?) {
+        return Err(Error::WrongKey);
+    }
+
+    Ok(name)
+}
+
+// encrypts one component of an entry path. parent is the clear path of the dir containing it, "" for the repo root
+pub fn transform_name(enc_params: &EncryptionParams, parent: &str, name: &str) -> Result<String, Error> {
+    match enc_params.names {
+        NameScheme::Cbc => transform_entry(enc_params, name),
+        NameScheme::Siv => transform_name_siv(enc_params, parent, name),
+    }
+}
+
+pub fn retransform_name(enc_params: &EncryptionParams, parent: &str, entry: &str) -> Result<String, Error> {
+    match enc_params.names {
+        NameScheme::Cbc => retransform_entry(enc_params, entry),
+        NameScheme::Siv => retransform_name_siv(enc_params, parent, entry),
+    }
+}
+
+pub fn transform_path(enc_params: &EncryptionParams, path: &str) -> Result<Vec<String>, Error> {
     let mut vec = Vec::new();
+    let mut parent = String::new();
 
     for part in path.split("/") {
-        vec.push(transform_entry(enc_params, part));
+        vec.push(transform_name(enc_params, parent.as_str(), part)?);
+        parent = join_entry_path(parent.as_str(), part);
     }
 
-    return vec
-}
\ No newline at end of file
+    Ok(vec)
+}
+
+// joins the clear path of a dir and the name of an entry in it
+pub fn join_entry_path(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        return name.to_owned();
+    }
+    let mut path = parent.to_owned();
+    path.push('/');
+    path.push_str(name);
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    fn params(cipher: EntryCipher, names: NameScheme) -> EncryptionParams<'static> {
+        EncryptionParams { key: KEY, iv: DEFAULT_IV, cipher, names }
+    }
+
+    // flips a bit in one ~ separated part of an entry
+    fn tamper(entry: &str, index: usize) -> String {
+        let mut parts: Vec<String> = entry.split('~').map(|p| p.to_owned()).collect();
+        let mut bytes = base64::decode_config(&parts[index], base64::URL_SAFE).unwrap();
+        bytes[0] ^= 1;
+        parts[index] = base64::encode_config(&bytes, base64::URL_SAFE);
+        parts.join("~")
+    }
+
+    #[test]
+    fn aead_round_trip() {
+        for cipher in &[EntryCipher::Aes256Gcm, EntryCipher::ChaCha20Poly1305] {
+            let params = params(*cipher, NameScheme::Siv);
+            let entry = transform_entry_with_random_iv(&params, "pässword\nuser: bob\n").unwrap();
+            assert!(entry.starts_with(&format!("2~{}~", cipher.name())));
+            assert_eq!(retransform_entry_with_random_iv(&params, &entry).unwrap(), "pässword\nuser: bob\n");
+
+            //the iv is random, the same content encrypts differently every time
+            assert_ne!(transform_entry_with_random_iv(&params, "pässword\nuser: bob\n").unwrap(), entry);
+        }
+    }
+
+    #[test]
+    fn aead_wrong_key() {
+        let entry = transform_entry_with_random_iv(&params(EntryCipher::Aes256Gcm, NameScheme::Siv), "pw").unwrap();
+        let other = EncryptionParams { key: b"fedcba9876543210fedcba9876543210", ..params(EntryCipher::Aes256Gcm, NameScheme::Siv) };
+        match retransform_entry_with_random_iv(&other, &entry) {
+            Err(Error::WrongKey) => {},
+            r => panic!("unexpected result {:?}", r.map_err(|e| e.to_string())),
+        }
+    }
+
+    #[test]
+    fn aead_tampered_header() {
+        let params = params(EntryCipher::Aes256Gcm, NameScheme::Siv);
+        let entry = transform_entry_with_random_iv(&params, "pw").unwrap();
+        let swapped = entry.replacen("aes-256-gcm", "chacha20-poly1305", 1);
+        assert!(retransform_entry_with_random_iv(&params, &swapped).is_err());
+        let version = entry.replacen("2~", "3~", 1);
+        assert!(retransform_entry_with_random_iv(&params, &version).is_err());
+    }
+
+    #[test]
+    fn aead_tampered_iv_ciphertext_and_tag() {
+        for cipher in &[EntryCipher::Aes256Gcm, EntryCipher::ChaCha20Poly1305] {
+            let params = params(*cipher, NameScheme::Siv);
+            let entry = transform_entry_with_random_iv(&params, "pw").unwrap();
+            for index in 2..5 {
+                match retransform_entry_with_random_iv(&params, &tamper(&entry, index)) {
+                    Err(Error::WrongKey) => {},
+                    r => panic!("part {}: unexpected result {:?}", index, r.map_err(|e| e.to_string())),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn aead_truncated_tag_and_iv() {
+        for cipher in &[EntryCipher::Aes256Gcm, EntryCipher::ChaCha20Poly1305] {
+            let params = params(*cipher, NameScheme::Siv);
+            let entry = transform_entry_with_random_iv(&params, "pw").unwrap();
+            let mut parts: Vec<String> = entry.split('~').map(|p| p.to_owned()).collect();
+            for (index, len) in &[(4, 4), (4, 15), (2, 8)] {
+                let mut truncated = parts.clone();
+                let bytes = base64::decode_config(&parts[*index], base64::URL_SAFE).unwrap();
+                truncated[*index] = base64::encode_config(&bytes[..*len], base64::URL_SAFE);
+                match retransform_entry_with_random_iv(&params, &truncated.join("~")) {
+                    Err(Error::CorruptEntry(_)) => {},
+                    r => panic!("part {} with {} bytes: unexpected result {:?}", index, len, r.map_err(|e| e.to_string())),
+                }
+            }
+            parts.truncate(4);
+            assert!(retransform_entry_with_random_iv(&params, &parts.join("~")).is_err());
+        }
+    }
+
+    #[test]
+    fn legacy_cbc_entry() {
+        //the layout entries had before the AEAD format: cipher~mac~iv
+        let iv = b"fedcba9876543210";
+        let ciphertext = encrypt(Cipher::aes_256_cbc(), KEY, Some(iv), b"old pw\n").unwrap();
+        let mac = encrypt(Cipher::aes_256_cbc(), KEY, Some(iv), &ciphertext).unwrap();
+        let entry = [&ciphertext[..], &mac, iv]
+            .iter()
+            .map(|p| base64::encode_config(p, base64::URL_SAFE))
+            .collect::<Vec<_>>()
+            .join("~");
+
+        let params = params(EntryCipher::Aes256Gcm, NameScheme::Cbc);
+        assert_eq!(retransform_entry_with_random_iv(&params, &entry).unwrap(), "old pw\n");
+        match retransform_entry_with_random_iv(&params, &tamper(&entry, 1)) {
+            Err(Error::WrongKey) => {},
+            r => panic!("unexpected result {:?}", r.map_err(|e| e.to_string())),
+        }
+    }
+
+    #[test]
+    fn siv_name_round_trip() {
+        let params = params(EntryCipher::Aes256Gcm, NameScheme::Siv);
+        for (parent, name) in &[("", "mail"), ("web/shops", "pässwörd ~ with / odd chars"), ("a", "")] {
+            let enc = transform_name(&params, parent, name).unwrap();
+            assert!(!enc.starts_with('.'));
+            assert!(!enc.contains('/'));
+            assert_eq!(retransform_name(&params, parent, &enc).unwrap(), *name);
+            //deterministic, so an entry can be found by its name
+            assert_eq!(transform_name(&params, parent, name).unwrap(), enc);
+        }
+
+        let path = transform_path(&params, "web/shops/mail").unwrap();
+        assert_eq!(path.len(), 3);
+        assert_eq!(retransform_name(&params, "web/shops", &path[2]).unwrap(), "mail");
+    }
+
+    #[test]
+    fn siv_name_depends_on_parent() {
+        let siv = params(EntryCipher::Aes256Gcm, NameScheme::Siv);
+        let root = transform_name(&siv, "", "mail").unwrap();
+        let work = transform_name(&siv, "work", "mail").unwrap();
+        let home = transform_name(&siv, "home", "mail").unwrap();
+        assert_ne!(root, work);
+        assert_ne!(work, home);
+
+        //a name moved to another dir doesn't decrypt there
+        assert!(retransform_name(&siv, "home", &work).is_err());
+
+        //unlike the cbc names, which are the same everywhere
+        let cbc = params(EntryCipher::Aes256Gcm, NameScheme::Cbc);
+        assert_eq!(transform_name(&cbc, "work", "mail").unwrap(), transform_name(&cbc, "home", "mail").unwrap());
+    }
+
+    #[test]
+    fn siv_name_tampered() {
+        let params = params(EntryCipher::Aes256Gcm, NameScheme::Siv);
+        let enc = transform_name(&params, "dir", "mail").unwrap();
+        let parts: Vec<&str> = enc.split('~').collect();
+        let flip = |part: &str| {
+            let mut bytes = base64::decode_config(part, base64::URL_SAFE_NO_PAD).unwrap();
+            bytes[0] ^= 1;
+            base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD)
+        };
+
+        for tampered in &[format!("{}~{}", flip(parts[0]), parts[1]), format!("{}~{}", parts[0], flip(parts[1]))] {
+            match retransform_name(&params, "dir", tampered) {
+                Err(Error::WrongKey) => {},
+                r => panic!("unexpected result {:?}", r.map_err(|e| e.to_string())),
+            }
+        }
+
+        let short_iv = format!("{}~{}", base64::encode_config(b"short", base64::URL_SAFE_NO_PAD), parts[1]);
+        match retransform_name(&params, "dir", &short_iv) {
+            Err(Error::CorruptEntry(_)) => {},
+            r => panic!("unexpected result {:?}", r.map_err(|e| e.to_string())),
+        }
+
+        let other = EncryptionParams { key: b"fedcba9876543210fedcba9876543210", ..params };
+        assert!(retransform_name(&other, "dir", &enc).is_err());
+    }
+}
@@ -1,12 +1,18 @@
 use crate::transform;
+use crate::error::Error;
 use std::path;
 use std::fs;
 use std::str;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+
+extern crate base64;
 
 
 extern crate levenshtein;
 use levenshtein::levenshtein;
 
+#[derive(Default)]
 pub struct Options {
     pub args: Vec<String>,
     pub key: String,
@@ -18,6 +24,36 @@ pub struct Options {
     pub show_tree: bool,
     pub interactive: bool,
     pub multiline: bool,
+    pub kdf: String,
+    pub kdf_params: String,
+    pub cipher: String,
+    pub new_key: String,
+    pub ignore_case: bool,
+    pub context: usize,
+    pub field: String,
+    pub classes: String,
+    pub custom_chars: String,
+    pub require: String,
+    pub no_ambiguous: bool,
+    pub policy: String,
+    pub save_policy: String,
+    pub length: usize,
+    pub words: usize,
+    pub separator: String,
+    pub capitalize: bool,
+    pub word_digits: bool,
+    pub wordlist: String,
+    pub in_place: bool,
+    pub keep_history: bool,
+    pub clip: bool,
+    pub clip_time: u64,
+    pub qrcode: bool,
+    pub qrcode_out: String,
+    pub dry_run: bool,
+    pub csv_map: String,
+    pub path_template: String,
+    pub delimiter: String,
+    pub on_conflict: String,
 }
 
 pub enum TreeNode {
@@ -40,7 +76,7 @@ fn copy_tree_node(tree: &TreeNode) -> TreeNode {
 }
 
 pub fn sort_tree_leveshtein(tree: &TreeNode, words: Vec<&str>) -> TreeNode {
-    if words.len() == 0 {
+    if words.is_empty() {
         return copy_tree_node(tree);
     }
 
@@ -167,21 +203,65 @@ pub fn prepare_entry_path(path: &str) -> &str {
     let mut tmp = path.trim_start_matches("/");
     tmp = tmp.trim_end_matches("/");
 
-    return tmp;
+    tmp
+}
+
+//the clear path of the dir containing the entry, "" for entries in the repo root
+pub fn parent_entry_path(path: &str) -> &str {
+    match path.rfind("/") {
+        Some(idx) => &path[..idx],
+        None => "",
+    }
+}
+
+//files starting with a '.' are not entries (e.g. the repo header). Encrypted names never start with a '.'
+pub fn is_hidden(p: &path::Path) -> bool {
+    match p.file_name() {
+        Some(name) => name.to_string_lossy().starts_with("."),
+        None => false,
+    }
+}
+
+// parent is the clear path of the dir containing p. None means p is the repo root, its name is not encrypted.
+// Files that were not written by sparkpass are skipped with a warning
+pub fn get_tree_from_path(p: &path::Path, parent: Option<&str>, enc_params: &transform::EncryptionParams) -> Result<TreeNode, Error> {
+    tree_from_path(p, parent, enc_params, false)
 }
 
-pub fn get_tree_from_path(p: &path::Path, is_clear: bool, enc_params: &transform::EncryptionParams) -> Result<TreeNode, String> {
+// like get_tree_from_path, but a file that is not an entry is an error. For rewriting the whole repo, where such a file
+// would be lost
+pub fn get_tree_from_path_strict(p: &path::Path, parent: Option<&str>, enc_params: &transform::EncryptionParams) -> Result<TreeNode, Error> {
+    tree_from_path(p, parent, enc_params, true)
+}
+
+fn tree_from_path(p: &path::Path, parent: Option<&str>, enc_params: &transform::EncryptionParams, strict: bool) -> Result<TreeNode, Error> {
+    let filename = match p.file_name().and_then(|n| n.to_str()) {
+        Some(n) => n,
+        None => return Err(Error::NonUtf8Name(p.to_path_buf())),
+    };
+
+    let name = match parent {
+        Some(parent) => match transform::retransform_name(enc_params, parent, filename) {
+            Ok(s) => s,
+            Err(Error::CorruptEntry(msg)) if strict => return Err(Error::CorruptEntry(format!("{}: {}", p.display(), msg))),
+            Err(e) => return Err(e),
+        },
+        None => filename.to_owned(),
+    };
+
     if p.is_file() {
-        let filename = p.file_name().unwrap().to_str().unwrap();
-        return match transform::retransform_entry(enc_params, filename) {
-            Ok(s) =>  Ok(TreeNode::Leaf(s)),
-            Err(e) => Err(e),
-        }
+        return Ok(TreeNode::Leaf(name));
     }
 
+    //the clear path of this dir, which is the parent of the entries in it
+    let clear_path = match parent {
+        Some(parent) => transform::join_entry_path(parent, name.as_str()),
+        None => "".to_owned(),
+    };
+
     let it = match fs::read_dir(p) {
         Ok(iter) => iter,
-        Err(_) => return Err("Couldn't read directory".to_owned()),
+        Err(e) => return Err(Error::Io(e)),
     };
 
     let mut result = Vec::new();
@@ -189,39 +269,52 @@ pub fn get_tree_from_path(p: &path::Path, is_clear: bool, enc_params: &transform
     for entry in it {
         let entry = match entry {
             Ok(e) => e,
-            Err(_) => return Err("Conversion error. Not UTF-8?".to_owned()),
+            Err(e) => return Err(Error::Io(e)),
         };
 
+        if is_hidden(&entry.path()) {
+            continue;
+        }
     
         let entryp = &entry.path();
         
-        match get_tree_from_path(entryp, false, enc_params) {
+        match tree_from_path(entryp, Some(clear_path.as_str()), enc_params, strict) {
             Ok(node) => {
                 result.push(node);
                 },
+            //the name can't be decrypted because it is no encrypted name at all
+            Err(e @ Error::CorruptEntry(_)) | Err(e @ Error::NonUtf8Name(_)) if !strict => {
+                eprintln!("Skipping {}, it is not an entry. {}", entryp.display(), e);
+            },
             Err(e) => {
                 return Err(e);
             },
         }
     }
 
-    let filename = p.file_name().unwrap().to_str().unwrap();
+    Ok(TreeNode::Node(name, result))
+}
 
-    let dirname = if !is_clear {
-        match transform::retransform_entry(enc_params, filename) {
-            Ok(s) => s,
-            Err(e) => return Err(e),
+// lists the clear paths of all entries below the dir, relative to the dir
+pub fn get_entries_in_dir(prefix: &path::Path, dir: &str, enc_params: &transform::EncryptionParams) -> Result<Vec<String>, Error> {
+    let full_path = prefix.join(transform::transform_path(enc_params, dir)?.join("/"));
+    let tree = get_tree_from_path(full_path.as_path(), Some(parent_entry_path(dir)), enc_params)?;
+
+    let mut entries = Vec::new();
+    if let TreeNode::Node(_, children) = tree {
+        for c in children {
+            for e in flatten_tree(&c, "".to_owned()) {
+                entries.push(prepare_entry_path(e.as_str()).to_owned());
+            }
         }
-    }else{
-        filename.to_owned()
-    };
-    return Ok(TreeNode::Node(dirname.to_owned(), result));
+    }
+    Ok(entries)
 }
 
-pub fn get_all_entries_in_path(p: &path::Path) -> Result<Vec<(String,bool)>, String> {
+pub fn get_all_entries_in_path(p: &path::Path) -> Result<Vec<(String,bool)>, Error> {
     let it = match fs::read_dir(p) {
         Ok(iter) => iter,
-        Err(_) => return Err("Couldn't read directory".to_owned()),
+        Err(e) => return Err(Error::Io(e)),
     };
 
     let mut result = Vec::new();
@@ -229,83 +322,205 @@ pub fn get_all_entries_in_path(p: &path::Path) -> Result<Vec<(String,bool)>, Str
     for entry in it {
         let entry = match entry {
             Ok(e) => e,
-            Err(_) => return Err("Conversion error. Not UTF-8?".to_owned()),
+            Err(e) => return Err(Error::Io(e)),
         };
 
         let dirp = &entry.path();
+        if is_hidden(dirp) {
+            continue;
+        }
         let x = match path::Path::new(dirp).file_name(){
             Some(s) => match s.to_owned().to_str() {
                 Some(s) => s.to_owned(),
-                None => return Err("Conversion error. Not UTF-8?".to_owned()),
+                //encrypted names are base64, so this is no entry
+                None => {
+                    eprintln!("Skipping {}, it is not an entry. {}", dirp.display(), Error::NonUtf8Name(dirp.to_path_buf()));
+                    continue;
+                },
             },
-            None => return Err("No Filename? Empty path?".to_owned()),
+            None => return Err(Error::NonUtf8Name(dirp.to_path_buf())),
         };
         result.push((x, entry.path().is_dir()));
     }
 
-    return Ok(result);
+    Ok(result)
 }
 
-pub fn add_entry(prefix : &path::Path, p: &path::Path, content: &str, overwrite: bool, enc_params: &transform::EncryptionParams) -> Result<(), String> {
-    let trans_path = transform::transform_path(enc_params, p.to_str().unwrap()).join("/");
-    let full_path = prefix.join(trans_path.clone());
+// fsync on a dir makes renames and newly created files in it durable
+pub fn sync_dir(dir: &path::Path) -> Result<(), Error> {
+    fs::File::open(dir)?.sync_all()?;
+    Ok(())
+}
 
-    let exists = match fs::metadata(full_path.clone()) {
-        Ok(_) => true,
-        Err(_) => false,
+// writes to a hidden temp file next to the target and renames it into place, so a crash leaves either the old or the new content
+pub fn write_file_atomic(target: &path::Path, content: &[u8]) -> Result<(), Error> {
+    write_file_atomic_with_mode(target, content, 0o666)
+}
+
+// the mode (minus the umask) is that of the new file, the mode of a replaced file doesn't carry over
+pub fn write_file_atomic_with_mode(target: &path::Path, content: &[u8], mode: u32) -> Result<(), Error> {
+    let dir = match target.parent() {
+        //a relative file name has the empty path as parent
+        Some(d) if d.as_os_str().is_empty() => path::Path::new("."),
+        Some(d) => d,
+        None => return Err(Error::InvalidArgument(format!("Not a file path: {}", target.display()))),
+    };
+    let file_name = match target.file_name().and_then(|n| n.to_str()) {
+        Some(n) => n,
+        None => return Err(Error::NonUtf8Name(target.to_path_buf())),
     };
+    let suffix = base64::encode_config(&transform::get_random_bytes(6)?, base64::URL_SAFE_NO_PAD);
+    let tmp_path = dir.join(format!(".{}.tmp-{}", file_name, suffix));
+
+    let result = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(&tmp_path)
+        .and_then(|mut f| {
+            f.write_all(content)?;
+            f.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp_path, target));
+
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(Error::Io(e));
+    }
+
+    sync_dir(dir)
+}
+
+// the relative path of an entry as given by the user, entry names are always UTF-8
+fn entry_path_str(p: &path::Path) -> Result<&str, Error> {
+    match p.to_str() {
+        Some(s) => Ok(s),
+        None => Err(Error::NonUtf8Name(p.to_path_buf())),
+    }
+}
+
+pub fn add_entry(prefix : &path::Path, p: &path::Path, content: &str, overwrite: bool, enc_params: &transform::EncryptionParams) -> Result<(), Error> {
+    let name = entry_path_str(p)?;
+    let trans_path = transform::transform_path(enc_params, name)?.join("/");
+    let full_path = prefix.join(trans_path.clone());
+
+    let exists = fs::metadata(full_path.clone()).is_ok();
 
     if exists && !overwrite {
-        let mut err = "Entry already exists: ".to_owned();
-        err.push_str(p.to_str().unwrap());
-        return Err(err.to_owned())
+        return Err(Error::Exists(name.to_owned()))
     }else{
-        let full_path_dir = full_path.as_path().parent().unwrap();
+        let full_path_dir = match full_path.as_path().parent() {
+            Some(d) => d,
+            None => return Err(Error::InvalidArgument(format!("Not an entry path: {}", name))),
+        };
         match fs::create_dir_all(full_path_dir) {
             Ok(_) => {},
-            Err(_) => {
-                return Err("An error occurred while creating necessary parent directories".to_owned());
+            Err(e) => {
+                return Err(Error::Io(e));
             }
         }
     }
 
-    let trans_content = transform::transform_entry_with_random_iv(enc_params, content);
-    match fs::write(full_path, trans_content) {
-        Ok(_) => {},
-        Err(_) => {
-            return Err("An error occurred while writing the content to the file".to_owned());
-        }
-    }
+    let trans_content = transform::transform_entry_with_random_iv(enc_params, content)?;
+    write_file_atomic(full_path.as_path(), trans_content.as_bytes())
+}
 
-    return Ok(())
+// the file of an entry in the repo, its name is encrypted
+pub fn entry_file_path(prefix: &path::Path, p: &path::Path, enc_params: &transform::EncryptionParams) -> Result<path::PathBuf, Error> {
+    let trans_path = transform::transform_path(enc_params, entry_path_str(p)?)?.join("/");
+    Ok(prefix.join(trans_path))
 }
 
-pub fn show_entry(prefix: &path::Path, p: &path::Path, enc_params: &transform::EncryptionParams) -> Result<String, String> {
-    let trans_path = transform::transform_path(enc_params, p.to_str().unwrap()).join("/");
+pub fn show_entry(prefix: &path::Path, p: &path::Path, enc_params: &transform::EncryptionParams) -> Result<String, Error> {
+    let name = entry_path_str(p)?;
+    let trans_path = transform::transform_path(enc_params, name)?.join("/");
     let full_path = prefix.join(trans_path);
 
     if full_path.is_dir() {
-        return Err("Is dir".to_owned());
+        return Err(Error::IsDir(name.to_owned()));
     }
 
-    let exists = match fs::metadata(full_path.as_path()) {
-        Ok(_) => true,
-        Err(_) => false,
-    };
+    let exists = fs::metadata(full_path.as_path()).is_ok();
 
     if !exists {
-        return Err("Entry does not exist".to_owned());
+        return Err(Error::NotFound(name.to_owned()));
     }
 
     let res = match fs::read(full_path) {
         Ok(r) => r,
-        Err(_) => {
-            return Err("An error occurred while reading the entry from the file".to_owned());
+        Err(e) => {
+            return Err(Error::Io(e));
         }
     };
 
 
-    let content = str::from_utf8(res.as_slice()).unwrap().to_owned();
+    let content = match str::from_utf8(res.as_slice()) {
+        Ok(c) => c.to_owned(),
+        Err(_) => return Err(Error::CorruptEntry("content is not UTF-8".to_owned())),
+    };
     
     transform::retransform_entry_with_random_iv(enc_params, content.as_str())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{add, enc_params, show, TempDir};
+    use std::os::unix::fs::PermissionsExt;
+
+    fn dir_contents(dir: &path::Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir).unwrap().map(|e| e.unwrap().file_name().into_string().unwrap()).collect();
+        names.sort();
+        names
+    }
+
+    fn mode(p: &path::Path) -> u32 {
+        fs::metadata(p).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn atomic_writes_leave_no_temp_files() {
+        let dir = TempDir::new("atomic-write");
+        let target = dir.path().join("file");
+
+        write_file_atomic(&target, b"first").unwrap();
+        write_file_atomic(&target, b"second").unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b"second");
+        assert_eq!(dir_contents(dir.path()), vec!["file"]);
+
+        //the rename fails, the temp file is removed again
+        fs::create_dir_all(dir.path().join("is_dir/sub")).unwrap();
+        assert!(matches!(write_file_atomic(&dir.path().join("is_dir"), b"x"), Err(Error::Io(_))));
+        assert!(matches!(write_file_atomic(&dir.path().join("missing/file"), b"x"), Err(Error::Io(_))));
+        assert_eq!(dir_contents(dir.path()), vec!["file", "is_dir"]);
+        assert_eq!(dir_contents(&dir.path().join("is_dir")), vec!["sub"]);
+    }
+
+    #[test]
+    fn atomic_writes_with_mode() {
+        let dir = TempDir::new("atomic-write-mode");
+        let target = dir.path().join("file");
+
+        fs::write(&target, "old").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o644)).unwrap();
+        write_file_atomic_with_mode(&target, b"new", 0o600).unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b"new");
+        assert_eq!(mode(&target), 0o600);
+    }
+
+    #[test]
+    fn entries_are_written_atomically() {
+        let repo = TempDir::new("atomic-entries");
+        add(repo.path(), "mail/work", "first");
+
+        let p = path::Path::new("mail/work");
+        assert!(matches!(add_entry(repo.path(), p, "second", false, &enc_params()), Err(Error::Exists(_))));
+        assert_eq!(show(repo.path(), "mail/work"), "first");
+        add_entry(repo.path(), p, "second", true, &enc_params()).unwrap();
+        assert_eq!(show(repo.path(), "mail/work"), "second");
+
+        let dir = repo.path().join(transform::transform_path(&enc_params(), "mail").unwrap().join("/"));
+        assert_eq!(dir_contents(&dir).len(), 1);
+        assert!(dir_contents(repo.path()).iter().all(|n| !n.contains(".tmp-")));
+    }
+}
@@ -0,0 +1,76 @@
+// Runs spass on a repo kept in git: every change is committed and neither the commit messages nor the file names in the
+// history show entry names
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("sparkpass-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+// git must neither read the config of the user nor need it for committing
+fn isolated<'a>(cmd: &'a mut Command, home: &Path) -> &'a mut Command {
+    cmd.env("HOME", home)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_AUTHOR_NAME", "test")
+        .env("GIT_AUTHOR_EMAIL", "test@example.com")
+        .env("GIT_COMMITTER_NAME", "test")
+        .env("GIT_COMMITTER_EMAIL", "test@example.com")
+}
+
+fn spass(home: &Path, repo: &Path, args: &[&str]) -> Output {
+    let output = isolated(&mut Command::new(env!("CARGO_BIN_EXE_spass")), home)
+        .env("SPARKPASS_REPO", repo)
+        .env("SPARKPASS_KEY", "test key")
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "spass {:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+fn git(home: &Path, args: &[&str]) -> String {
+    let output = isolated(&mut Command::new("git"), home).args(args).output().unwrap();
+    assert!(output.status.success(), "git {:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn changes_are_committed_without_entry_names() {
+    let tmp = TempDir::new("git-test");
+    let repo = tmp.0.join("repo");
+    let remote = tmp.0.join("remote.git");
+    let repo_str = repo.to_str().unwrap();
+
+    //a cheap kdf, every command derives the key again
+    spass(&tmp.0, &repo, &["--kdf-params", "m=1024,t=1,p=1", "init", repo_str]);
+    spass(&tmp.0, &repo, &["git", "init", "-q"]);
+    spass(&tmp.0, &repo, &["add", "Banking/secret-account", "hunter2"]);
+    spass(&tmp.0, &repo, &["mv", "Banking/secret-account", "Banking/other-account"]);
+
+    let messages = git(&tmp.0, &["-C", repo_str, "log", "--format=%B"]);
+    assert_eq!(messages.lines().filter(|l| !l.is_empty()).collect::<Vec<_>>(),
+               vec!["Move entry", "Add entry", "Add current contents of the repo"]);
+    assert!(git(&tmp.0, &["-C", repo_str, "status", "--porcelain"]).is_empty());
+
+    //the history is what gets pushed, so check it in a bare clone
+    git(&tmp.0, &["init", "-q", "--bare", remote.to_str().unwrap()]);
+    spass(&tmp.0, &repo, &["git", "push", "-q", remote.to_str().unwrap(), "HEAD:refs/heads/main"]);
+    let history = git(&tmp.0, &["-C", remote.to_str().unwrap(), "log", "--name-only", "--format=%B", "main"]);
+    assert_eq!(history.matches("entry").count(), 2);
+    for name in &["Banking", "secret-account", "other-account", "hunter2"] {
+        assert!(!history.contains(name), "{} in the history:\n{}", name, history);
+    }
+}